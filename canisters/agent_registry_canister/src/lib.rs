@@ -0,0 +1,345 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::{Serialize, Deserialize as SerdeDeserialize};
+use std::cell::RefCell;
+
+mod storage;
+
+use storage::Memory;
+
+// Configuration loaded from shared TOML
+const CONFIG_TOML: &str = include_str!("../../revenue_config.toml");
+
+#[derive(SerdeDeserialize, Clone)]
+struct RevenueConfig {
+    company_wallet: CompanyWalletConfig,
+}
+
+#[derive(SerdeDeserialize, Clone)]
+struct CompanyWalletConfig {
+    principal: String,
+}
+
+// ============================================================================
+// DATA STRUCTURES
+// ============================================================================
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum KycStatus {
+    Pending,
+    Approved,
+    Rejected { reason: String },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AgentStatus {
+    Active,
+    Suspended { reason: String, since: u64 },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentLocation {
+    pub country: String,
+    pub region: String,
+    pub city: String,
+    pub address: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// Cash the agent declares it holds to pay out withdrawals.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CashFloatAttestation {
+    pub amount_ugx: u64,
+    pub attested_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AgentProfile {
+    pub principal: Principal,
+    pub business_name: String,
+    pub phone_number: String,
+    pub location: AgentLocation,
+    pub kyc_status: KycStatus,
+    pub status: AgentStatus,
+    pub cash_float: Option<CashFloatAttestation>,
+    pub registered_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RegisterAgentRequest {
+    pub business_name: String,
+    pub phone_number: String,
+    pub location: AgentLocation,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum RegistryError {
+    Unauthorized,
+    AlreadyRegistered,
+    NotRegistered,
+    InvalidInput { reason: String },
+}
+
+/// Why an agent may not take part in deposits or withdrawals.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AgentIneligible {
+    NotRegistered,
+    Suspended,
+    KycNotApproved,
+}
+
+// ============================================================================
+// STATE
+// ============================================================================
+
+thread_local! {
+    static CONFIG: RefCell<Option<RevenueConfig>> = const { RefCell::new(None) };
+
+    static SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::SCHEMA_VERSION_MEMORY_ID), 0)
+            .expect("Failed to init schema version")
+    );
+
+    static AGENTS: RefCell<StableBTreeMap<Principal, AgentProfile, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENTS_MEMORY_ID))
+    );
+}
+
+// ============================================================================
+// INITIALIZATION
+// ============================================================================
+
+#[init]
+fn init() {
+    load_config();
+    SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(storage::SCHEMA_VERSION)
+            .expect("Failed to write schema version");
+    });
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    load_config();
+
+    let stored = SCHEMA_VERSION.with(|v| *v.borrow().get());
+    if stored > storage::SCHEMA_VERSION {
+        ic_cdk::trap(format!(
+            "Stable schema v{} is newer than this build (v{}); refusing to downgrade",
+            stored,
+            storage::SCHEMA_VERSION
+        ));
+    }
+}
+
+fn load_config() {
+    // Load configuration from shared TOML
+    let config: RevenueConfig = toml::from_str(CONFIG_TOML)
+        .expect("Failed to parse revenue_config.toml");
+
+    CONFIG.with(|c| *c.borrow_mut() = Some(config));
+}
+
+fn get_config() -> RevenueConfig {
+    CONFIG.with(|c| {
+        c.borrow()
+            .clone()
+            .expect("Config not initialized. Call init() first.")
+    })
+}
+
+fn require_company_wallet(caller: Principal) -> Result<(), RegistryError> {
+    let company = Principal::from_text(get_config().company_wallet.principal)
+        .map_err(|_| RegistryError::Unauthorized)?;
+
+    if caller != company {
+        return Err(RegistryError::Unauthorized);
+    }
+    Ok(())
+}
+
+// ============================================================================
+// VALIDATION
+// ============================================================================
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_PHONE_LEN: usize = 20;
+const MAX_LOCATION_FIELD_LEN: usize = 100;
+const MAX_ADDRESS_LEN: usize = 200;
+
+fn validate_text(field: &str, value: &str, max_len: usize, required: bool) -> Result<(), RegistryError> {
+    if required && value.trim().is_empty() {
+        return Err(RegistryError::InvalidInput { reason: format!("{} is required", field) });
+    }
+    if value.chars().count() > max_len {
+        return Err(RegistryError::InvalidInput {
+            reason: format!("{} must be at most {} characters", field, max_len),
+        });
+    }
+    Ok(())
+}
+
+fn validate_location(location: &AgentLocation) -> Result<(), RegistryError> {
+    validate_text("Country", &location.country, MAX_LOCATION_FIELD_LEN, true)?;
+    validate_text("Region", &location.region, MAX_LOCATION_FIELD_LEN, false)?;
+    validate_text("City", &location.city, MAX_LOCATION_FIELD_LEN, true)?;
+    validate_text("Address", &location.address, MAX_ADDRESS_LEN, false)?;
+
+    if location.latitude.is_some_and(|lat| !(-90.0..=90.0).contains(&lat)) {
+        return Err(RegistryError::InvalidInput { reason: "Latitude out of range".to_string() });
+    }
+    if location.longitude.is_some_and(|lng| !(-180.0..=180.0).contains(&lng)) {
+        return Err(RegistryError::InvalidInput { reason: "Longitude out of range".to_string() });
+    }
+    Ok(())
+}
+
+fn validate_registration(request: &RegisterAgentRequest) -> Result<(), RegistryError> {
+    validate_text("Business name", &request.business_name, MAX_NAME_LEN, true)?;
+    validate_text("Phone number", &request.phone_number, MAX_PHONE_LEN, true)?;
+    validate_location(&request.location)
+}
+
+// ============================================================================
+// AGENT SELF-SERVICE
+// ============================================================================
+
+/// Register the caller as an agent. New agents start active with KYC pending,
+/// so they cannot take deposits or withdrawals until KYC is approved.
+#[update]
+fn register_agent(request: RegisterAgentRequest) -> Result<AgentProfile, RegistryError> {
+    let caller = ic_cdk::api::msg_caller();
+
+    if caller == Principal::anonymous() {
+        return Err(RegistryError::Unauthorized);
+    }
+
+    validate_registration(&request)?;
+
+    if AGENTS.with(|a| a.borrow().contains_key(&caller)) {
+        return Err(RegistryError::AlreadyRegistered);
+    }
+
+    let now = ic_cdk::api::time();
+    let profile = AgentProfile {
+        principal: caller,
+        business_name: request.business_name,
+        phone_number: request.phone_number,
+        location: request.location,
+        kyc_status: KycStatus::Pending,
+        status: AgentStatus::Active,
+        cash_float: None,
+        registered_at: now,
+        updated_at: now,
+    };
+
+    AGENTS.with(|a| a.borrow_mut().insert(caller, profile.clone()));
+    Ok(profile)
+}
+
+#[update]
+fn update_agent_location(location: AgentLocation) -> Result<AgentProfile, RegistryError> {
+    validate_location(&location)?;
+
+    update_profile(ic_cdk::api::msg_caller(), |profile| {
+        profile.location = location;
+    })
+}
+
+/// Declare the cash the caller currently holds for withdrawals.
+#[update]
+fn attest_cash_float(amount_ugx: u64) -> Result<AgentProfile, RegistryError> {
+    let now = ic_cdk::api::time();
+
+    update_profile(ic_cdk::api::msg_caller(), |profile| {
+        profile.cash_float = Some(CashFloatAttestation { amount_ugx, attested_at: now });
+    })
+}
+
+// ============================================================================
+// ADMINISTRATION (company wallet only)
+// ============================================================================
+
+#[update]
+fn set_kyc_status(agent: Principal, kyc_status: KycStatus) -> Result<AgentProfile, RegistryError> {
+    require_company_wallet(ic_cdk::api::msg_caller())?;
+
+    update_profile(agent, |profile| {
+        profile.kyc_status = kyc_status;
+    })
+}
+
+#[update]
+fn suspend_agent(agent: Principal, reason: String) -> Result<AgentProfile, RegistryError> {
+    require_company_wallet(ic_cdk::api::msg_caller())?;
+    validate_text("Reason", &reason, MAX_ADDRESS_LEN, true)?;
+
+    let now = ic_cdk::api::time();
+    update_profile(agent, |profile| {
+        profile.status = AgentStatus::Suspended { reason, since: now };
+    })
+}
+
+#[update]
+fn reinstate_agent(agent: Principal) -> Result<AgentProfile, RegistryError> {
+    require_company_wallet(ic_cdk::api::msg_caller())?;
+
+    update_profile(agent, |profile| {
+        profile.status = AgentStatus::Active;
+    })
+}
+
+fn update_profile(agent: Principal, change: impl FnOnce(&mut AgentProfile)) -> Result<AgentProfile, RegistryError> {
+    let mut profile = AGENTS.with(|a| a.borrow().get(&agent)).ok_or(RegistryError::NotRegistered)?;
+
+    change(&mut profile);
+    profile.updated_at = ic_cdk::api::time();
+
+    AGENTS.with(|a| a.borrow_mut().insert(agent, profile.clone()));
+    Ok(profile)
+}
+
+// ============================================================================
+// QUERIES
+// ============================================================================
+
+/// Whether `agent` may take part in deposits and withdrawals. Called by the
+/// deposit and withdrawal canisters before accepting a request.
+#[query]
+fn check_agent(agent: Principal) -> Result<(), AgentIneligible> {
+    AGENTS.with(|a| a.borrow().get(&agent))
+        .ok_or(AgentIneligible::NotRegistered)
+        .and_then(|profile| eligibility(&profile))
+}
+
+fn eligibility(profile: &AgentProfile) -> Result<(), AgentIneligible> {
+    if let AgentStatus::Suspended { .. } = profile.status {
+        return Err(AgentIneligible::Suspended);
+    }
+    if profile.kyc_status != KycStatus::Approved {
+        return Err(AgentIneligible::KycNotApproved);
+    }
+    Ok(())
+}
+
+/// Full profile, including contact details (the agent or company wallet only).
+#[query]
+fn get_agent(agent: Principal) -> Result<AgentProfile, RegistryError> {
+    let caller = ic_cdk::api::msg_caller();
+    if caller != agent {
+        require_company_wallet(caller)?;
+    }
+
+    AGENTS.with(|a| a.borrow().get(&agent)).ok_or(RegistryError::NotRegistered)
+}
+
+// Tests module
+#[cfg(test)]
+mod tests;
+
+// Export Candid interface
+ic_cdk::export_candid!();
@@ -0,0 +1,59 @@
+//! Stable memory layout for the agent registry canister.
+//!
+//! Records are written inside a versioned envelope: when a record layout
+//! changes, add a new variant and convert older variants on read.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::AgentProfile;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Current layout of the records stored by this canister.
+/// Bump this whenever a new envelope variant is introduced.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const AGENTS_MEMORY_ID: MemoryId = MemoryId::new(1);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+fn encode<T: CandidType>(value: &T) -> Cow<'static, [u8]> {
+    Cow::Owned(candid::encode_one(value).expect("Failed to encode stable record"))
+}
+
+fn decode<T: CandidType + for<'de> Deserialize<'de>>(bytes: &[u8]) -> T {
+    candid::decode_one(bytes).expect("Failed to decode stable record")
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredAgentProfile {
+    V1(AgentProfile),
+}
+
+impl Storable for AgentProfile {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(&StoredAgentProfile::V1(self.clone()))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match decode(&bytes) {
+            StoredAgentProfile::V1(profile) => profile,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -0,0 +1,108 @@
+use super::*;
+
+fn sample_location() -> AgentLocation {
+    AgentLocation {
+        country: "Uganda".to_string(),
+        region: "Central".to_string(),
+        city: "Kampala".to_string(),
+        address: "Plot 12, Kampala Road".to_string(),
+        latitude: Some(0.3136),
+        longitude: Some(32.5811),
+    }
+}
+
+fn sample_profile() -> AgentProfile {
+    AgentProfile {
+        principal: Principal::from_slice(&[2]),
+        business_name: "Kampala Mobile Money".to_string(),
+        phone_number: "+256700000000".to_string(),
+        location: sample_location(),
+        kyc_status: KycStatus::Approved,
+        status: AgentStatus::Active,
+        cash_float: None,
+        registered_at: 0,
+        updated_at: 0,
+    }
+}
+
+// ============================================================================
+// ELIGIBILITY TESTS
+// ============================================================================
+
+#[test]
+fn test_approved_active_agent_is_eligible() {
+    assert_eq!(eligibility(&sample_profile()), Ok(()));
+}
+
+#[test]
+fn test_pending_or_rejected_kyc_is_ineligible() {
+    let mut profile = sample_profile();
+
+    profile.kyc_status = KycStatus::Pending;
+    assert_eq!(eligibility(&profile), Err(AgentIneligible::KycNotApproved));
+
+    profile.kyc_status = KycStatus::Rejected { reason: "Expired ID".to_string() };
+    assert_eq!(eligibility(&profile), Err(AgentIneligible::KycNotApproved));
+}
+
+#[test]
+fn test_suspended_agent_is_ineligible() {
+    let mut profile = sample_profile();
+    profile.status = AgentStatus::Suspended { reason: "Cash shortfall".to_string(), since: 1 };
+
+    assert_eq!(eligibility(&profile), Err(AgentIneligible::Suspended));
+}
+
+#[test]
+fn test_unregistered_agent_is_ineligible() {
+    assert_eq!(check_agent(Principal::from_slice(&[9])), Err(AgentIneligible::NotRegistered));
+
+    let profile = sample_profile();
+    AGENTS.with(|a| a.borrow_mut().insert(profile.principal, profile.clone()));
+    assert_eq!(check_agent(profile.principal), Ok(()));
+}
+
+// ============================================================================
+// VALIDATION TESTS
+// ============================================================================
+
+#[test]
+fn test_registration_requires_name_phone_and_place() {
+    let request = RegisterAgentRequest {
+        business_name: "Kampala Mobile Money".to_string(),
+        phone_number: "+256700000000".to_string(),
+        location: sample_location(),
+    };
+    assert!(validate_registration(&request).is_ok());
+
+    let no_name = RegisterAgentRequest { business_name: "  ".to_string(), ..request };
+    assert!(matches!(validate_registration(&no_name), Err(RegistryError::InvalidInput { .. })));
+}
+
+#[test]
+fn test_location_coordinates_are_range_checked() {
+    let mut location = sample_location();
+    location.latitude = Some(91.0);
+    assert!(validate_location(&location).is_err());
+
+    let mut location = sample_location();
+    location.longitude = Some(-181.0);
+    assert!(validate_location(&location).is_err());
+
+    let mut location = sample_location();
+    location.latitude = None;
+    location.longitude = None;
+    assert!(validate_location(&location).is_ok());
+}
+
+#[test]
+fn test_agent_profile_survives_stable_roundtrip() {
+    use ic_stable_structures::Storable;
+
+    let profile = sample_profile();
+    let restored = AgentProfile::from_bytes(profile.to_bytes());
+
+    assert_eq!(restored.principal, profile.principal);
+    assert_eq!(restored.location, profile.location);
+    assert_eq!(restored.kyc_status, KycStatus::Approved);
+}
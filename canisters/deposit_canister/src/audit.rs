@@ -0,0 +1,63 @@
+//! Append-only audit log.
+//!
+//! Every state transition in the canister (deposit status changes, disputes,
+//! settlements, config changes) appends an event recording who did it, to
+//! what, and the status before and after. Events are never modified or
+//! removed, so compliance and dispute reviews can replay any record's history.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_shared::audit::AuditLog;
+use serde::Serialize;
+use std::cell::RefCell;
+
+pub use canister_shared::audit::Transition;
+
+use crate::storage::{self, Memory};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AuditAction {
+    DepositCreated,
+    DepositConfirmed,
+    DepositCancelled,
+    DepositExpired,
+    DisputeOpened,
+    DisputeResolved,
+    FraudAlertRaised,
+    FraudAlertReviewed,
+    SettlementGenerated,
+    SettlementRecomputed,
+    PeriodClosed,
+    SettlementPayoutStarted,
+    SettlementPayoutFailed,
+    SettlementPaid,
+    ConfigChanged,
+}
+
+/// The record an event applies to.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AuditEntity {
+    Deposit { id: u64 },
+    FraudAlert { id: u64 },
+    Settlement { id: u64 },
+    Period { month: String },
+    Config { change_id: u64 },
+}
+
+pub type AuditEvent = canister_shared::audit::AuditEvent<AuditAction, AuditEntity>;
+
+thread_local! {
+    static EVENTS: RefCell<AuditLog<AuditAction, AuditEntity, Memory>> = RefCell::new(
+        AuditLog::init(storage::memory(storage::AUDIT_LOG_MEMORY_ID))
+    );
+}
+
+/// Append an event and return its id.
+pub fn record(actor: Principal, action: AuditAction, entity: AuditEntity, transition: Transition, now: u64) -> u64 {
+    EVENTS.with(|e| e.borrow_mut().record(actor, action, entity, transition, now))
+}
+
+/// Events newest first. `before` is an exclusive event id cursor from a
+/// previous page.
+pub fn events(before: Option<u64>, limit: u64) -> Vec<AuditEvent> {
+    EVENTS.with(|e| e.borrow().events(before, limit))
+}
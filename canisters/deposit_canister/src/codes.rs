@@ -0,0 +1,84 @@
+//! Deposit code generation and validation.
+//!
+//! Codes look like `DEP-7KQ2-M9XD`: seven random Crockford base32 characters
+//! followed by a Luhn mod 32 check character, so a mistyped code is rejected
+//! before any lookup. The alphabet omits I, L, O and U so codes can be read
+//! aloud at an agent kiosk without ambiguity.
+
+const PREFIX: &str = "DEP";
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Random characters per code (35 bits of entropy).
+pub const CODE_BODY_LEN: usize = 7;
+
+fn char_value(c: u8) -> Option<u8> {
+    ALPHABET.iter().position(|&a| a == c).map(|p| p as u8)
+}
+
+/// Luhn mod N check character over base32 digit values.
+fn check_char(body: &[u8]) -> u8 {
+    let n = ALPHABET.len() as u32;
+    let mut factor = 2;
+    let mut sum = 0u32;
+
+    for &value in body.iter().rev() {
+        let addend = factor * value as u32;
+        sum += addend / n + addend % n;
+        factor = if factor == 2 { 1 } else { 2 };
+    }
+
+    ALPHABET[((n - sum % n) % n) as usize]
+}
+
+fn format_code(body: &[u8]) -> String {
+    let mut chars: Vec<u8> = body.iter().map(|&v| ALPHABET[v as usize]).collect();
+    chars.push(check_char(body));
+
+    let chars = String::from_utf8(chars).expect("Alphabet is ASCII");
+    format!("{}-{}-{}", PREFIX, &chars[..4], &chars[4..])
+}
+
+/// Build a deposit code from `CODE_BODY_LEN` bytes of entropy.
+pub fn generate_deposit_code(entropy: &[u8]) -> String {
+    assert!(entropy.len() >= CODE_BODY_LEN, "Not enough entropy for a deposit code");
+
+    let body: Vec<u8> = entropy[..CODE_BODY_LEN].iter().map(|b| b % 32).collect();
+    format_code(&body)
+}
+
+/// Canonicalize user input (case, dashes, spaces, look-alike characters) and
+/// verify the check character. Returns `None` if the code cannot be valid.
+pub fn normalize_deposit_code(input: &str) -> Option<String> {
+    let cleaned: String = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_ascii_uppercase();
+
+    let cleaned = if cleaned.len() == PREFIX.len() + CODE_BODY_LEN + 1 {
+        cleaned.strip_prefix(PREFIX).unwrap_or(&cleaned)
+    } else {
+        &cleaned
+    };
+
+    let values: Vec<u8> = cleaned
+        .bytes()
+        .map(|c| match c {
+            b'O' => b'0',
+            b'I' | b'L' => b'1',
+            other => other,
+        })
+        .map(char_value)
+        .collect::<Option<_>>()?;
+
+    if values.len() != CODE_BODY_LEN + 1 {
+        return None;
+    }
+
+    let (body, check) = values.split_at(CODE_BODY_LEN);
+    if check_char(body) != ALPHABET[check[0] as usize] {
+        return None;
+    }
+
+    Some(format_code(body))
+}
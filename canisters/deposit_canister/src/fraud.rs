@@ -0,0 +1,6 @@
+//! Fraud rules and the alerts they raise; see `canister_shared::fraud`.
+
+pub use canister_shared::fraud::{
+    latest_alert, raise_alert, screen, strongest, validate, AlertStatus, AlertSubject, FraudAction, FraudAlert,
+    FraudConfig, FraudRule, PastRequest, ReviewDecision, RuleHit, Screening, ScreeningStage,
+};
@@ -0,0 +1,84 @@
+//! Minimal client for external ICRC-1 token ledgers (e.g. ckUSDC).
+//!
+//! Only the types needed to send a transfer are declared here; they follow
+//! the ICRC-1 standard Candid interface.
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::call::Call;
+use serde_bytes::ByteBuf;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Icrc1Account {
+    pub owner: Principal,
+    pub subaccount: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Icrc1TransferArg {
+    pub from_subaccount: Option<ByteBuf>,
+    pub to: Icrc1Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Icrc1TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Outcome of a transfer attempt, from the point of view of the payer.
+#[derive(Debug, PartialEq)]
+pub enum TransferOutcome {
+    /// The ledger holds a matching transfer at this block index.
+    Completed { block_index: u64 },
+    /// The ledger definitely rejected the transfer; nothing moved.
+    Rejected { reason: String },
+    /// The call failed without a ledger answer; the transfer may or may not
+    /// have happened. Retry with the same `created_at_time` and memo so the
+    /// ledger's deduplication resolves it. Past the deduplication window
+    /// (`TooOld`) only the ledger history can tell, so a controller resolves it.
+    Unknown { reason: String },
+}
+
+fn nat_to_u64(n: &Nat) -> Option<u64> {
+    n.0.clone().try_into().ok()
+}
+
+/// Map a ledger reply onto a `TransferOutcome`. A `Duplicate` answer means an
+/// earlier attempt with the same arguments already landed.
+pub fn classify(result: Result<Nat, Icrc1TransferError>) -> TransferOutcome {
+    match result {
+        Ok(block) | Err(Icrc1TransferError::Duplicate { duplicate_of: block }) => match nat_to_u64(&block) {
+            Some(block_index) => TransferOutcome::Completed { block_index },
+            None => TransferOutcome::Unknown { reason: format!("Block index out of range: {}", block) },
+        },
+        Err(Icrc1TransferError::TooOld) => TransferOutcome::Unknown {
+            reason: "Ledger deduplication window has passed; check the ledger and resolve_settlement_payout".to_string(),
+        },
+        Err(Icrc1TransferError::TemporarilyUnavailable) => TransferOutcome::Rejected {
+            reason: "Ledger temporarily unavailable".to_string(),
+        },
+        Err(e) => TransferOutcome::Rejected { reason: format!("Ledger rejected transfer: {:?}", e) },
+    }
+}
+
+pub async fn transfer(ledger: Principal, arg: Icrc1TransferArg) -> TransferOutcome {
+    let response = match Call::unbounded_wait(ledger, "icrc1_transfer").with_arg(arg).await {
+        Ok(response) => response,
+        Err(e) => return TransferOutcome::Unknown { reason: format!("Call failed: {:?}", e) },
+    };
+
+    match candid::decode_one::<Result<Nat, Icrc1TransferError>>(&response.into_bytes()) {
+        Ok(result) => classify(result),
+        Err(e) => TransferOutcome::Unknown { reason: format!("Decode failed: {:?}", e) },
+    }
+}
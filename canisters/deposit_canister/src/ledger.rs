@@ -0,0 +1,371 @@
+//! Authoritative fiat ledger, modelled on ICRC-1 and ICRC-2.
+//!
+//! Balances are held per `(currency, account)`. The deposit canister is the
+//! minting account: confirming a deposit mints the user's digital balance and
+//! reversing one burns it. Owners can approve other principals (e.g. the
+//! withdrawal canister) to move funds with `transfer_from`. Every movement is
+//! appended to an immutable block log and indexed per account for history
+//! queries.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde::Serialize;
+use std::cell::RefCell;
+
+pub use canister_shared::currency::Currency;
+
+use crate::storage::{self, Memory};
+
+pub type Subaccount = [u8; 32];
+
+const DEFAULT_SUBACCOUNT: Subaccount = [0; 32];
+
+/// Maximum number of blocks returned by a single history query.
+pub const MAX_HISTORY_PAGE: u64 = 100;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    pub fn of(owner: Principal) -> Self {
+        Account { owner, subaccount: None }
+    }
+}
+
+/// Balance key. `None` and the all-zero subaccount are the same account.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AccountKey {
+    pub currency: Currency,
+    pub owner: Principal,
+    pub subaccount: Subaccount,
+}
+
+impl AccountKey {
+    fn new(account: &Account, currency: Currency) -> Self {
+        AccountKey {
+            currency,
+            owner: account.owner,
+            subaccount: account.subaccount.unwrap_or(DEFAULT_SUBACCOUNT),
+        }
+    }
+}
+
+/// Per-account history index entry. Its stable encoding sorts by account,
+/// then block index (see `storage.rs`).
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AccountBlockKey {
+    pub account: AccountKey,
+    pub block_index: u64,
+}
+
+/// ICRC-2 allowance key: `account` lets `spender` move its funds.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AllowanceKey {
+    pub account: AccountKey,
+    pub spender: Principal,
+    pub spender_subaccount: Subaccount,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Allowance {
+    pub allowance: u64,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum LedgerOperation {
+    Mint { to: Account },
+    Burn { from: Account },
+    Transfer { from: Account, to: Account },
+    Approve { from: Account, spender: Account },
+    TransferFrom { from: Account, to: Account, spender: Account },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct LedgerBlock {
+    pub index: u64,
+    pub currency: Currency,
+    pub operation: LedgerOperation,
+    pub amount: u64,
+    pub memo: Option<Vec<u8>>,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub currency: Currency,
+    pub amount: u64,
+    pub memo: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub spender: Account,
+    pub currency: Currency,
+    /// Replaces any existing allowance; 0 revokes it
+    pub amount: u64,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub currency: Currency,
+    pub amount: u64,
+    pub memo: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TransferError {
+    InsufficientFunds { balance: u64 },
+    InvalidAmount,
+    AnonymousCaller,
+    MemoTooLong { max_len: u64 },
+    InsufficientAllowance { allowance: u64 },
+    Expired { ledger_time: u64 },
+}
+
+const MAX_MEMO_LEN: usize = 32;
+
+thread_local! {
+    static BALANCES: RefCell<StableBTreeMap<AccountKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::LEDGER_BALANCES_MEMORY_ID))
+    );
+
+    // Append-only block log; the block index is its position
+    static BLOCKS: RefCell<StableBTreeMap<u64, LedgerBlock, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::LEDGER_BLOCKS_MEMORY_ID))
+    );
+
+    static ACCOUNT_BLOCKS: RefCell<StableBTreeMap<AccountBlockKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::LEDGER_ACCOUNT_BLOCKS_MEMORY_ID))
+    );
+
+    static ALLOWANCES: RefCell<StableBTreeMap<AllowanceKey, Allowance, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::LEDGER_ALLOWANCES_MEMORY_ID))
+    );
+}
+
+pub fn balance_of(account: &Account, currency: Currency) -> u64 {
+    BALANCES.with(|b| b.borrow().get(&AccountKey::new(account, currency))).unwrap_or(0)
+}
+
+fn set_balance(key: AccountKey, amount: u64) {
+    BALANCES.with(|b| {
+        let mut balances = b.borrow_mut();
+        if amount == 0 {
+            balances.remove(&key);
+        } else {
+            balances.insert(key, amount);
+        }
+    });
+}
+
+fn append_block(
+    currency: Currency,
+    operation: LedgerOperation,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    timestamp: u64,
+) -> u64 {
+    let index = BLOCKS.with(|b| b.borrow().len());
+    index_block(&operation, currency, index);
+    BLOCKS.with(|b| {
+        b.borrow_mut().insert(index, LedgerBlock { index, currency, operation, amount, memo, timestamp });
+    });
+
+    index
+}
+
+fn index_block(operation: &LedgerOperation, currency: Currency, index: u64) {
+    let accounts: Vec<Account> = match operation {
+        LedgerOperation::Mint { to } => vec![*to],
+        LedgerOperation::Burn { from } => vec![*from],
+        LedgerOperation::Transfer { from, to } | LedgerOperation::TransferFrom { from, to, .. } => vec![*from, *to],
+        LedgerOperation::Approve { from, .. } => vec![*from],
+    };
+
+    ACCOUNT_BLOCKS.with(|a| {
+        let mut account_blocks = a.borrow_mut();
+        for account in accounts {
+            account_blocks.insert(
+                AccountBlockKey { account: AccountKey::new(&account, currency), block_index: index },
+                (),
+            );
+        }
+    });
+}
+
+/// Credit newly issued funds to `to`. Returns the block index.
+pub fn mint(to: Account, currency: Currency, amount: u64, memo: Option<Vec<u8>>, now: u64) -> u64 {
+    let key = AccountKey::new(&to, currency);
+    let balance = balance_of(&to, currency);
+    set_balance(key, balance.saturating_add(amount));
+    append_block(currency, LedgerOperation::Mint { to }, amount, memo, now)
+}
+
+/// Destroy up to `amount` from `from`. Burns whatever is available and
+/// returns `(block index, amount actually burned)`.
+pub fn burn(from: Account, currency: Currency, amount: u64, memo: Option<Vec<u8>>, now: u64) -> (u64, u64) {
+    let key = AccountKey::new(&from, currency);
+    let balance = balance_of(&from, currency);
+    let burned = amount.min(balance);
+    set_balance(key, balance - burned);
+    (append_block(currency, LedgerOperation::Burn { from }, burned, memo, now), burned)
+}
+
+pub fn transfer(
+    from: Account,
+    to: Account,
+    currency: Currency,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    now: u64,
+) -> Result<u64, TransferError> {
+    check_transfer(&from, currency, amount, &memo)?;
+    move_funds(&from, &to, currency, amount);
+
+    Ok(append_block(currency, LedgerOperation::Transfer { from, to }, amount, memo, now))
+}
+
+fn check_memo(memo: &Option<Vec<u8>>) -> Result<(), TransferError> {
+    if memo.as_ref().is_some_and(|m| m.len() > MAX_MEMO_LEN) {
+        return Err(TransferError::MemoTooLong { max_len: MAX_MEMO_LEN as u64 });
+    }
+
+    Ok(())
+}
+
+fn check_transfer(from: &Account, currency: Currency, amount: u64, memo: &Option<Vec<u8>>) -> Result<(), TransferError> {
+    if amount == 0 {
+        return Err(TransferError::InvalidAmount);
+    }
+
+    check_memo(memo)?;
+
+    let balance = balance_of(from, currency);
+    if balance < amount {
+        return Err(TransferError::InsufficientFunds { balance });
+    }
+
+    Ok(())
+}
+
+fn move_funds(from: &Account, to: &Account, currency: Currency, amount: u64) {
+    let from_balance = balance_of(from, currency);
+    set_balance(AccountKey::new(from, currency), from_balance - amount);
+    let to_balance = balance_of(to, currency);
+    set_balance(AccountKey::new(to, currency), to_balance.saturating_add(amount));
+}
+
+fn allowance_key(account: &Account, spender: &Account, currency: Currency) -> AllowanceKey {
+    AllowanceKey {
+        account: AccountKey::new(account, currency),
+        spender: spender.owner,
+        spender_subaccount: spender.subaccount.unwrap_or(DEFAULT_SUBACCOUNT),
+    }
+}
+
+/// What `spender` may still move from `account`. Expired allowances are zero.
+pub fn allowance(account: &Account, spender: &Account, currency: Currency, now: u64) -> Allowance {
+    ALLOWANCES
+        .with(|a| a.borrow().get(&allowance_key(account, spender, currency)))
+        .filter(|a| a.expires_at.is_none_or(|expires_at| expires_at > now))
+        .unwrap_or_default()
+}
+
+/// Set (not add to) the allowance `from` grants `spender`. Returns the block index.
+pub fn approve(
+    from: Account,
+    spender: Account,
+    currency: Currency,
+    amount: u64,
+    expires_at: Option<u64>,
+    memo: Option<Vec<u8>>,
+    now: u64,
+) -> Result<u64, TransferError> {
+    check_memo(&memo)?;
+
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(TransferError::Expired { ledger_time: now });
+    }
+
+    let key = allowance_key(&from, &spender, currency);
+    ALLOWANCES.with(|a| {
+        let mut allowances = a.borrow_mut();
+        if amount == 0 {
+            allowances.remove(&key);
+        } else {
+            allowances.insert(key, Allowance { allowance: amount, expires_at });
+        }
+    });
+
+    Ok(append_block(currency, LedgerOperation::Approve { from, spender }, amount, memo, now))
+}
+
+/// Move funds from `from` on its behalf, spending `spender`'s allowance.
+pub fn transfer_from(
+    spender: Account,
+    from: Account,
+    to: Account,
+    currency: Currency,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    now: u64,
+) -> Result<u64, TransferError> {
+    let current = allowance(&from, &spender, currency, now);
+    if current.allowance < amount {
+        return Err(TransferError::InsufficientAllowance { allowance: current.allowance });
+    }
+
+    check_transfer(&from, currency, amount, &memo)?;
+
+    let key = allowance_key(&from, &spender, currency);
+    ALLOWANCES.with(|a| {
+        let mut allowances = a.borrow_mut();
+        if current.allowance == amount {
+            allowances.remove(&key);
+        } else {
+            allowances.insert(key, Allowance { allowance: current.allowance - amount, ..current });
+        }
+    });
+    move_funds(&from, &to, currency, amount);
+
+    Ok(append_block(currency, LedgerOperation::TransferFrom { from, to, spender }, amount, memo, now))
+}
+
+/// Blocks touching `account` in `currency`, newest first. `before` is an
+/// exclusive block index cursor from a previous page.
+pub fn account_history(
+    account: &Account,
+    currency: Currency,
+    before: Option<u64>,
+    limit: u64,
+) -> Vec<LedgerBlock> {
+    let key = AccountKey::new(account, currency);
+    let start = AccountBlockKey { account: key.clone(), block_index: 0 };
+    let end = AccountBlockKey { account: key, block_index: before.unwrap_or(u64::MAX) };
+
+    let indexes: Vec<u64> = ACCOUNT_BLOCKS.with(|a| {
+        a.borrow()
+            .range(start..end)
+            .rev()
+            .take(limit.min(MAX_HISTORY_PAGE) as usize)
+            .map(|(k, _)| k.block_index)
+            .collect()
+    });
+
+    BLOCKS.with(|b| {
+        let blocks = b.borrow();
+        indexes.into_iter().filter_map(|i| blocks.get(&i)).collect()
+    })
+}
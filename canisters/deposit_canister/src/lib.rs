@@ -1,32 +1,102 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk_macros::*;
-use serde::{Serialize, Deserialize as SerdeDeserialize};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::Serialize;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-// Configuration loaded from shared TOML
+mod agent_registry;
+mod audit;
+mod codes;
+mod fraud;
+mod icrc1;
+mod ledger;
+mod periods;
+mod reputation;
+mod storage;
+
+use agent_registry::AgentIneligible;
+use audit::{AuditAction, AuditEntity, AuditEvent, Transition};
+use codes::{generate_deposit_code, normalize_deposit_code, CODE_BODY_LEN};
+use fraud::{AlertStatus, AlertSubject, FraudAction, FraudAlert, FraudConfig, FraudRule, ReviewDecision, RuleHit, ScreeningStage};
+use icrc1::{Icrc1Account, Icrc1TransferArg, TransferOutcome};
+use ledger::{Account, Allowance, ApproveArgs, Currency, LedgerBlock, TransferArgs, TransferError, TransferFromArgs};
+use periods::Month;
+use reputation::{AgentRating, AgentReputation, Tally};
+use storage::Memory;
+
+// Default configuration from the shared TOML; see CONFIGURATION below for
+// runtime changes
 const CONFIG_TOML: &str = include_str!("../../revenue_config.toml");
 
-#[derive(SerdeDeserialize, Clone)]
-struct RevenueConfig {
-    company_wallet: CompanyWalletConfig,
-    deposit: DepositConfig,
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RevenueConfig {
+    pub company_wallet: CompanyWalletConfig,
+    pub agent_registry: AgentRegistryConfig,
+    pub governance: GovernanceConfig,
+    pub deposit: DepositConfig,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CompanyWalletConfig {
+    pub principal: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentRegistryConfig {
+    pub canister_id: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GovernanceConfig {
+    // SNS governance canister allowed to change the config (empty = controllers only)
+    pub sns_governance: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct DepositConfig {
+    // How long a deposit code stays valid before the deposit expires
+    pub code_validity_hours: u64,
+    // Fees and limits per currency code; other currencies are not accepted
+    pub currencies: BTreeMap<String, DepositCurrencyConfig>,
+    // On-chain settlement payouts; when absent settlements are paid off-chain
+    pub settlement_payout: Option<SettlementPayoutConfig>,
+    // Fraud rules screening requests; when absent requests are not screened
+    pub fraud: Option<FraudConfig>,
+    // Withdrawal canister allowed to read users' deposit history for its
+    // round-trip fraud rule; empty = none
+    pub withdrawal_canister: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct DepositCurrencyConfig {
+    pub agent_commission_basis_points: u64,
+    pub platform_fee_basis_points: u64,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    // Velocity caps (0 = unlimited)
+    pub user_daily_limit: u64,
+    pub user_weekly_limit: u64,
+    pub agent_daily_limit: u64,
+    pub agent_weekly_limit: u64,
 }
 
-#[derive(SerdeDeserialize, Clone)]
-struct CompanyWalletConfig {
-    principal: String,
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SettlementPayoutConfig {
+    pub ledger: String,
+    pub decimals: u8,
+    // Currency code -> local units worth one payout token
+    pub units_per_token: BTreeMap<String, u64>,
 }
 
-#[derive(SerdeDeserialize, Clone)]
-struct DepositConfig {
-    #[allow(dead_code)]
-    agent_commission_basis_points: u64,
-    platform_fee_basis_points: u64,
-    #[allow(dead_code)]
-    min_deposit_ugx: u64,
-    #[allow(dead_code)]
-    max_deposit_ugx: u64,
+impl DepositConfig {
+    fn currency(&self, currency: Currency) -> Result<&DepositCurrencyConfig, DepositError> {
+        self.currencies
+            .get(currency.code())
+            .ok_or(DepositError::UnsupportedCurrency { currency })
+    }
 }
 
 // ============================================================================
@@ -38,10 +108,14 @@ pub struct DepositTransaction {
     pub id: u64,
     pub user_principal: Principal,
     pub agent_principal: Principal,
-    pub amount_ugx: u64,
-    pub commission_ugx: u64,
+    pub currency: Currency,
+    pub amount: u64,
+    pub platform_fee: u64,       // AfriTokeni revenue
+    pub agent_commission: u64,   // Earned by the agent, paid out in settlements
     pub deposit_code: String,
     pub timestamp: u64,
+    pub expires_at: u64,
+    pub confirmed_at: Option<u64>,
     pub status: TransactionStatus,
 }
 
@@ -50,31 +124,239 @@ pub enum TransactionStatus {
     Pending,
     Confirmed,
     Cancelled,
+    Expired,
+    Disputed,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct AgentBalance {
     pub principal: Principal,
-    pub total_deposits: u64,
-    pub total_commission_owed: u64,
-    pub total_commission_paid: u64,
+    pub currencies: Vec<CurrencyBalance>,   // One entry per currency the agent has handled
     pub last_settlement_date: Option<u64>,
 }
 
+/// An agent's deposit totals in a single currency.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CurrencyBalance {
+    pub currency: Currency,
+    pub total_deposits: u64,
+    pub total_platform_fees: u64,     // Platform revenue from this agent's deposits
+    pub total_commission_owed: u64,   // Commission owed to the agent
+    pub total_commission_paid: u64,   // Commission already settled to the agent
+}
+
+/// A total in one currency, as returned by the revenue queries.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CurrencyAmount {
+    pub currency: Currency,
+    pub amount: u64,
+}
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct MonthlySettlement {
     pub month: String, // "2024-11"
     pub agent_principal: Principal,
+    pub currency: Currency,
     pub total_commission: u64,
     pub paid: bool,
     pub paid_date: Option<u64>,
+    pub payout_block_index: Option<u64>,     // Ledger block of the on-chain payout
+    pub payout: Option<SettlementPayout>,    // Latest on-chain payout attempt
+}
+
+/// An ICRC-1 transfer paying out a settlement.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SettlementPayout {
+    pub ledger: Principal,
+    pub amount: u64,            // In ledger token units
+    pub created_at_time: u64,   // Reused on retry so the ledger deduplicates
+    pub status: PayoutStatus,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum PayoutStatus {
+    InFlight,                      // Sent, or outcome unknown
+    Completed { block_index: u64 },
+    Failed { reason: String },     // Rejected by the ledger; nothing moved
+}
+
+/// Lifecycle of a settlement month: generated (and possibly refreshed), then closed.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SettlementPeriod {
+    pub month: String,
+    pub generated_by: Principal,
+    pub generated_at: u64,
+    pub last_generated_at: u64,
+    pub closed_by: Option<Principal>,
+    pub closed_at: Option<u64>,
+}
+
+/// Principals with fleet-wide read access. Users and agents need no role:
+/// they can always read their own records.
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaffRole {
+    Admin,
+    Auditor,
+}
+
+/// A runtime configuration change, kept forever as an audit trail.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ConfigChange {
+    pub id: u64,
+    pub changed_by: Principal,
+    pub changed_at: u64,
+    pub old: RevenueConfig,
+    pub new: RevenueConfig,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct DepositInitArgs {
+    pub admins: Vec<Principal>,
+    pub auditors: Vec<Principal>,
+}
+
+/// Per-user / per-agent deposit index key, ordered by creation time.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PartyDepositKey {
+    pub party: Principal,
+    pub timestamp: u64,
+    pub id: u64,
+}
+
+/// Position after the last deposit of a page; pass it back to continue.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DepositCursor {
+    pub timestamp: u64,
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SortOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+/// Filters for deposit history queries. Timestamps are creation times in
+/// nanoseconds; `from` is inclusive and `to` exclusive.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct DepositQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub status: Option<TransactionStatus>,
+    pub order: Option<SortOrder>,
+    pub cursor: Option<DepositCursor>,
+    pub limit: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DepositPage {
+    pub deposits: Vec<DepositTransaction>,
+    pub next_cursor: Option<DepositCursor>,
+}
+
+/// Secondary index key for settlements: one per agent per currency per month.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SettlementKey {
+    pub month: String,
+    pub agent: Principal,
+    pub currency: Currency,
+}
+
+/// Deposit volume accumulated by a user or agent in the current day and week.
+/// Days and weeks are counted from the Unix epoch in UTC.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct VolumeWindow {
+    pub day: u64,
+    pub day_total: u64,
+    pub week: u64,
+    pub week_total: u64,
+}
+
+/// Velocity caps apply per currency, so volume is tracked per (party, currency).
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VolumeKey {
+    pub owner: Principal,
+    pub currency: Currency,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum DepositError {
+    Unauthorized,
+    InvalidAmount,
+    UnsupportedCurrency { currency: Currency },
+    BelowMinimum { min: u64 },
+    AboveMaximum { max: u64 },
+    UserDailyLimitReached { limit: u64, remaining: u64 },
+    UserWeeklyLimitReached { limit: u64, remaining: u64 },
+    AgentDailyLimitReached { limit: u64, remaining: u64 },
+    AgentWeeklyLimitReached { limit: u64, remaining: u64 },
+    CodeGenerationFailed,
+    NotFound,
+    InvalidStatus { status: TransactionStatus },
+    InvalidInput { reason: String },
+    Misconfigured { reason: String },
+    AgentIneligible { reason: AgentIneligible },
+    AgentRegistryUnavailable { reason: String },
+    // Confirmation
+    InvalidCode,
+    AlreadyProcessed { status: TransactionStatus },
+    WrongAgent,
+    CodeExpired,
+    // Fraud screening
+    TransactionBlocked { rules: Vec<FraudRule> },
+    HeldForReview { alert_id: u64 },
+    // Settlements
+    InvalidMonth { month: String },
+    PeriodNotEnded { month: String },
+    PeriodAlreadyClosed { month: String },
+    AlreadyPaid,
+    PayoutInFlight,
+    PayoutNotConfigured,
+    PayoutRateMissing { currency: Currency },
+    PayoutTooSmall,
+    /// Settlements are paid on-chain; use `pay_settlement`
+    PaidOnChainOnly,
+    /// The ledger rejected the payout; it may be retried
+    PayoutFailed { reason: String },
+    /// The ledger call's outcome is unknown; retry `pay_settlement` to reconcile,
+    /// or `resolve_settlement_payout` once the deduplication window has passed
+    PayoutOutcomeUnknown { reason: String },
+    /// Only a payout whose outcome is unknown can be resolved
+    PayoutNotInFlight,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum DisputeOutcome {
+    /// The deposit stands: the agent received the cash
+    Confirmed,
+    /// The deposit is voided: no cash changed hands
+    Cancelled,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DepositDispute {
+    pub deposit_id: u64,
+    pub reason: String,
+    pub evidence_note: String,
+    pub opened_by: Principal,
+    pub opened_at: u64,
+    pub status_before_dispute: TransactionStatus,
+    pub outcome: Option<DisputeOutcome>,
+    pub resolution_note: Option<String>,
+    pub resolved_by: Option<Principal>,
+    pub resolved_at: Option<u64>,
+    /// Credit (in the deposit's currency) that could not be clawed back
+    /// because the user had already spent it
+    pub unrecovered: Option<u64>,
 }
 
 #[derive(CandidType, Deserialize)]
 pub struct CreateDepositRequest {
     pub user_principal: Principal,
     pub agent_principal: Principal,
-    pub amount_ugx: u64,
+    pub currency: Currency,
+    pub amount: u64,
 }
 
 #[derive(CandidType, Deserialize)]
@@ -87,28 +369,323 @@ pub struct ConfirmDepositRequest {
 // STATE
 // ============================================================================
 
+// Config is rebuilt on every install/upgrade from the TOML defaults plus the
+// latest runtime change; everything else lives in stable memory (see
+// storage.rs) and survives upgrades untouched.
 thread_local! {
-    static CONFIG: RefCell<Option<RevenueConfig>> = RefCell::new(None);
-    static DEPOSITS: RefCell<HashMap<u64, DepositTransaction>> = RefCell::new(HashMap::new());
-    static AGENT_BALANCES: RefCell<HashMap<Principal, AgentBalance>> = RefCell::new(HashMap::new());
-    static SETTLEMENTS: RefCell<Vec<MonthlySettlement>> = RefCell::new(Vec::new());
-    static NEXT_DEPOSIT_ID: RefCell<u64> = RefCell::new(1);
+    static CONFIG: RefCell<Option<RevenueConfig>> = const { RefCell::new(None) };
+
+    static SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::SCHEMA_VERSION_MEMORY_ID), 0)
+            .expect("Failed to init schema version")
+    );
+
+    static DEPOSITS: RefCell<StableBTreeMap<u64, DepositTransaction, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::DEPOSITS_MEMORY_ID))
+    );
+
+    static AGENT_BALANCES: RefCell<StableBTreeMap<Principal, AgentBalance, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_BALANCES_MEMORY_ID))
+    );
+
+    static SETTLEMENTS: RefCell<StableBTreeMap<u64, MonthlySettlement, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::SETTLEMENTS_MEMORY_ID))
+    );
+
+    static NEXT_DEPOSIT_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::NEXT_DEPOSIT_ID_MEMORY_ID), 1)
+            .expect("Failed to init deposit id counter")
+    );
+
+    static NEXT_SETTLEMENT_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::NEXT_SETTLEMENT_ID_MEMORY_ID), 1)
+            .expect("Failed to init settlement id counter")
+    );
+
+    static USER_VOLUMES: RefCell<StableBTreeMap<VolumeKey, VolumeWindow, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::USER_VOLUMES_MEMORY_ID))
+    );
+
+    static AGENT_VOLUMES: RefCell<StableBTreeMap<VolumeKey, VolumeWindow, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_VOLUMES_MEMORY_ID))
+    );
+
+    // deposit code -> deposit id
+    static DEPOSIT_CODES: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::DEPOSIT_CODES_MEMORY_ID))
+    );
+
+    // (expires_at, deposit id) for every pending deposit, oldest first
+    static DEPOSIT_EXPIRIES: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::DEPOSIT_EXPIRIES_MEMORY_ID))
+    );
+
+    // (confirmed_at, deposit id) for settlement period queries
+    static CONFIRMED_DEPOSITS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::CONFIRMED_DEPOSITS_MEMORY_ID))
+    );
+
+    // (month, agent, currency) -> settlement id
+    static SETTLEMENT_INDEX: RefCell<StableBTreeMap<SettlementKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::SETTLEMENT_INDEX_MEMORY_ID))
+    );
+
+    static SETTLEMENT_PERIODS: RefCell<StableBTreeMap<String, SettlementPeriod, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::SETTLEMENT_PERIODS_MEMORY_ID))
+    );
+
+    // (user, timestamp, id) and (agent, timestamp, id) for history queries
+    static USER_DEPOSITS: RefCell<StableBTreeMap<PartyDepositKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::USER_DEPOSITS_MEMORY_ID))
+    );
+
+    static AGENT_DEPOSITS: RefCell<StableBTreeMap<PartyDepositKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_DEPOSITS_MEMORY_ID))
+    );
+
+    static STAFF_ROLES: RefCell<StableBTreeMap<Principal, StaffRole, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::STAFF_ROLES_MEMORY_ID))
+    );
+
+    // change id -> runtime config change; the newest entry is the live config
+    static CONFIG_HISTORY: RefCell<StableBTreeMap<u64, ConfigChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::CONFIG_HISTORY_MEMORY_ID))
+    );
+
+    // deposit id -> dispute (open or resolved)
+    static DISPUTES: RefCell<StableBTreeMap<u64, DepositDispute, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::DISPUTES_MEMORY_ID))
+    );
+
+    // deposit id -> the user's rating of the agent; at most one per deposit
+    static AGENT_RATINGS: RefCell<StableBTreeMap<u64, AgentRating, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_RATINGS_MEMORY_ID))
+    );
+
+    // Reputation totals per agent, moved along as deposits and ratings change
+    static AGENT_TALLIES: RefCell<StableBTreeMap<Principal, Tally, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_TALLIES_MEMORY_ID))
+    );
+
+    // Fraud review queue; the alert id is its position
+    static FRAUD_ALERTS: RefCell<StableBTreeMap<u64, FraudAlert, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::FRAUD_ALERTS_MEMORY_ID))
+    );
+
+    // deposit id -> its latest fraud alert
+    static DEPOSIT_ALERTS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::DEPOSIT_ALERTS_MEMORY_ID))
+    );
 }
 
 // ============================================================================
 // INITIALIZATION
 // ============================================================================
 
+// How often pending deposits are checked for expiry
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 #[init]
-fn init() {
-    // Load configuration from shared TOML
-    let config: RevenueConfig = toml::from_str(CONFIG_TOML)
+fn init(args: Option<DepositInitArgs>) {
+    load_config();
+    SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(storage::SCHEMA_VERSION)
+            .expect("Failed to write schema version");
+    });
+    apply_init_args(args);
+    start_timers();
+}
+
+#[post_upgrade]
+fn post_upgrade(args: Option<DepositInitArgs>) {
+    load_config();
+    migrate_schema();
+    apply_init_args(args);
+    start_timers();
+}
+
+fn apply_init_args(args: Option<DepositInitArgs>) {
+    let Some(args) = args else { return };
+    for admin in args.admins {
+        STAFF_ROLES.with(|r| r.borrow_mut().insert(admin, StaffRole::Admin));
+    }
+    for auditor in args.auditors {
+        STAFF_ROLES.with(|r| r.borrow_mut().insert(auditor, StaffRole::Auditor));
+    }
+}
+
+// Timers do not survive upgrades, so they are re-armed on every install
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(EXPIRY_SWEEP_INTERVAL, || {
+        expire_stale_deposits(ic_cdk::api::canister_self(), ic_cdk::api::time());
+    });
+}
+
+fn load_config() {
+    // Load the defaults from shared TOML; a config set at runtime overrides them
+    let defaults: RevenueConfig = toml::from_str(CONFIG_TOML)
         .expect("Failed to parse revenue_config.toml");
     
+    let config = CONFIG_HISTORY.with(|h| h.borrow().last_key_value())
+        .map(|(_, change)| change.new)
+        .unwrap_or(defaults);
+    
     CONFIG.with(|c| *c.borrow_mut() = Some(config));
 }
 
-fn get_config() -> RevenueConfig {
+/// Bring stable records up to `storage::SCHEMA_VERSION`.
+///
+/// Older envelope variants are converted on read, so migrating means
+/// rewriting each record once in the current layout.
+fn migrate_schema() {
+    let stored = SCHEMA_VERSION.with(|v| *v.borrow().get());
+    
+    if stored > storage::SCHEMA_VERSION {
+        ic_cdk::trap(format!(
+            "Stable schema v{} is newer than this build (v{}); refusing to downgrade",
+            stored,
+            storage::SCHEMA_VERSION
+        ));
+    }
+    
+    if stored == storage::SCHEMA_VERSION {
+        return;
+    }
+    
+    DEPOSITS.with(|deposits| {
+        let mut deps = deposits.borrow_mut();
+        let all: Vec<_> = deps.iter().collect();
+        for (id, deposit) in all {
+            deps.insert(id, deposit);
+        }
+    });
+    
+    AGENT_BALANCES.with(|balances| {
+        let mut bals = balances.borrow_mut();
+        let all: Vec<_> = bals.iter().collect();
+        for (agent, balance) in all {
+            bals.insert(agent, balance);
+        }
+    });
+    
+    SETTLEMENTS.with(|settlements| {
+        let mut setts = settlements.borrow_mut();
+        let all: Vec<_> = setts.iter().collect();
+        for (id, settlement) in all {
+            setts.insert(id, settlement);
+        }
+    });
+    
+    // v2: index codes and schedule expiry for deposits created before v2
+    if stored < 2 {
+        DEPOSITS.with(|deposits| {
+            for (id, deposit) in deposits.borrow().iter() {
+                DEPOSIT_CODES.with(|c| c.borrow_mut().insert(deposit.deposit_code.clone(), id));
+                if deposit.status == TransactionStatus::Pending {
+                    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().insert((deposit.expires_at, id), ()));
+                }
+            }
+        });
+    }
+    
+    // v4: index confirmations. Confirmation time was not recorded before v4,
+    // so creation time stands in for it. (v4 also indexed settlements; that
+    // index is rebuilt under currency-aware keys in v6.)
+    if stored < 4 {
+        DEPOSITS.with(|deposits| {
+            let mut deps = deposits.borrow_mut();
+            let confirmed: Vec<_> = deps.iter()
+                .filter(|(_, d)| d.status == TransactionStatus::Confirmed && d.confirmed_at.is_none())
+                .collect();
+            for (id, mut deposit) in confirmed {
+                deposit.confirmed_at = Some(deposit.timestamp);
+                CONFIRMED_DEPOSITS.with(|c| c.borrow_mut().insert((deposit.timestamp, id), ()));
+                deps.insert(id, deposit);
+            }
+        });
+    }
+    
+    // v5: per-user and per-agent deposit history indexes
+    if stored < 5 {
+        DEPOSITS.with(|deposits| {
+            for (_, deposit) in deposits.borrow().iter() {
+                index_deposit(&deposit);
+            }
+        });
+    }
+    
+    // v6: multi-currency. Records written before v6 are UGX; velocity
+    // windows and the settlement index moved to currency-aware keys in new
+    // memories.
+    if stored < 6 {
+        DISPUTES.with(|disputes| {
+            let mut disps = disputes.borrow_mut();
+            let all: Vec<_> = disps.iter().collect();
+            for (id, dispute) in all {
+                disps.insert(id, dispute);
+            }
+        });
+        
+        migrate_legacy_volumes(storage::LEGACY_USER_VOLUMES_MEMORY_ID, &USER_VOLUMES);
+        migrate_legacy_volumes(storage::LEGACY_AGENT_VOLUMES_MEMORY_ID, &AGENT_VOLUMES);
+        index_settlements();
+    }
+    
+    // v7: agent reputation is kept as running totals
+    if stored < 7 {
+        DEPOSITS.with(|deposits| {
+            for (_, deposit) in deposits.borrow().iter() {
+                let disputed = DISPUTES.with(|d| d.borrow().contains_key(&deposit.id));
+                update_tally(deposit.agent_principal, |t| t.add_request(&reputation::outcome(&deposit, disputed)));
+            }
+        });
+        AGENT_RATINGS.with(|ratings| {
+            for (_, rating) in ratings.borrow().iter() {
+                update_tally(rating.agent, |t| t.add_rating(rating.stars));
+            }
+        });
+    }
+    
+    // v8: fraud alert subjects name their deposit `request_id`
+    if stored < 8 {
+        FRAUD_ALERTS.with(|alerts| {
+            let mut alerts = alerts.borrow_mut();
+            let all: Vec<_> = alerts.iter().collect();
+            for (id, alert) in all {
+                alerts.insert(id, alert);
+            }
+        });
+    }
+    
+    SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(storage::SCHEMA_VERSION)
+            .expect("Failed to write schema version");
+    });
+}
+
+fn index_settlements() {
+    SETTLEMENTS.with(|settlements| {
+        for (id, settlement) in settlements.borrow().iter() {
+            SETTLEMENT_INDEX.with(|i| i.borrow_mut().insert(settlement_key(&settlement), id));
+        }
+    });
+}
+
+/// Copy pre-v6 velocity windows, which were keyed by principal alone and
+/// counted UGX, into the per-currency map.
+fn migrate_legacy_volumes(legacy_memory: MemoryId, volumes: &'static VolumeMap) {
+    let legacy: StableBTreeMap<Principal, VolumeWindow, Memory> = StableBTreeMap::init(storage::memory(legacy_memory));
+    volumes.with(|v| {
+        let mut vols = v.borrow_mut();
+        for (owner, window) in legacy.iter() {
+            vols.insert(VolumeKey { owner, currency: Currency::UGX }, window);
+        }
+    });
+}
+
+fn current_config() -> RevenueConfig {
     CONFIG.with(|c| {
         c.borrow()
             .clone()
@@ -117,235 +694,1632 @@ fn get_config() -> RevenueConfig {
 }
 
 fn get_company_wallet() -> Result<Principal, String> {
-    let config = get_config();
+    let config = current_config();
     Principal::from_text(&config.company_wallet.principal)
         .map_err(|e| format!("Invalid company wallet principal: {}", e))
 }
 
+fn agent_registry_id() -> Result<Principal, String> {
+    let config = current_config();
+    if config.agent_registry.canister_id.is_empty() {
+        return Err("Agent registry canister is not configured".to_string());
+    }
+    Principal::from_text(&config.agent_registry.canister_id)
+        .map_err(|e| format!("Invalid agent registry canister id: {}", e))
+}
+
 // ============================================================================
 // DEPOSIT FLOW
 // ============================================================================
 
 #[update]
-fn create_deposit_request(request: CreateDepositRequest) -> Result<DepositTransaction, String> {
+async fn create_deposit_request(request: CreateDepositRequest) -> Result<DepositTransaction, DepositError> {
     let caller = ic_cdk::api::msg_caller();
     
     // Verify caller is the user
     if caller != request.user_principal {
-        return Err("Caller must be the user".to_string());
+        return Err(DepositError::Unauthorized);
     }
     
-    if request.amount_ugx == 0 {
-        return Err("Amount must be greater than 0".to_string());
+    // Only registered, active, KYC-approved agents may take deposits
+    let registry = agent_registry_id().map_err(|reason| DepositError::Misconfigured { reason })?;
+    agent_registry::check_agent(registry, request.agent_principal)
+        .await
+        .map_err(|reason| DepositError::AgentRegistryUnavailable { reason })?
+        .map_err(|reason| DepositError::AgentIneligible { reason })?;
+    
+    // Fetch entropy before touching state so all checks below run atomically
+    let entropy = ic_cdk::management_canister::raw_rand()
+        .await
+        .map_err(|_| DepositError::CodeGenerationFailed)?;
+    
+    let config = current_config();
+    let currency_config = config.deposit.currency(request.currency)?;
+    check_amount_bounds(request.amount, currency_config)?;
+    
+    // Enforce per-user and per-agent velocity caps in the deposit's currency
+    let user_key = VolumeKey { owner: request.user_principal, currency: request.currency };
+    let agent_key = VolumeKey { owner: request.agent_principal, currency: request.currency };
+    let today = day_index(ic_cdk::api::time());
+    let user_window = USER_VOLUMES.with(|v| v.borrow().get(&user_key))
+        .unwrap_or_default()
+        .rolled_to(today);
+    let agent_window = AGENT_VOLUMES.with(|v| v.borrow().get(&agent_key))
+        .unwrap_or_default()
+        .rolled_to(today);
+    
+    check_velocity(
+        &user_window,
+        request.amount,
+        currency_config.user_daily_limit,
+        currency_config.user_weekly_limit,
+    ).map_err(|cap| match cap.period {
+        CapPeriod::Daily => DepositError::UserDailyLimitReached {
+            limit: cap.limit,
+            remaining: cap.remaining,
+        },
+        CapPeriod::Weekly => DepositError::UserWeeklyLimitReached {
+            limit: cap.limit,
+            remaining: cap.remaining,
+        },
+    })?;
+    
+    check_velocity(
+        &agent_window,
+        request.amount,
+        currency_config.agent_daily_limit,
+        currency_config.agent_weekly_limit,
+    ).map_err(|cap| match cap.period {
+        CapPeriod::Daily => DepositError::AgentDailyLimitReached {
+            limit: cap.limit,
+            remaining: cap.remaining,
+        },
+        CapPeriod::Weekly => DepositError::AgentWeeklyLimitReached {
+            limit: cap.limit,
+            remaining: cap.remaining,
+        },
+    })?;
+    
+    // Screen before anything is reserved; a blocked request leaves only its alert
+    let now = ic_cdk::api::time();
+    let subject = AlertSubject {
+        request_id: None,
+        user: request.user_principal,
+        agent: request.agent_principal,
+        currency: request.currency,
+        amount: request.amount,
+    };
+    let hits = screen_deposit(&config.deposit, &subject, now);
+    let verdict = fraud::strongest(&hits);
+    if verdict == Some(FraudAction::Block) {
+        let rules = hits.iter().map(|hit| hit.rule).collect();
+        raise_alert(caller, subject, ScreeningStage::Create, FraudAction::Block, hits, now);
+        return Err(DepositError::TransactionBlocked { rules });
     }
     
-    // Generate unique deposit code
-    let deposit_id = NEXT_DEPOSIT_ID.with(|id| {
-        let current = *id.borrow();
-        *id.borrow_mut() = current + 1;
-        current
+    // Generate unique, unguessable deposit code
+    let deposit_code = entropy
+        .chunks_exact(CODE_BODY_LEN)
+        .map(generate_deposit_code)
+        .find(|code| DEPOSIT_CODES.with(|c| !c.borrow().contains_key(code)))
+        .ok_or(DepositError::CodeGenerationFailed)?;
+    
+    USER_VOLUMES.with(|v| {
+        v.borrow_mut().insert(user_key, user_window.with_amount(request.amount));
+    });
+    AGENT_VOLUMES.with(|v| {
+        v.borrow_mut().insert(agent_key, agent_window.with_amount(request.amount));
     });
     
-    let deposit_code = generate_deposit_code(deposit_id);
+    let deposit_id = next_id(&NEXT_DEPOSIT_ID);
     
-    // Calculate commission from config
-    let config = get_config();
-    let commission = (request.amount_ugx * config.deposit.platform_fee_basis_points) / 10000;
+    // Calculate fees from config
+    let (platform_fee, agent_commission) = calculate_fees(request.amount, currency_config);
+    
+    let expires_at = now + config.deposit.code_validity_hours * NANOS_PER_HOUR;
     
     let transaction = DepositTransaction {
         id: deposit_id,
         user_principal: request.user_principal,
         agent_principal: request.agent_principal,
-        amount_ugx: request.amount_ugx,
-        commission_ugx: commission,
+        currency: request.currency,
+        amount: request.amount,
+        platform_fee,
+        agent_commission,
         deposit_code: deposit_code.clone(),
-        timestamp: ic_cdk::api::time(),
+        timestamp: now,
+        expires_at,
+        confirmed_at: None,
         status: TransactionStatus::Pending,
     };
     
-    DEPOSITS.with(|deposits| {
-        deposits.borrow_mut().insert(deposit_id, transaction.clone());
-    });
+    store_deposit(&transaction);
+    DEPOSIT_CODES.with(|c| c.borrow_mut().insert(deposit_code, deposit_id));
+    // A held code must not lapse while under review
+    if verdict != Some(FraudAction::Hold) {
+        DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().insert((expires_at, deposit_id), ()));
+    }
+    index_deposit(&transaction);
+    audit::record(
+        caller,
+        AuditAction::DepositCreated,
+        AuditEntity::Deposit { id: deposit_id },
+        Transition::status(None, &transaction.status),
+        now,
+    );
+    
+    if let Some(action) = verdict {
+        let subject = AlertSubject { request_id: Some(deposit_id), ..subject };
+        raise_alert(caller, subject, ScreeningStage::Create, action, hits, now);
+    }
     
     Ok(transaction)
 }
 
 #[update]
-fn confirm_deposit(request: ConfirmDepositRequest) -> Result<DepositTransaction, String> {
+fn confirm_deposit(request: ConfirmDepositRequest) -> Result<DepositTransaction, DepositError> {
     let caller = ic_cdk::api::msg_caller();
     
     // Verify caller is the agent
     if caller != request.agent_principal {
-        return Err("Only the assigned agent can confirm".to_string());
+        return Err(DepositError::Unauthorized);
     }
     
     // Find deposit by code
-    let deposit_id = DEPOSITS.with(|deposits| {
-        deposits.borrow()
-            .iter()
-            .find(|(_, d)| d.deposit_code == request.deposit_code)
-            .map(|(id, _)| *id)
-    }).ok_or("Deposit code not found".to_string())?;
+    let deposit_id = find_deposit_id_by_code(&request.deposit_code)?;
+    
+    let mut transaction = DEPOSITS.with(|deposits| deposits.borrow().get(&deposit_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    if transaction.status != TransactionStatus::Pending {
+        return Err(DepositError::AlreadyProcessed { status: transaction.status });
+    }
+    
+    if transaction.agent_principal != request.agent_principal {
+        return Err(DepositError::WrongAgent);
+    }
+    
+    // A lapsed code is expired right away rather than waiting for the sweep
+    let now = ic_cdk::api::time();
+    if now >= transaction.expires_at {
+        expire_deposit(deposit_id, caller, now);
+        return Err(DepositError::CodeExpired);
+    }
+    
+    screen_confirmation(caller, &transaction, now)?;
     
     // Update deposit status
-    let transaction = DEPOSITS.with(|deposits| {
-        let mut deps = deposits.borrow_mut();
-        let deposit = deps.get_mut(&deposit_id)
-            .ok_or("Deposit not found".to_string())?;
-        
-        if deposit.status != TransactionStatus::Pending {
-            return Err("Deposit already processed".to_string());
-        }
-        
-        if deposit.agent_principal != request.agent_principal {
-            return Err("Wrong agent".to_string());
-        }
-        
-        deposit.status = TransactionStatus::Confirmed;
-        Ok(deposit.clone())
-    })?;
+    mark_confirmed(&mut transaction, now);
+    store_deposit(&transaction);
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(transaction.expires_at, deposit_id)));
+    
+    // Mint the user's digital balance
+    credit_user(&transaction, now);
     
     // Update agent balance
-    update_agent_balance(
-        request.agent_principal,
-        transaction.amount_ugx,
-        transaction.commission_ugx,
+    update_agent_balance(&transaction);
+    
+    audit::record(
+        caller,
+        AuditAction::DepositConfirmed,
+        AuditEntity::Deposit { id: deposit_id },
+        Transition::status(Some(&TransactionStatus::Pending), &transaction.status),
+        now,
     );
     
     Ok(transaction)
 }
 
-// ============================================================================
-// AGENT BALANCE MANAGEMENT
-// ============================================================================
+#[update]
+fn cancel_deposit(deposit_id: u64) -> Result<DepositTransaction, DepositError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    let mut deposit = DEPOSITS.with(|d| d.borrow().get(&deposit_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    // Either side of the deposit may call it off before cash changes hands
+    if caller != deposit.user_principal && caller != deposit.agent_principal {
+        return Err(DepositError::Unauthorized);
+    }
+    
+    if deposit.status != TransactionStatus::Pending {
+        return Err(DepositError::InvalidStatus { status: deposit.status });
+    }
+    
+    deposit.status = TransactionStatus::Cancelled;
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(deposit.expires_at, deposit_id)));
+    release_volumes(&deposit);
+    store_deposit(&deposit);
+    audit::record(
+        caller,
+        AuditAction::DepositCancelled,
+        AuditEntity::Deposit { id: deposit_id },
+        Transition::status(Some(&TransactionStatus::Pending), &deposit.status),
+        ic_cdk::api::time(),
+    );
+    
+    Ok(deposit)
+}
 
-fn update_agent_balance(agent: Principal, deposit_amount: u64, commission: u64) {
-    AGENT_BALANCES.with(|balances| {
-        let mut bals = balances.borrow_mut();
-        let balance = bals.entry(agent).or_insert(AgentBalance {
-            principal: agent,
-            total_deposits: 0,
-            total_commission_owed: 0,
-            total_commission_paid: 0,
-            last_settlement_date: None,
-        });
-        
-        balance.total_deposits += deposit_amount;
-        balance.total_commission_owed += commission;
+/// Write a deposit, moving its agent's reputation tally along with it.
+fn store_deposit(deposit: &DepositTransaction) {
+    let before = DEPOSITS.with(|d| d.borrow_mut().insert(deposit.id, deposit.clone()));
+    let disputed = DISPUTES.with(|d| d.borrow().contains_key(&deposit.id));
+    let before = before.map(|b| reputation::outcome(&b, disputed));
+    let after = reputation::outcome(deposit, disputed);
+    update_tally(deposit.agent_principal, |t| t.update_request(before.as_ref(), &after));
+}
+
+fn update_tally(agent: Principal, update: impl FnOnce(&mut Tally)) {
+    AGENT_TALLIES.with(|t| {
+        let mut tallies = t.borrow_mut();
+        let mut tally = tallies.get(&agent).unwrap_or_default();
+        update(&mut tally);
+        tallies.insert(agent, tally);
     });
 }
 
+/// Stamp a deposit as confirmed and index it for its settlement period.
+fn mark_confirmed(deposit: &mut DepositTransaction, now: u64) {
+    deposit.status = TransactionStatus::Confirmed;
+    deposit.confirmed_at = Some(now);
+    CONFIRMED_DEPOSITS.with(|c| c.borrow_mut().insert((now, deposit.id), ()));
+}
+
+// ============================================================================
+// DISPUTES
+// ============================================================================
+
+const MAX_DISPUTE_REASON_LEN: usize = 280;
+const MAX_DISPUTE_NOTE_LEN: usize = 2000;
+
+fn require_company_wallet() -> Result<Principal, DepositError> {
+    let caller = ic_cdk::api::msg_caller();
+    let company = get_company_wallet().map_err(|reason| DepositError::Misconfigured { reason })?;
+    
+    if caller != company {
+        return Err(DepositError::Unauthorized);
+    }
+    
+    Ok(caller)
+}
+
+fn validate_dispute_reason(reason: &str) -> Result<(), DepositError> {
+    if reason.trim().is_empty() {
+        return Err(DepositError::InvalidInput { reason: "Dispute reason is required".to_string() });
+    }
+    
+    if reason.chars().count() > MAX_DISPUTE_REASON_LEN {
+        return Err(DepositError::InvalidInput {
+            reason: format!("Dispute reason exceeds {} characters", MAX_DISPUTE_REASON_LEN),
+        });
+    }
+    
+    Ok(())
+}
+
+fn validate_dispute_note(note: &str) -> Result<(), DepositError> {
+    if note.chars().count() > MAX_DISPUTE_NOTE_LEN {
+        return Err(DepositError::InvalidInput {
+            reason: format!("Note exceeds {} characters", MAX_DISPUTE_NOTE_LEN),
+        });
+    }
+    
+    Ok(())
+}
+
+/// Freeze a deposit while the company investigates a complaint.
+/// Pending, confirmed and expired deposits can all be disputed.
+#[update]
+fn open_dispute(deposit_id: u64, reason: String, evidence_note: String) -> Result<DepositDispute, DepositError> {
+    let opened_by = require_company_wallet()?;
+    validate_dispute_reason(&reason)?;
+    validate_dispute_note(&evidence_note)?;
+    
+    let mut deposit = DEPOSITS.with(|d| d.borrow().get(&deposit_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    match deposit.status {
+        TransactionStatus::Pending | TransactionStatus::Confirmed | TransactionStatus::Expired => {}
+        status => return Err(DepositError::InvalidStatus { status }),
+    }
+    
+    // Keep the original dispute record intact: a deposit is disputed at most once
+    if DISPUTES.with(|d| d.borrow().contains_key(&deposit_id)) {
+        return Err(DepositError::InvalidInput { reason: "Deposit was already disputed".to_string() });
+    }
+    
+    let now = ic_cdk::api::time();
+    let dispute = DepositDispute {
+        deposit_id,
+        reason,
+        evidence_note,
+        opened_by,
+        opened_at: now,
+        status_before_dispute: deposit.status.clone(),
+        outcome: None,
+        resolution_note: None,
+        resolved_by: None,
+        resolved_at: None,
+        unrecovered: None,
+    };
+    
+    // A disputed code must not lapse while under review
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(deposit.expires_at, deposit_id)));
+    deposit.status = TransactionStatus::Disputed;
+    audit::record(
+        opened_by,
+        AuditAction::DisputeOpened,
+        AuditEntity::Deposit { id: deposit_id },
+        Transition::status(Some(&dispute.status_before_dispute), &deposit.status).with_note(dispute.reason.clone()),
+        now,
+    );
+    store_deposit(&deposit);
+    DISPUTES.with(|d| d.borrow_mut().insert(deposit_id, dispute.clone()));
+    
+    Ok(dispute)
+}
+
+/// Close a dispute, settling the deposit as confirmed or cancelled and
+/// applying or reversing its effect on the agent's balance.
+#[update]
+fn resolve_dispute(
+    deposit_id: u64,
+    outcome: DisputeOutcome,
+    resolution_note: String,
+) -> Result<DepositDispute, DepositError> {
+    let resolved_by = require_company_wallet()?;
+    validate_dispute_note(&resolution_note)?;
+    
+    let mut deposit = DEPOSITS.with(|d| d.borrow().get(&deposit_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    if deposit.status != TransactionStatus::Disputed {
+        return Err(DepositError::InvalidStatus { status: deposit.status });
+    }
+    
+    let mut dispute = DISPUTES.with(|d| d.borrow().get(&deposit_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    let now = ic_cdk::api::time();
+    let unrecovered = settle_disputed_deposit(&mut deposit, &dispute.status_before_dispute, &outcome, now);
+    
+    dispute.unrecovered = (unrecovered > 0).then_some(unrecovered);
+    dispute.outcome = Some(outcome);
+    dispute.resolution_note = Some(resolution_note);
+    dispute.resolved_by = Some(resolved_by);
+    dispute.resolved_at = Some(now);
+    
+    let mut transition = Transition::status(Some(&TransactionStatus::Disputed), &deposit.status);
+    if unrecovered > 0 {
+        transition = transition.with_note(format!("Unrecovered credit: {}", unrecovered));
+    }
+    audit::record(resolved_by, AuditAction::DisputeResolved, AuditEntity::Deposit { id: deposit_id }, transition, now);
+    
+    store_deposit(&deposit);
+    DISPUTES.with(|d| d.borrow_mut().insert(deposit_id, dispute.clone()));
+    
+    Ok(dispute)
+}
+
+/// Move a disputed deposit to its final status, applying or reversing the
+/// ledger, agent balance and velocity effects implied by its status before
+/// the dispute. Returns any credit that could not be clawed back.
+fn settle_disputed_deposit(
+    deposit: &mut DepositTransaction,
+    previous: &TransactionStatus,
+    outcome: &DisputeOutcome,
+    now: u64,
+) -> u64 {
+    let mut unrecovered = 0;
+    
+    deposit.status = match outcome {
+        DisputeOutcome::Confirmed => {
+            if *previous != TransactionStatus::Confirmed {
+                mark_confirmed(deposit, now);
+                credit_user(deposit, now);
+                update_agent_balance(deposit);
+            }
+            TransactionStatus::Confirmed
+        }
+        DisputeOutcome::Cancelled => {
+            if *previous == TransactionStatus::Confirmed {
+                unrecovered = debit_user(deposit, now);
+                reverse_agent_balance(deposit);
+                if let Some(confirmed_at) = deposit.confirmed_at {
+                    CONFIRMED_DEPOSITS.with(|c| c.borrow_mut().remove(&(confirmed_at, deposit.id)));
+                }
+            }
+            // Expired deposits already gave back their velocity allowance
+            if *previous != TransactionStatus::Expired {
+                release_volumes(deposit);
+            }
+            TransactionStatus::Cancelled
+        }
+    };
+    
+    unrecovered
+}
+
 #[query]
-fn get_agent_balance(agent: Principal) -> Option<AgentBalance> {
-    AGENT_BALANCES.with(|balances| {
-        balances.borrow().get(&agent).cloned()
+fn get_dispute(deposit_id: u64) -> Result<Option<DepositDispute>, DepositError> {
+    let deposit = DEPOSITS.with(|d| d.borrow().get(&deposit_id)).ok_or(DepositError::NotFound)?;
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[deposit.user_principal, deposit.agent_principal])?;
+    
+    Ok(DISPUTES.with(|d| d.borrow().get(&deposit_id)))
+}
+
+#[query]
+fn get_open_disputes() -> Result<Vec<DepositDispute>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    
+    Ok(DISPUTES.with(|d| {
+        d.borrow()
+            .iter()
+            .map(|(_, dispute)| dispute)
+            .filter(|dispute| dispute.outcome.is_none())
+            .collect()
+    }))
+}
+
+fn find_deposit_id_by_code(input: &str) -> Result<u64, DepositError> {
+    // Exact match first so codes issued before schema v2 still resolve
+    if let Some(id) = DEPOSIT_CODES.with(|c| c.borrow().get(&input.to_string())) {
+        return Ok(id);
+    }
+    
+    let code = normalize_deposit_code(input)
+        .ok_or(DepositError::InvalidCode)?;
+    
+    DEPOSIT_CODES.with(|c| c.borrow().get(&code))
+        .ok_or(DepositError::NotFound)
+}
+
+// ============================================================================
+// FRAUD REVIEW
+// ============================================================================
+
+/// Run the fraud rules against a deposit request. When an existing deposit
+/// is re-screened it is left out of the user's history.
+fn screen_deposit(config: &DepositConfig, subject: &AlertSubject, now: u64) -> Vec<RuleHit> {
+    let (Some(rules), Ok(currency_config)) = (&config.fraud, config.currency(subject.currency)) else {
+        return vec![];
+    };
+    
+    let lookback = rules.window_hours.max(rules.new_account_hours).saturating_mul(NANOS_PER_HOUR);
+    let key = |timestamp, id| PartyDepositKey { party: subject.user, timestamp, id };
+    let other = |k: &PartyDepositKey| Some(k.id) != subject.request_id;
+    
+    let (first_seen, ids) = USER_DEPOSITS.with(|i| {
+        let i = i.borrow();
+        let first_seen = i.range(key(0, 0)..=key(u64::MAX, u64::MAX))
+            .map(|(k, _)| k)
+            .find(other)
+            .map(|k| k.timestamp);
+        let ids: Vec<u64> = i.range(key(now.saturating_sub(lookback), 0)..=key(u64::MAX, u64::MAX))
+            .map(|(k, _)| k)
+            .filter(other)
+            .map(|k| k.id)
+            .collect();
+        (first_seen, ids)
+    });
+    
+    let history: Vec<fraud::PastRequest> = DEPOSITS.with(|d| {
+        let deps = d.borrow();
+        ids.iter()
+            .filter_map(|id| deps.get(id))
+            .map(|d| fraud::PastRequest {
+                timestamp: d.timestamp,
+                currency: d.currency,
+                amount: d.amount,
+                live: !matches!(d.status, TransactionStatus::Cancelled | TransactionStatus::Expired),
+            })
+            .collect()
+    });
+    
+    fraud::screen(rules, &fraud::Screening {
+        currency: subject.currency,
+        amount: subject.amount,
+        max_amount: currency_config.max_deposit,
+        first_seen,
+        history: &history,
+        agent_deposits: 0,
+        now,
     })
 }
 
-#[query]
-fn get_all_agent_balances() -> Vec<AgentBalance> {
-    AGENT_BALANCES.with(|balances| {
-        balances.borrow().values().cloned().collect()
+/// Re-screen a deposit the agent is confirming. A deposit already cleared by
+/// staff is not screened again.
+fn screen_confirmation(actor: Principal, deposit: &DepositTransaction, now: u64) -> Result<(), DepositError> {
+    let latest = latest_alert(deposit.id);
+    match &latest {
+        Some(alert) if alert.is_holding() => return Err(DepositError::HeldForReview { alert_id: alert.id }),
+        Some(alert) if alert.status == AlertStatus::Cleared => return Ok(()),
+        _ => {}
+    }
+    
+    let subject = AlertSubject {
+        request_id: Some(deposit.id),
+        user: deposit.user_principal,
+        agent: deposit.agent_principal,
+        currency: deposit.currency,
+        amount: deposit.amount,
+    };
+    let hits = screen_deposit(&current_config().deposit, &subject, now);
+    let Some(action) = fraud::strongest(&hits) else {
+        return Ok(());
+    };
+    
+    if action == FraudAction::Flag {
+        // Still queued from creation; one open alert per deposit is enough
+        if !latest.is_some_and(|alert| alert.status == AlertStatus::Open) {
+            raise_alert(actor, subject, ScreeningStage::Confirm, action, hits, now);
+        }
+        return Ok(());
+    }
+    
+    // The cash may already have changed hands, so a block holds the deposit instead
+    let alert = raise_alert(actor, subject, ScreeningStage::Confirm, FraudAction::Hold, hits, now);
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(deposit.expires_at, deposit.id)));
+    Err(DepositError::HeldForReview { alert_id: alert.id })
+}
+
+fn raise_alert(
+    actor: Principal,
+    subject: AlertSubject,
+    stage: ScreeningStage,
+    action: FraudAction,
+    hits: Vec<RuleHit>,
+    now: u64,
+) -> FraudAlert {
+    let rules: Vec<String> = hits.iter().map(|hit| format!("{:?}", hit.rule)).collect();
+    let alert = FRAUD_ALERTS.with(|a| {
+        DEPOSIT_ALERTS.with(|d| fraud::raise_alert(&mut a.borrow_mut(), &mut d.borrow_mut(), subject, stage, action, hits, now))
+    });
+    
+    audit::record(
+        actor,
+        AuditAction::FraudAlertRaised,
+        AuditEntity::FraudAlert { id: alert.id },
+        Transition::status(None, &action).with_note(rules.join(", ")),
+        now,
+    );
+    
+    alert
+}
+
+fn latest_alert(deposit_id: u64) -> Option<FraudAlert> {
+    FRAUD_ALERTS.with(|a| DEPOSIT_ALERTS.with(|d| fraud::latest_alert(&a.borrow(), &d.borrow(), deposit_id)))
+}
+
+/// Close an alert from the review queue. Clearing a hold lets the deposit be
+/// confirmed, with a fresh code validity window; rejecting cancels the
+/// deposit if it is still pending. Confirmed deposits are reversed through
+/// `open_dispute` instead.
+#[update]
+fn review_fraud_alert(alert_id: u64, decision: ReviewDecision, note: String) -> Result<FraudAlert, DepositError> {
+    let reviewer = require_company_wallet()?;
+    validate_dispute_note(&note)?;
+    
+    review_alert(alert_id, decision, note, reviewer, ic_cdk::api::time())
+}
+
+fn review_alert(
+    alert_id: u64,
+    decision: ReviewDecision,
+    note: String,
+    reviewer: Principal,
+    now: u64,
+) -> Result<FraudAlert, DepositError> {
+    let mut alert = FRAUD_ALERTS.with(|a| a.borrow().get(&alert_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    if alert.status != AlertStatus::Open {
+        return Err(DepositError::InvalidInput { reason: "Alert was already reviewed".to_string() });
+    }
+    
+    let was_holding = alert.is_holding();
+    alert.status = match decision {
+        ReviewDecision::Clear => AlertStatus::Cleared,
+        ReviewDecision::Reject => AlertStatus::Rejected,
+    };
+    alert.reviewed_by = Some(reviewer);
+    alert.reviewed_at = Some(now);
+    alert.review_note = Some(note);
+    
+    let pending = alert.subject.request_id
+        .and_then(|id| DEPOSITS.with(|d| d.borrow().get(&id)))
+        .filter(|deposit| deposit.status == TransactionStatus::Pending);
+    
+    if let Some(mut deposit) = pending {
+        match decision {
+            ReviewDecision::Clear if was_holding => {
+                // The code may have lapsed while under review
+                deposit.expires_at = now + current_config().deposit.code_validity_hours * NANOS_PER_HOUR;
+                DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().insert((deposit.expires_at, deposit.id), ()));
+                store_deposit(&deposit);
+            }
+            ReviewDecision::Clear => {}
+            ReviewDecision::Reject => {
+                deposit.status = TransactionStatus::Cancelled;
+                DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(deposit.expires_at, deposit.id)));
+                release_volumes(&deposit);
+                audit::record(
+                    reviewer,
+                    AuditAction::DepositCancelled,
+                    AuditEntity::Deposit { id: deposit.id },
+                    Transition::status(Some(&TransactionStatus::Pending), &deposit.status)
+                        .with_note(format!("Rejected in fraud review of alert {}", alert_id)),
+                    now,
+                );
+                store_deposit(&deposit);
+            }
+        }
+    }
+    
+    FRAUD_ALERTS.with(|a| a.borrow_mut().insert(alert_id, alert.clone()));
+    audit::record(
+        reviewer,
+        AuditAction::FraudAlertReviewed,
+        AuditEntity::FraudAlert { id: alert_id },
+        Transition::status(Some(&AlertStatus::Open), &alert.status),
+        now,
+    );
+    
+    Ok(alert)
+}
+
+#[query]
+fn get_fraud_alert(alert_id: u64) -> Result<FraudAlert, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    FRAUD_ALERTS.with(|a| a.borrow().get(&alert_id)).ok_or(DepositError::NotFound)
+}
+
+/// The review queue: alerts not yet reviewed, oldest first.
+#[query]
+fn get_open_fraud_alerts() -> Result<Vec<FraudAlert>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    
+    Ok(FRAUD_ALERTS.with(|a| {
+        a.borrow()
+            .iter()
+            .map(|(_, alert)| alert)
+            .filter(|alert| alert.status == AlertStatus::Open)
+            .collect()
+    }))
+}
+
+// ============================================================================
+// AGENT REPUTATION
+// ============================================================================
+
+/// Rate the agent that handled a confirmed deposit, once per deposit.
+#[update]
+fn rate_agent(deposit_id: u64, stars: u8, comment: Option<String>) -> Result<AgentRating, DepositError> {
+    let caller = ic_cdk::api::msg_caller();
+    submit_rating(caller, deposit_id, stars, comment, ic_cdk::api::time())
+}
+
+fn submit_rating(
+    user: Principal,
+    deposit_id: u64,
+    stars: u8,
+    comment: Option<String>,
+    now: u64,
+) -> Result<AgentRating, DepositError> {
+    reputation::validate_rating(stars, comment.as_deref())
+        .map_err(|reason| DepositError::InvalidInput { reason })?;
+    
+    let deposit = DEPOSITS.with(|d| d.borrow().get(&deposit_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    if user != deposit.user_principal {
+        return Err(DepositError::Unauthorized);
+    }
+    
+    if deposit.status != TransactionStatus::Confirmed {
+        return Err(DepositError::InvalidStatus { status: deposit.status });
+    }
+    
+    if AGENT_RATINGS.with(|r| r.borrow().contains_key(&deposit_id)) {
+        return Err(DepositError::InvalidInput { reason: "Deposit was already rated".to_string() });
+    }
+    
+    let rating = AgentRating {
+        deposit_id,
+        agent: deposit.agent_principal,
+        user,
+        stars,
+        comment: comment.filter(|c| !c.trim().is_empty()),
+        rated_at: now,
+    };
+    AGENT_RATINGS.with(|r| r.borrow_mut().insert(deposit_id, rating.clone()));
+    update_tally(rating.agent, |t| t.add_rating(stars));
+    
+    Ok(rating)
+}
+
+/// Public so users can compare agents before choosing one.
+#[query]
+fn get_agent_reputation(agent: Principal) -> AgentReputation {
+    agent_reputation(agent)
+}
+
+fn agent_reputation(agent: Principal) -> AgentReputation {
+    AGENT_TALLIES.with(|t| t.borrow().get(&agent).unwrap_or_default().finish(agent))
+}
+
+// ============================================================================
+// FIAT LEDGER
+// ============================================================================
+
+/// Digital balance a confirmed deposit is worth to the user.
+fn user_credit(deposit: &DepositTransaction) -> u64 {
+    deposit.amount
+        .saturating_sub(deposit.platform_fee)
+        .saturating_sub(deposit.agent_commission)
+}
+
+fn credit_user(deposit: &DepositTransaction, now: u64) -> u64 {
+    ledger::mint(
+        Account::of(deposit.user_principal),
+        deposit.currency,
+        user_credit(deposit),
+        Some(deposit.id.to_be_bytes().to_vec()),
+        now,
+    )
+}
+
+/// Claw back a deposit's credit. Returns the part the user had already spent.
+fn debit_user(deposit: &DepositTransaction, now: u64) -> u64 {
+    let credit = user_credit(deposit);
+    let (_, burned) = ledger::burn(
+        Account::of(deposit.user_principal),
+        deposit.currency,
+        credit,
+        Some(deposit.id.to_be_bytes().to_vec()),
+        now,
+    );
+    credit - burned
+}
+
+#[query]
+fn balance_of(account: Account, currency: Currency) -> Result<u64, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[account.owner])?;
+    Ok(ledger::balance_of(&account, currency))
+}
+
+#[update]
+fn transfer(args: TransferArgs) -> Result<u64, TransferError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    if caller == Principal::anonymous() {
+        return Err(TransferError::AnonymousCaller);
+    }
+    
+    let from = Account { owner: caller, subaccount: args.from_subaccount };
+    ledger::transfer(from, args.to, args.currency, args.amount, args.memo, ic_cdk::api::time())
+}
+
+/// Let `spender` move up to `amount` from the caller's account (ICRC-2).
+/// Replaces any existing allowance for the same spender.
+#[update]
+fn approve(args: ApproveArgs) -> Result<u64, TransferError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    if caller == Principal::anonymous() {
+        return Err(TransferError::AnonymousCaller);
+    }
+    
+    let from = Account { owner: caller, subaccount: args.from_subaccount };
+    ledger::approve(from, args.spender, args.currency, args.amount, args.expires_at, args.memo, ic_cdk::api::time())
+}
+
+/// Move funds out of `from` using an allowance granted to the caller (ICRC-2).
+#[update]
+fn transfer_from(args: TransferFromArgs) -> Result<u64, TransferError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    if caller == Principal::anonymous() {
+        return Err(TransferError::AnonymousCaller);
+    }
+    
+    let spender = Account { owner: caller, subaccount: args.spender_subaccount };
+    ledger::transfer_from(spender, args.from, args.to, args.currency, args.amount, args.memo, ic_cdk::api::time())
+}
+
+#[query]
+fn allowance(account: Account, spender: Account, currency: Currency) -> Result<Allowance, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[account.owner, spender.owner])?;
+    Ok(ledger::allowance(&account, &spender, currency, ic_cdk::api::time()))
+}
+
+/// Ledger history for an account, newest first. Pass the lowest block index
+/// of the previous page as `before` to continue.
+#[query]
+fn get_account_transactions(
+    account: Account,
+    currency: Currency,
+    before: Option<u64>,
+    limit: u64,
+) -> Result<Vec<LedgerBlock>, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[account.owner])?;
+    Ok(ledger::account_history(&account, currency, before, limit))
+}
+
+// ============================================================================
+// EXPIRY
+// ============================================================================
+
+/// Move every pending deposit whose code has lapsed to `Expired`.
+/// Returns the number of deposits expired.
+fn expire_stale_deposits(actor: Principal, now: u64) -> usize {
+    let due: Vec<(u64, u64)> = DEPOSIT_EXPIRIES.with(|e| {
+        e.borrow()
+            .range(..=(now, u64::MAX))
+            .map(|(key, _)| key)
+            .collect()
+    });
+    
+    due.iter()
+        .filter(|(_, id)| expire_deposit(*id, actor, now))
+        .count()
+}
+
+/// Expire a single pending deposit and give back its velocity allowance.
+fn expire_deposit(deposit_id: u64, actor: Principal, now: u64) -> bool {
+    let Some(mut deposit) = DEPOSITS.with(|d| d.borrow().get(&deposit_id)) else {
+        return false;
+    };
+    
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(deposit.expires_at, deposit_id)));
+    
+    if deposit.status != TransactionStatus::Pending {
+        return false;
+    }
+    
+    deposit.status = TransactionStatus::Expired;
+    release_volumes(&deposit);
+    store_deposit(&deposit);
+    audit::record(
+        actor,
+        AuditAction::DepositExpired,
+        AuditEntity::Deposit { id: deposit_id },
+        Transition::status(Some(&TransactionStatus::Pending), &TransactionStatus::Expired),
+        now,
+    );
+    true
+}
+
+// ============================================================================
+// LIMITS & VELOCITY
+// ============================================================================
+
+const NANOS_PER_HOUR: u64 = 3_600 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+fn day_index(timestamp_nanos: u64) -> u64 {
+    timestamp_nanos / NANOS_PER_DAY
+}
+
+impl VolumeWindow {
+    /// Reset whichever buckets have elapsed by `day`.
+    fn rolled_to(self, day: u64) -> Self {
+        let week = day / 7;
+        VolumeWindow {
+            day,
+            day_total: if self.day == day { self.day_total } else { 0 },
+            week,
+            week_total: if self.week == week { self.week_total } else { 0 },
+        }
+    }
+    
+    fn with_amount(self, amount: u64) -> Self {
+        VolumeWindow {
+            day_total: self.day_total.saturating_add(amount),
+            week_total: self.week_total.saturating_add(amount),
+            ..self
+        }
+    }
+    
+    /// Undo `with_amount` for volume recorded on `day`, if its buckets are still current.
+    fn without_amount(self, amount: u64, day: u64) -> Self {
+        VolumeWindow {
+            day_total: if self.day == day {
+                self.day_total.saturating_sub(amount)
+            } else {
+                self.day_total
+            },
+            week_total: if self.week == day / 7 {
+                self.week_total.saturating_sub(amount)
+            } else {
+                self.week_total
+            },
+            ..self
+        }
+    }
+}
+
+type VolumeMap = std::thread::LocalKey<RefCell<StableBTreeMap<VolumeKey, VolumeWindow, Memory>>>;
+
+/// Give back the velocity allowance a deposit consumed for its user and agent.
+fn release_volumes(deposit: &DepositTransaction) {
+    release_volume(&USER_VOLUMES, deposit.user_principal, deposit);
+    release_volume(&AGENT_VOLUMES, deposit.agent_principal, deposit);
+}
+
+fn release_volume(volumes: &'static VolumeMap, owner: Principal, deposit: &DepositTransaction) {
+    let key = VolumeKey { owner, currency: deposit.currency };
+    volumes.with(|v| {
+        let mut vols = v.borrow_mut();
+        if let Some(window) = vols.get(&key) {
+            vols.insert(key, window.without_amount(deposit.amount, day_index(deposit.timestamp)));
+        }
+    });
+}
+
+fn check_amount_bounds(amount: u64, config: &DepositCurrencyConfig) -> Result<(), DepositError> {
+    if amount == 0 {
+        return Err(DepositError::InvalidAmount);
+    }
+    
+    if amount < config.min_deposit {
+        return Err(DepositError::BelowMinimum { min: config.min_deposit });
+    }
+    
+    if amount > config.max_deposit {
+        return Err(DepositError::AboveMaximum { max: config.max_deposit });
+    }
+    
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum CapPeriod {
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, PartialEq)]
+struct CapExceeded {
+    period: CapPeriod,
+    limit: u64,
+    remaining: u64,
+}
+
+/// Check `amount` against a daily and weekly cap (0 = unlimited).
+fn check_velocity(
+    window: &VolumeWindow,
+    amount: u64,
+    daily_limit: u64,
+    weekly_limit: u64,
+) -> Result<(), CapExceeded> {
+    if daily_limit > 0 && window.day_total.saturating_add(amount) > daily_limit {
+        return Err(CapExceeded {
+            period: CapPeriod::Daily,
+            limit: daily_limit,
+            remaining: daily_limit.saturating_sub(window.day_total),
+        });
+    }
+    
+    if weekly_limit > 0 && window.week_total.saturating_add(amount) > weekly_limit {
+        return Err(CapExceeded {
+            period: CapPeriod::Weekly,
+            limit: weekly_limit,
+            remaining: weekly_limit.saturating_sub(window.week_total),
+        });
+    }
+    
+    Ok(())
+}
+
+// ============================================================================
+// AGENT BALANCE MANAGEMENT
+// ============================================================================
+
+/// Split a deposit amount into (platform fee, agent commission).
+fn calculate_fees(amount: u64, config: &DepositCurrencyConfig) -> (u64, u64) {
+    let platform_fee = (amount * config.platform_fee_basis_points) / 10000;
+    let agent_commission = (amount * config.agent_commission_basis_points) / 10000;
+    (platform_fee, agent_commission)
+}
+
+impl AgentBalance {
+    fn new(principal: Principal) -> Self {
+        AgentBalance { principal, currencies: vec![], last_settlement_date: None }
+    }
+    
+    /// Totals in `currency`, added on first use.
+    fn in_currency_mut(&mut self, currency: Currency) -> &mut CurrencyBalance {
+        let index = match self.currencies.iter().position(|b| b.currency == currency) {
+            Some(index) => index,
+            None => {
+                self.currencies.push(CurrencyBalance::zero(currency));
+                self.currencies.len() - 1
+            }
+        };
+        &mut self.currencies[index]
+    }
+}
+
+impl CurrencyBalance {
+    fn zero(currency: Currency) -> Self {
+        CurrencyBalance {
+            currency,
+            total_deposits: 0,
+            total_platform_fees: 0,
+            total_commission_owed: 0,
+            total_commission_paid: 0,
+        }
+    }
+}
+
+fn update_agent_balance(deposit: &DepositTransaction) {
+    let agent = deposit.agent_principal;
+    AGENT_BALANCES.with(|balances| {
+        let mut bals = balances.borrow_mut();
+        let mut balance = bals.get(&agent).unwrap_or_else(|| AgentBalance::new(agent));
+        
+        let totals = balance.in_currency_mut(deposit.currency);
+        totals.total_deposits += deposit.amount;
+        totals.total_platform_fees += deposit.platform_fee;
+        totals.total_commission_owed += deposit.agent_commission;
+        bals.insert(agent, balance);
+    });
+}
+
+fn reverse_agent_balance(deposit: &DepositTransaction) {
+    let agent = deposit.agent_principal;
+    AGENT_BALANCES.with(|balances| {
+        let mut bals = balances.borrow_mut();
+        if let Some(mut balance) = bals.get(&agent) {
+            let totals = balance.in_currency_mut(deposit.currency);
+            totals.total_deposits = totals.total_deposits.saturating_sub(deposit.amount);
+            totals.total_platform_fees = totals.total_platform_fees.saturating_sub(deposit.platform_fee);
+            totals.total_commission_owed = totals.total_commission_owed.saturating_sub(deposit.agent_commission);
+            bals.insert(agent, balance);
+        }
+    });
+}
+
+#[query]
+fn get_agent_balance(agent: Principal) -> Result<Option<AgentBalance>, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[agent])?;
+    
+    Ok(AGENT_BALANCES.with(|balances| {
+        balances.borrow().get(&agent)
+    }))
+}
+
+#[query]
+fn get_all_agent_balances() -> Result<Vec<AgentBalance>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    
+    Ok(AGENT_BALANCES.with(|balances| {
+        balances.borrow().iter().map(|(_, b)| b).collect()
+    }))
+}
+
+// ============================================================================
+// SETTLEMENT MANAGEMENT
+// ============================================================================
+
+/// Generate (or refresh) settlements for a finished calendar month.
+///
+/// Commission is computed only from deposits confirmed inside the month, so
+/// running this again for the same month never double-books an agent: unpaid
+/// settlements are recomputed in place and paid ones are left untouched.
+/// Once the month has been closed with `close_period`, the stored settlements
+/// are returned unchanged.
+#[update]
+fn create_monthly_settlement(month: String) -> Result<Vec<MonthlySettlement>, DepositError> {
+    // Only company wallet can create settlements
+    let caller = require_company_wallet()?;
+    
+    generate_settlements(&month, caller, ic_cdk::api::time())
+}
+
+/// Lock a month's settlement figures. Requires settlements to have been generated.
+#[update]
+fn close_period(month: String) -> Result<SettlementPeriod, DepositError> {
+    let caller = require_company_wallet()?;
+    
+    close_settlement_period(&month, caller, ic_cdk::api::time())
+}
+
+#[query]
+fn get_settlement_period(month: String) -> Result<Option<SettlementPeriod>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    Ok(SETTLEMENT_PERIODS.with(|p| p.borrow().get(&month)))
+}
+
+fn parse_period(month: &str) -> Result<Month, DepositError> {
+    Month::parse(month).ok_or_else(|| DepositError::InvalidMonth { month: month.to_string() })
+}
+
+fn generate_settlements(month: &str, actor: Principal, now: u64) -> Result<Vec<MonthlySettlement>, DepositError> {
+    let period = parse_period(month)?;
+    
+    if now < period.end_nanos() {
+        return Err(DepositError::PeriodNotEnded { month: month.to_string() });
+    }
+    
+    let existing = SETTLEMENT_PERIODS.with(|p| p.borrow().get(&month.to_string()));
+    if existing.as_ref().is_some_and(|p| p.closed_at.is_some()) {
+        return Ok(settlements_for_month(month));
+    }
+    
+    let commissions = period_commissions(period.start_nanos(), period.end_nanos());
+    
+    // Agents already settled for this month, plus agents with commission in
+    // it, once per currency
+    let mut parties: Vec<(Principal, Currency)> = settlements_for_month(month)
+        .into_iter()
+        .map(|s| (s.agent_principal, s.currency))
+        .collect();
+    parties.extend(commissions.keys().copied());
+    parties.sort();
+    parties.dedup();
+    
+    for (agent, currency) in parties {
+        let total_commission = commissions.get(&(agent, currency)).copied().unwrap_or(0);
+        let key = SettlementKey { month: month.to_string(), agent, currency };
+        
+        match SETTLEMENT_INDEX.with(|i| i.borrow().get(&key)) {
+            Some(settlement_id) => {
+                let previous = SETTLEMENTS.with(|settlements| {
+                    let mut setts = settlements.borrow_mut();
+                    let mut settlement = setts.get(&settlement_id)?;
+                    if settlement.paid || payout_in_flight(&settlement) || settlement.total_commission == total_commission {
+                        return None;
+                    }
+                    let previous = settlement.total_commission;
+                    settlement.total_commission = total_commission;
+                    setts.insert(settlement_id, settlement);
+                    Some(previous)
+                });
+                if let Some(previous) = previous {
+                    audit::record(
+                        actor,
+                        AuditAction::SettlementRecomputed,
+                        AuditEntity::Settlement { id: settlement_id },
+                        Transition::new(Some(previous.to_string()), Some(total_commission.to_string()))
+                            .with_note("Commission recomputed"),
+                        now,
+                    );
+                }
+            }
+            None if total_commission > 0 => {
+                let settlement_id = next_id(&NEXT_SETTLEMENT_ID);
+                SETTLEMENTS.with(|settlements| {
+                    settlements.borrow_mut().insert(settlement_id, MonthlySettlement {
+                        month: month.to_string(),
+                        agent_principal: agent,
+                        currency,
+                        total_commission,
+                        paid: false,
+                        paid_date: None,
+                        payout_block_index: None,
+                        payout: None,
+                    });
+                });
+                SETTLEMENT_INDEX.with(|i| i.borrow_mut().insert(key, settlement_id));
+                audit::record(
+                    actor,
+                    AuditAction::SettlementGenerated,
+                    AuditEntity::Settlement { id: settlement_id },
+                    Transition::new(None, Some(SETTLEMENT_UNPAID.to_string()))
+                        .with_note(format!("Commission: {} {}", total_commission, currency.code())),
+                    now,
+                );
+            }
+            None => {}
+        }
+    }
+    
+    let record = match existing {
+        Some(mut record) => {
+            record.last_generated_at = now;
+            record
+        }
+        None => SettlementPeriod {
+            month: month.to_string(),
+            generated_by: actor,
+            generated_at: now,
+            last_generated_at: now,
+            closed_by: None,
+            closed_at: None,
+        },
+    };
+    SETTLEMENT_PERIODS.with(|p| p.borrow_mut().insert(month.to_string(), record));
+    
+    Ok(settlements_for_month(month))
+}
+
+fn close_settlement_period(month: &str, actor: Principal, now: u64) -> Result<SettlementPeriod, DepositError> {
+    parse_period(month)?;
+    
+    // Settlements must have been generated first
+    let mut record = SETTLEMENT_PERIODS.with(|p| p.borrow().get(&month.to_string()))
+        .ok_or(DepositError::NotFound)?;
+    
+    if record.closed_at.is_some() {
+        return Err(DepositError::PeriodAlreadyClosed { month: month.to_string() });
+    }
+    
+    record.closed_by = Some(actor);
+    record.closed_at = Some(now);
+    SETTLEMENT_PERIODS.with(|p| p.borrow_mut().insert(month.to_string(), record.clone()));
+    audit::record(
+        actor,
+        AuditAction::PeriodClosed,
+        AuditEntity::Period { month: month.to_string() },
+        Transition::new(Some("Open".to_string()), Some("Closed".to_string())),
+        now,
+    );
+    
+    Ok(record)
+}
+
+/// Agent commission per currency from deposits confirmed in `[start, end)`.
+fn period_commissions(start: u64, end: u64) -> BTreeMap<(Principal, Currency), u64> {
+    let ids: Vec<u64> = CONFIRMED_DEPOSITS.with(|c| {
+        c.borrow()
+            .range((start, 0)..(end, 0))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    
+    let mut commissions = BTreeMap::new();
+    DEPOSITS.with(|deposits| {
+        let deps = deposits.borrow();
+        for deposit in ids.iter().filter_map(|id| deps.get(id)) {
+            if deposit.status == TransactionStatus::Confirmed {
+                *commissions.entry((deposit.agent_principal, deposit.currency)).or_insert(0) += deposit.agent_commission;
+            }
+        }
+    });
+    
+    commissions
+}
+
+fn settlement_key(settlement: &MonthlySettlement) -> SettlementKey {
+    SettlementKey {
+        month: settlement.month.clone(),
+        agent: settlement.agent_principal,
+        currency: settlement.currency,
+    }
+}
+
+fn settlements_for_month(month: &str) -> Vec<MonthlySettlement> {
+    // The management canister's empty principal sorts before every agent
+    let start = SettlementKey {
+        month: month.to_string(),
+        agent: Principal::management_canister(),
+        currency: Currency::UGX,
+    };
+    
+    let ids: Vec<u64> = SETTLEMENT_INDEX.with(|i| {
+        i.borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.month == month)
+            .map(|(_, id)| id)
+            .collect()
+    });
+    
+    SETTLEMENTS.with(|settlements| {
+        let setts = settlements.borrow();
+        ids.iter().filter_map(|id| setts.get(id)).collect()
     })
 }
 
-// ============================================================================
-// SETTLEMENT MANAGEMENT
-// ============================================================================
+/// Record a settlement as paid off-chain. Only available when no payout
+/// ledger is configured; otherwise use `pay_settlement`.
+#[update]
+fn mark_settlement_paid(month: String, agent: Principal, currency: Currency) -> Result<(), DepositError> {
+    // Only company wallet can mark as paid
+    let caller = require_company_wallet()?;
+    
+    if current_config().deposit.settlement_payout.is_some() {
+        return Err(DepositError::PaidOnChainOnly);
+    }
+    
+    let settlement_id = find_settlement_id(&month, agent, currency)?;
+    let settlement = SETTLEMENTS.with(|s| s.borrow().get(&settlement_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    if settlement.paid {
+        return Err(DepositError::AlreadyPaid);
+    }
+    
+    if payout_in_flight(&settlement) {
+        return Err(DepositError::PayoutInFlight);
+    }
+    
+    let before = settlement_status(&settlement);
+    let paid = record_settlement_paid(settlement_id, settlement, ic_cdk::api::time());
+    audit_settlement_transition(
+        caller,
+        AuditAction::SettlementPaid,
+        settlement_id,
+        before,
+        settlement_status(&paid),
+        Some("Paid off-chain".to_string()),
+    );
+    Ok(())
+}
 
+/// Pay a settlement with an ICRC-1 transfer from the configured payout ledger.
+///
+/// The settlement is only marked paid once the ledger returns a block index.
+/// If the outcome of a transfer is unknown, calling this again retries with
+/// the same arguments so the ledger's deduplication prevents a double payment.
 #[update]
-fn create_monthly_settlement(month: String) -> Result<Vec<MonthlySettlement>, String> {
-    // Only company wallet can create settlements
-    let caller = ic_cdk::api::msg_caller();
-    let company = get_company_wallet()?;
+async fn pay_settlement(month: String, agent: Principal, currency: Currency) -> Result<MonthlySettlement, DepositError> {
+    let caller = require_company_wallet()?;
     
-    if caller != company {
-        return Err("Only company wallet can create settlements".to_string());
+    let payout_config = current_config().deposit.settlement_payout
+        .ok_or(DepositError::PayoutNotConfigured)?;
+    let ledger = Principal::from_text(&payout_config.ledger)
+        .map_err(|e| DepositError::Misconfigured { reason: format!("Invalid settlement payout ledger: {}", e) })?;
+    
+    let settlement_id = find_settlement_id(&month, agent, currency)?;
+    let before = settlement_status_of(settlement_id);
+    let payout = begin_settlement_payout(settlement_id, ledger, &payout_config, ic_cdk::api::time())?;
+    let in_flight = settlement_status_of(settlement_id);
+    if in_flight != before {
+        audit_settlement_transition(caller, AuditAction::SettlementPayoutStarted, settlement_id, before, in_flight, None);
     }
     
-    let mut new_settlements = Vec::new();
+    let outcome = icrc1::transfer(payout.ledger, payout_transfer_arg(settlement_id, agent, &payout)).await;
     
-    AGENT_BALANCES.with(|balances| {
-        for (agent, balance) in balances.borrow().iter() {
-            if balance.total_commission_owed > balance.total_commission_paid {
-                let outstanding = balance.total_commission_owed - balance.total_commission_paid;
-                
-                let settlement = MonthlySettlement {
-                    month: month.clone(),
-                    agent_principal: *agent,
-                    total_commission: outstanding,
-                    paid: false,
-                    paid_date: None,
-                };
-                
-                new_settlements.push(settlement);
-            }
-        }
-    });
+    let result = finish_settlement_payout(settlement_id, outcome, ic_cdk::api::time());
+    match &result {
+        Ok(settlement) if in_flight != SETTLEMENT_PAID => audit_settlement_transition(
+            caller,
+            AuditAction::SettlementPaid,
+            settlement_id,
+            in_flight,
+            settlement_status(settlement),
+            settlement.payout_block_index.map(|block| format!("Block index: {}", block)),
+        ),
+        Err(DepositError::PayoutFailed { reason }) => audit_settlement_transition(
+            caller,
+            AuditAction::SettlementPayoutFailed,
+            settlement_id,
+            in_flight,
+            settlement_status_of(settlement_id),
+            Some(reason.clone()),
+        ),
+        _ => {}
+    }
     
-    SETTLEMENTS.with(|settlements| {
-        settlements.borrow_mut().extend(new_settlements.clone());
-    });
+    result
+}
+
+const SETTLEMENT_UNPAID: &str = "Unpaid";
+const SETTLEMENT_PAID: &str = "Paid";
+
+/// A settlement's payment state, as recorded in the audit log.
+fn settlement_status(settlement: &MonthlySettlement) -> &'static str {
+    if settlement.paid {
+        return SETTLEMENT_PAID;
+    }
     
-    Ok(new_settlements)
+    match settlement.payout.as_ref().map(|p| &p.status) {
+        Some(PayoutStatus::InFlight) => "PayoutInFlight",
+        Some(PayoutStatus::Failed { .. }) => "PayoutFailed",
+        _ => SETTLEMENT_UNPAID,
+    }
+}
+
+fn settlement_status_of(settlement_id: u64) -> &'static str {
+    SETTLEMENTS.with(|s| s.borrow().get(&settlement_id))
+        .map_or(SETTLEMENT_UNPAID, |settlement| settlement_status(&settlement))
 }
 
+fn audit_settlement_transition(
+    actor: Principal,
+    action: AuditAction,
+    settlement_id: u64,
+    before: &str,
+    after: &str,
+    note: Option<String>,
+) {
+    let transition = Transition { before: Some(before.to_string()), after: Some(after.to_string()), note };
+    audit::record(actor, action, AuditEntity::Settlement { id: settlement_id }, transition, ic_cdk::api::time());
+}
+
+/// Settle a payout stuck `InFlight` after the ledger's deduplication window
+/// has passed, when retrying only returns `TooOld` (controllers only). Look
+/// the transfer up in the ledger history by its memo (the settlement id) and
+/// pass its block index, or `None` if it never landed so it can be paid again.
 #[update]
-fn mark_settlement_paid(month: String, agent: Principal) -> Result<(), String> {
-    // Only company wallet can mark as paid
+fn resolve_settlement_payout(
+    month: String,
+    agent: Principal,
+    currency: Currency,
+    block_index: Option<u64>,
+) -> Result<MonthlySettlement, DepositError> {
     let caller = ic_cdk::api::msg_caller();
-    let company = get_company_wallet()?;
+    require_controller(caller)?;
     
-    if caller != company {
-        return Err("Only company wallet can mark settlements paid".to_string());
+    let settlement_id = find_settlement_id(&month, agent, currency)?;
+    let before = settlement_status_of(settlement_id);
+    let resolved = resolve_payout(settlement_id, block_index, ic_cdk::api::time())?;
+    let action = if resolved.paid { AuditAction::SettlementPaid } else { AuditAction::SettlementPayoutFailed };
+    audit_settlement_transition(
+        caller,
+        action,
+        settlement_id,
+        before,
+        settlement_status(&resolved),
+        Some(match block_index {
+            Some(block) => format!("Resolved against block index {}", block),
+            None => "Resolved as not found on the ledger".to_string(),
+        }),
+    );
+    
+    Ok(resolved)
+}
+
+fn resolve_payout(settlement_id: u64, block_index: Option<u64>, now: u64) -> Result<MonthlySettlement, DepositError> {
+    let settlement = SETTLEMENTS.with(|s| s.borrow().get(&settlement_id))
+        .ok_or(DepositError::NotFound)?;
+    if !payout_in_flight(&settlement) {
+        return Err(DepositError::PayoutNotInFlight);
     }
     
-    SETTLEMENTS.with(|settlements| {
-        let mut setts = settlements.borrow_mut();
-        let settlement = setts.iter_mut()
-            .find(|s| s.month == month && s.agent_principal == agent)
-            .ok_or("Settlement not found".to_string())?;
-        
-        if settlement.paid {
-            return Err("Settlement already paid".to_string());
+    let outcome = match block_index {
+        Some(block_index) => TransferOutcome::Completed { block_index },
+        None => TransferOutcome::Rejected { reason: "Not found on the ledger".to_string() },
+    };
+    match finish_settlement_payout(settlement_id, outcome, now) {
+        Err(DepositError::PayoutFailed { .. }) => SETTLEMENTS.with(|s| s.borrow().get(&settlement_id))
+            .ok_or(DepositError::NotFound),
+        result => result,
+    }
+}
+
+fn find_settlement_id(month: &str, agent: Principal, currency: Currency) -> Result<u64, DepositError> {
+    SETTLEMENT_INDEX
+        .with(|i| i.borrow().get(&SettlementKey { month: month.to_string(), agent, currency }))
+        .ok_or(DepositError::NotFound)
+}
+
+fn payout_in_flight(settlement: &MonthlySettlement) -> bool {
+    settlement.payout.as_ref().is_some_and(|p| p.status == PayoutStatus::InFlight)
+}
+
+/// Convert a commission in `currency` into payout ledger units (rounded down).
+fn settlement_token_amount(
+    commission: u64,
+    currency: Currency,
+    config: &SettlementPayoutConfig,
+) -> Result<u64, DepositError> {
+    let units_per_token = config.units_per_token.get(currency.code()).copied().unwrap_or(0);
+    if units_per_token == 0 {
+        return Err(DepositError::PayoutRateMissing { currency });
+    }
+    
+    let units = commission as u128 * 10u128.pow(config.decimals as u32) / units_per_token as u128;
+    u64::try_from(units).map_err(|_| DepositError::InvalidAmount)
+}
+
+/// Start (or resume) the payout for a settlement and persist it before the
+/// transfer is sent. An attempt still in flight is reused unchanged.
+fn begin_settlement_payout(
+    settlement_id: u64,
+    ledger: Principal,
+    config: &SettlementPayoutConfig,
+    now: u64,
+) -> Result<SettlementPayout, DepositError> {
+    let mut settlement = SETTLEMENTS.with(|s| s.borrow().get(&settlement_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    if settlement.paid {
+        return Err(DepositError::AlreadyPaid);
+    }
+    
+    if let Some(payout) = settlement.payout.as_ref().filter(|p| p.status == PayoutStatus::InFlight) {
+        return Ok(payout.clone());
+    }
+    
+    let amount = settlement_token_amount(settlement.total_commission, settlement.currency, config)?;
+    if amount == 0 {
+        return Err(DepositError::PayoutTooSmall);
+    }
+    
+    let payout = SettlementPayout {
+        ledger,
+        amount,
+        created_at_time: now,
+        status: PayoutStatus::InFlight,
+    };
+    settlement.payout = Some(payout.clone());
+    SETTLEMENTS.with(|s| s.borrow_mut().insert(settlement_id, settlement));
+    
+    Ok(payout)
+}
+
+fn payout_transfer_arg(settlement_id: u64, agent: Principal, payout: &SettlementPayout) -> Icrc1TransferArg {
+    Icrc1TransferArg {
+        from_subaccount: None,
+        to: Icrc1Account { owner: agent, subaccount: None },
+        amount: candid::Nat::from(payout.amount),
+        fee: None,
+        memo: Some(serde_bytes::ByteBuf::from(settlement_id.to_be_bytes().to_vec())),
+        created_at_time: Some(payout.created_at_time),
+    }
+}
+
+fn finish_settlement_payout(
+    settlement_id: u64,
+    outcome: TransferOutcome,
+    now: u64,
+) -> Result<MonthlySettlement, DepositError> {
+    let mut settlement = SETTLEMENTS.with(|s| s.borrow().get(&settlement_id))
+        .ok_or(DepositError::NotFound)?;
+    
+    // A concurrent retry may already have recorded the same transfer
+    if settlement.paid {
+        return Ok(settlement);
+    }
+    
+    let Some(payout) = settlement.payout.as_mut() else {
+        return Err(DepositError::NotFound);
+    };
+    
+    match outcome {
+        TransferOutcome::Completed { block_index } => {
+            payout.status = PayoutStatus::Completed { block_index };
+            settlement.payout_block_index = Some(block_index);
+            Ok(record_settlement_paid(settlement_id, settlement, now))
         }
-        
-        settlement.paid = true;
-        settlement.paid_date = Some(ic_cdk::api::time());
-        
-        // Update agent balance
-        AGENT_BALANCES.with(|balances| {
-            let mut bals = balances.borrow_mut();
-            if let Some(balance) = bals.get_mut(&agent) {
-                balance.total_commission_paid += settlement.total_commission;
-                balance.last_settlement_date = Some(ic_cdk::api::time());
-            }
-        });
-        
-        Ok(())
-    })
+        TransferOutcome::Rejected { reason } => {
+            payout.status = PayoutStatus::Failed { reason: reason.clone() };
+            SETTLEMENTS.with(|s| s.borrow_mut().insert(settlement_id, settlement));
+            Err(DepositError::PayoutFailed { reason })
+        }
+        TransferOutcome::Unknown { reason } => Err(DepositError::PayoutOutcomeUnknown { reason }),
+    }
+}
+
+fn record_settlement_paid(settlement_id: u64, mut settlement: MonthlySettlement, now: u64) -> MonthlySettlement {
+    settlement.paid = true;
+    settlement.paid_date = Some(now);
+    
+    // Update agent balance
+    AGENT_BALANCES.with(|balances| {
+        let mut bals = balances.borrow_mut();
+        if let Some(mut balance) = bals.get(&settlement.agent_principal) {
+            balance.in_currency_mut(settlement.currency).total_commission_paid += settlement.total_commission;
+            balance.last_settlement_date = Some(now);
+            bals.insert(settlement.agent_principal, balance);
+        }
+    });
+    
+    SETTLEMENTS.with(|s| s.borrow_mut().insert(settlement_id, settlement.clone()));
+    settlement
 }
 
 #[query]
-fn get_settlements_for_month(month: String) -> Vec<MonthlySettlement> {
-    SETTLEMENTS.with(|settlements| {
-        settlements.borrow()
-            .iter()
-            .filter(|s| s.month == month)
-            .cloned()
-            .collect()
-    })
+fn get_settlements_for_month(month: String) -> Result<Vec<MonthlySettlement>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    Ok(settlements_for_month(&month))
 }
 
 #[query]
-fn get_agent_settlements(agent: Principal) -> Vec<MonthlySettlement> {
-    SETTLEMENTS.with(|settlements| {
+fn get_agent_settlements(agent: Principal) -> Result<Vec<MonthlySettlement>, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[agent])?;
+    
+    Ok(SETTLEMENTS.with(|settlements| {
         settlements.borrow()
             .iter()
+            .map(|(_, s)| s)
             .filter(|s| s.agent_principal == agent)
-            .cloned()
             .collect()
-    })
+    }))
 }
 
 // ============================================================================
@@ -353,72 +2327,390 @@ fn get_agent_settlements(agent: Principal) -> Vec<MonthlySettlement> {
 // ============================================================================
 
 #[query]
-fn get_deposit(id: u64) -> Option<DepositTransaction> {
-    DEPOSITS.with(|deposits| {
-        deposits.borrow().get(&id).cloned()
-    })
+fn get_deposit(id: u64) -> Result<DepositTransaction, DepositError> {
+    let deposit = DEPOSITS.with(|deposits| {
+        deposits.borrow().get(&id)
+    }).ok_or(DepositError::NotFound)?;
+    
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[deposit.user_principal, deposit.agent_principal])?;
+    Ok(deposit)
 }
 
 #[query]
-fn get_user_deposits(user: Principal) -> Vec<DepositTransaction> {
-    DEPOSITS.with(|deposits| {
-        deposits.borrow()
-            .values()
-            .filter(|d| d.user_principal == user)
-            .cloned()
-            .collect()
-    })
+fn get_user_deposits(user: Principal, query: DepositQuery) -> Result<DepositPage, DepositError> {
+    require_history_reader(ic_cdk::api::msg_caller(), user)?;
+    Ok(query_deposits(&USER_DEPOSITS, user, &query))
 }
 
 #[query]
-fn get_agent_deposits(agent: Principal) -> Vec<DepositTransaction> {
-    DEPOSITS.with(|deposits| {
-        deposits.borrow()
-            .values()
-            .filter(|d| d.agent_principal == agent)
-            .cloned()
-            .collect()
-    })
+fn get_agent_deposits(agent: Principal, query: DepositQuery) -> Result<DepositPage, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[agent])?;
+    Ok(query_deposits(&AGENT_DEPOSITS, agent, &query))
 }
 
 #[query]
-fn get_pending_deposits(agent: Principal) -> Vec<DepositTransaction> {
-    DEPOSITS.with(|deposits| {
-        deposits.borrow()
-            .values()
-            .filter(|d| d.agent_principal == agent && d.status == TransactionStatus::Pending)
-            .cloned()
-            .collect()
-    })
+fn get_pending_deposits(agent: Principal, query: DepositQuery) -> Result<DepositPage, DepositError> {
+    require_party_or_staff(ic_cdk::api::msg_caller(), &[agent])?;
+    let query = DepositQuery { status: Some(TransactionStatus::Pending), ..query };
+    Ok(query_deposits(&AGENT_DEPOSITS, agent, &query))
+}
+
+const DEFAULT_DEPOSIT_PAGE: u64 = 50;
+const MAX_DEPOSIT_PAGE: u64 = 100;
+
+type DepositIndex = std::thread::LocalKey<RefCell<StableBTreeMap<PartyDepositKey, (), Memory>>>;
+
+fn index_deposit(deposit: &DepositTransaction) {
+    let key = |party| PartyDepositKey { party, timestamp: deposit.timestamp, id: deposit.id };
+    USER_DEPOSITS.with(|i| i.borrow_mut().insert(key(deposit.user_principal), ()));
+    AGENT_DEPOSITS.with(|i| i.borrow_mut().insert(key(deposit.agent_principal), ()));
 }
 
+/// Walk one party's slice of a deposit index within the query's time range,
+/// resuming after the cursor, until a page of matching deposits is filled.
+fn query_deposits(index: &'static DepositIndex, party: Principal, query: &DepositQuery) -> DepositPage {
+    let limit = query.limit.unwrap_or(DEFAULT_DEPOSIT_PAGE).clamp(1, MAX_DEPOSIT_PAGE) as usize;
+    let order = query.order.unwrap_or_default();
+    
+    let key = |timestamp, id| PartyDepositKey { party, timestamp, id };
+    let mut lower = key(query.from.unwrap_or(0), 0);
+    let mut upper = key(query.to.unwrap_or(u64::MAX), 0);
+    
+    if let Some(cursor) = query.cursor {
+        match order {
+            SortOrder::NewestFirst => upper = upper.min(key(cursor.timestamp, cursor.id)),
+            SortOrder::OldestFirst => {
+                let after = match cursor.id.checked_add(1) {
+                    Some(id) => key(cursor.timestamp, id),
+                    None => key(cursor.timestamp.saturating_add(1), 0),
+                };
+                lower = lower.max(after);
+            }
+        }
+    }
+    
+    if lower >= upper {
+        return DepositPage { deposits: vec![], next_cursor: None };
+    }
+    
+    let mut deposits: Vec<DepositTransaction> = index.with(|i| {
+        let i = i.borrow();
+        let keys = i.range(lower..upper).map(|(k, _)| k);
+        let keys: Box<dyn Iterator<Item = PartyDepositKey>> = match order {
+            SortOrder::NewestFirst => Box::new(keys.rev()),
+            SortOrder::OldestFirst => Box::new(keys),
+        };
+        
+        DEPOSITS.with(|d| {
+            let deps = d.borrow();
+            keys.filter_map(|k| deps.get(&k.id))
+                .filter(|d| match &query.status {
+                    Some(status) => d.status == *status,
+                    None => true,
+                })
+                .take(limit + 1)
+                .collect()
+        })
+    });
+    
+    let next_cursor = if deposits.len() > limit {
+        deposits.truncate(limit);
+        deposits.last().map(|d| DepositCursor { timestamp: d.timestamp, id: d.id })
+    } else {
+        None
+    };
+    
+    DepositPage { deposits, next_cursor }
+}
+
+/// Platform fees collected on confirmed deposits (AfriTokeni revenue), per currency.
 #[query]
-fn get_total_revenue() -> u64 {
+fn get_total_revenue() -> Result<Vec<CurrencyAmount>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    Ok(currency_amounts(total_platform_fees()))
+}
+
+/// Commission earned by agents on confirmed deposits, paid or not, per currency.
+#[query]
+fn get_total_agent_commissions() -> Result<Vec<CurrencyAmount>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    Ok(currency_amounts(total_agent_commissions()))
+}
+
+fn total_platform_fees() -> BTreeMap<Currency, u64> {
+    sum_agent_balances(|b| b.total_platform_fees)
+}
+
+fn total_agent_commissions() -> BTreeMap<Currency, u64> {
+    sum_agent_balances(|b| b.total_commission_owed)
+}
+
+fn sum_agent_balances(field: impl Fn(&CurrencyBalance) -> u64) -> BTreeMap<Currency, u64> {
+    let mut totals = BTreeMap::new();
     AGENT_BALANCES.with(|balances| {
-        balances.borrow()
-            .values()
-            .map(|b| b.total_commission_owed)
-            .sum()
-    })
+        for (_, balance) in balances.borrow().iter() {
+            for entry in &balance.currencies {
+                *totals.entry(entry.currency).or_insert(0) += field(entry);
+            }
+        }
+    });
+    totals
+}
+
+fn currency_amounts(totals: BTreeMap<Currency, u64>) -> Vec<CurrencyAmount> {
+    totals
+        .into_iter()
+        .map(|(currency, amount)| CurrencyAmount { currency, amount })
+        .collect()
+}
+
+#[query]
+fn get_commission_rate(currency: Currency) -> Result<u64, DepositError> {
+    let config = current_config();
+    Ok(config.deposit.currency(currency)?.platform_fee_basis_points)
+}
+
+#[query]
+fn get_fee_split(currency: Currency) -> Result<(u64, u64), DepositError> {
+    let config = current_config();
+    let currency_config = config.deposit.currency(currency)?;
+    Ok((currency_config.platform_fee_basis_points, currency_config.agent_commission_basis_points))
+}
+
+#[query]
+fn get_company_wallet_principal() -> Result<Principal, DepositError> {
+    get_company_wallet().map_err(|reason| DepositError::Misconfigured { reason })
+}
+
+// ============================================================================
+// AUDIT LOG
+// ============================================================================
+
+/// Audit events, newest first (staff only). Pass the lowest event id of the
+/// previous page as `before` to continue.
+#[query]
+fn get_audit_events(before: Option<u64>, limit: u64) -> Result<Vec<AuditEvent>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    Ok(audit::events(before, limit))
+}
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+// Sanity bounds for runtime config changes
+const MAX_TOTAL_FEE_BPS: u64 = 2_500;
+const MAX_CODE_VALIDITY_HOURS: u64 = 7 * 24;
+const MAX_PAYOUT_DECIMALS: u8 = 18;
+
+#[query]
+fn get_config() -> RevenueConfig {
+    current_config()
+}
+
+/// Replace the live configuration (controllers or SNS governance only).
+/// The change is validated, applied immediately and survives upgrades.
+#[update]
+fn set_config(config: RevenueConfig) -> Result<ConfigChange, DepositError> {
+    let caller = ic_cdk::api::msg_caller();
+    require_governance(caller)?;
+    validate_config(&config).map_err(|reason| DepositError::InvalidInput { reason })?;
+    
+    Ok(apply_config(config, caller, ic_cdk::api::time()))
 }
 
+/// Every runtime config change, newest first.
 #[query]
-fn get_commission_rate() -> u64 {
-    let config = get_config();
-    config.deposit.platform_fee_basis_points
+fn get_config_history() -> Result<Vec<ConfigChange>, DepositError> {
+    let caller = ic_cdk::api::msg_caller();
+    if require_governance(caller).is_err() {
+        require_staff(caller)?;
+    }
+    
+    Ok(CONFIG_HISTORY.with(|h| h.borrow().iter().rev().map(|(_, change)| change).collect()))
+}
+
+fn apply_config(config: RevenueConfig, actor: Principal, now: u64) -> ConfigChange {
+    let id = CONFIG_HISTORY.with(|h| h.borrow().last_key_value().map_or(1, |(id, _)| id + 1));
+    let change = ConfigChange {
+        id,
+        changed_by: actor,
+        changed_at: now,
+        old: current_config(),
+        new: config.clone(),
+    };
+    
+    CONFIG_HISTORY.with(|h| h.borrow_mut().insert(id, change.clone()));
+    CONFIG.with(|c| *c.borrow_mut() = Some(config));
+    audit::record(
+        actor,
+        AuditAction::ConfigChanged,
+        AuditEntity::Config { change_id: id },
+        Transition::default().with_note("Old and new values are in get_config_history"),
+        now,
+    );
+    change
+}
+
+fn require_governance(caller: Principal) -> Result<(), DepositError> {
+    if ic_cdk::api::is_controller(&caller) || is_sns_governance(caller) {
+        Ok(())
+    } else {
+        Err(DepositError::Unauthorized)
+    }
+}
+
+fn is_sns_governance(caller: Principal) -> bool {
+    let governance = current_config().governance.sns_governance;
+    !governance.is_empty() && Principal::from_text(&governance).is_ok_and(|p| p == caller)
+}
+
+fn parse_principal(field: &str, text: &str) -> Result<Principal, String> {
+    Principal::from_text(text).map_err(|e| format!("{} is not a valid principal: {}", field, e))
+}
+
+fn validate_config(config: &RevenueConfig) -> Result<(), String> {
+    let company = parse_principal("company_wallet.principal", &config.company_wallet.principal)?;
+    if company == Principal::anonymous() {
+        return Err("company_wallet.principal must not be anonymous".to_string());
+    }
+    if !config.agent_registry.canister_id.is_empty() {
+        parse_principal("agent_registry.canister_id", &config.agent_registry.canister_id)?;
+    }
+    if !config.governance.sns_governance.is_empty() {
+        parse_principal("governance.sns_governance", &config.governance.sns_governance)?;
+    }
+    
+    let deposit = &config.deposit;
+    if deposit.code_validity_hours == 0 || deposit.code_validity_hours > MAX_CODE_VALIDITY_HOURS {
+        return Err(format!("code_validity_hours must be between 1 and {}", MAX_CODE_VALIDITY_HOURS));
+    }
+    if deposit.currencies.is_empty() {
+        return Err("At least one currency must be configured".to_string());
+    }
+    for (code, currency) in &deposit.currencies {
+        validate_currency_config(code, currency)?;
+    }
+    
+    if let Some(payout) = &deposit.settlement_payout {
+        parse_principal("settlement_payout.ledger", &payout.ledger)?;
+        if payout.decimals > MAX_PAYOUT_DECIMALS {
+            return Err(format!("settlement_payout.decimals must be at most {}", MAX_PAYOUT_DECIMALS));
+        }
+        for (code, units) in &payout.units_per_token {
+            Currency::from_code(code).ok_or(format!("Unknown currency '{}' in units_per_token", code))?;
+            if *units == 0 {
+                return Err(format!("units_per_token for {} must be greater than 0", code));
+            }
+        }
+    }
+    
+    if let Some(fraud) = &deposit.fraud {
+        fraud::validate(fraud)?;
+        if fraud.round_trip_hours > 0 {
+            return Err("fraud: round_trip_hours applies to withdrawals only".to_string());
+        }
+    }
+    if !deposit.withdrawal_canister.is_empty() {
+        parse_principal("deposit.withdrawal_canister", &deposit.withdrawal_canister)?;
+    }
+    
+    Ok(())
+}
+
+fn validate_currency_config(code: &str, config: &DepositCurrencyConfig) -> Result<(), String> {
+    Currency::from_code(code).ok_or(format!("Unknown currency '{}'", code))?;
+    
+    let total_fee = config.platform_fee_basis_points.saturating_add(config.agent_commission_basis_points);
+    if total_fee > MAX_TOTAL_FEE_BPS {
+        return Err(format!(
+            "{}: platform fee plus agent commission must be at most {} bps",
+            code, MAX_TOTAL_FEE_BPS
+        ));
+    }
+    if config.min_deposit == 0 || config.min_deposit > config.max_deposit {
+        return Err(format!("{}: min_deposit must be positive and at most max_deposit", code));
+    }
+    
+    Ok(())
+}
+
+// ============================================================================
+// ACCESS CONTROL
+// ============================================================================
+
+/// Staff role of a principal. The company wallet is always an admin.
+fn staff_role(principal: Principal) -> Option<StaffRole> {
+    if get_company_wallet().is_ok_and(|company| company == principal) {
+        return Some(StaffRole::Admin);
+    }
+    STAFF_ROLES.with(|r| r.borrow().get(&principal))
+}
+
+fn require_staff(caller: Principal) -> Result<(), DepositError> {
+    staff_role(caller).map(|_| ()).ok_or(DepositError::Unauthorized)
+}
+
+/// Allow the caller if it is one of `parties` (the user or agent on a
+/// record) or holds a staff role.
+fn require_party_or_staff(caller: Principal, parties: &[Principal]) -> Result<(), DepositError> {
+    if caller != Principal::anonymous() && parties.contains(&caller) {
+        return Ok(());
+    }
+    require_staff(caller)
+}
+
+/// Like `require_party_or_staff` for a user's deposit history, which the
+/// configured withdrawal canister may also read.
+fn require_history_reader(caller: Principal, user: Principal) -> Result<(), DepositError> {
+    let withdrawal_canister = current_config().deposit.withdrawal_canister;
+    if !withdrawal_canister.is_empty() && Principal::from_text(&withdrawal_canister).ok() == Some(caller) {
+        return Ok(());
+    }
+    require_party_or_staff(caller, &[user])
+}
+
+/// Grant or change a staff role (canister controllers only).
+#[update]
+fn grant_role(principal: Principal, role: StaffRole) -> Result<(), DepositError> {
+    require_controller(ic_cdk::api::msg_caller())?;
+    STAFF_ROLES.with(|r| r.borrow_mut().insert(principal, role));
+    Ok(())
+}
+
+#[update]
+fn revoke_role(principal: Principal) -> Result<(), DepositError> {
+    require_controller(ic_cdk::api::msg_caller())?;
+    STAFF_ROLES.with(|r| r.borrow_mut().remove(&principal));
+    Ok(())
 }
 
 #[query]
-fn get_company_wallet_principal() -> Result<Principal, String> {
-    get_company_wallet()
+fn get_staff_roles() -> Result<Vec<(Principal, StaffRole)>, DepositError> {
+    require_staff(ic_cdk::api::msg_caller())?;
+    Ok(STAFF_ROLES.with(|r| r.borrow().iter().collect()))
+}
+
+fn require_controller(caller: Principal) -> Result<(), DepositError> {
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(DepositError::Unauthorized)
+    }
 }
 
 // ============================================================================
 // HELPER FUNCTIONS
 // ============================================================================
-// Helper: Generate deposit code
-fn generate_deposit_code(id: u64) -> String {
-    format!("DEP-{:08}", id)
+
+// Helper: Take the next value from a stable id counter
+fn next_id(counter: &'static std::thread::LocalKey<RefCell<StableCell<u64, Memory>>>) -> u64 {
+    counter.with(|c| {
+        let mut cell = c.borrow_mut();
+        let current = *cell.get();
+        cell.set(current + 1).expect("Failed to persist id counter");
+        current
+    })
 }
 
 // Tests module
@@ -0,0 +1,66 @@
+//! Calendar helpers for settlement periods.
+//!
+//! A settlement period is a UTC calendar month written `YYYY-MM`. Canister
+//! time is nanoseconds since the Unix epoch, so periods are converted to a
+//! half-open `[start, end)` nanosecond range.
+
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Month {
+    pub year: u32,
+    pub month: u32,
+}
+
+impl Month {
+    /// Parse a strict `YYYY-MM` string (no earlier than 1970-01).
+    pub fn parse(input: &str) -> Option<Month> {
+        let bytes = input.as_bytes();
+        if bytes.len() != 7 || bytes[4] != b'-' {
+            return None;
+        }
+
+        if !input[..4].bytes().chain(input[5..].bytes()).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let year: u32 = input[..4].parse().ok()?;
+        let month: u32 = input[5..].parse().ok()?;
+
+        if year < 1970 || !(1..=12).contains(&month) {
+            return None;
+        }
+
+        Some(Month { year, month })
+    }
+
+    pub fn next(self) -> Month {
+        if self.month == 12 {
+            Month { year: self.year + 1, month: 1 }
+        } else {
+            Month { year: self.year, month: self.month + 1 }
+        }
+    }
+
+    /// First nanosecond of the month.
+    pub fn start_nanos(self) -> u64 {
+        days_from_civil(self.year, self.month, 1) * NANOS_PER_DAY
+    }
+
+    /// First nanosecond after the month.
+    pub fn end_nanos(self) -> u64 {
+        self.next().start_nanos()
+    }
+}
+
+/// Days since 1970-01-01 for a Gregorian date on or after the epoch
+/// (Howard Hinnant's `days_from_civil`).
+fn days_from_civil(year: u32, month: u32, day: u32) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year } as u64;
+    let m = month as u64;
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as u64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
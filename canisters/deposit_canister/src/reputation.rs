@@ -0,0 +1,43 @@
+//! Agent reputation from deposit outcomes and ratings; see `canister_shared::reputation`.
+
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+pub use canister_shared::reputation::{validate_rating, AgentReputation, Tally};
+use canister_shared::reputation::{RequestOutcome, Stage};
+
+use crate::{DepositTransaction, TransactionStatus};
+
+/// A user's rating of the agent that handled one of their deposits.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentRating {
+    pub deposit_id: u64,
+    pub agent: Principal,
+    pub user: Principal,
+    pub stars: u8,
+    pub comment: Option<String>,
+    pub rated_at: u64,
+}
+
+/// What a deposit contributes to its agent's tally. `disputed` says whether
+/// a dispute was ever opened on it.
+pub fn outcome(deposit: &DepositTransaction, disputed: bool) -> RequestOutcome {
+    let stage = match deposit.status {
+        TransactionStatus::Pending => Stage::Pending,
+        TransactionStatus::Confirmed => Stage::Confirmed,
+        TransactionStatus::Cancelled => Stage::Cancelled,
+        TransactionStatus::Expired => Stage::Expired,
+        TransactionStatus::Disputed => Stage::UnderReview,
+    };
+    let disputed = disputed || stage == Stage::UnderReview;
+
+    RequestOutcome {
+        stage,
+        disputed,
+        // Deposits confirmed by a dispute ruling say nothing about the agent's speed
+        confirmation_latency: match (disputed, deposit.confirmed_at) {
+            (false, Some(confirmed_at)) => Some(confirmed_at.saturating_sub(deposit.timestamp)),
+            _ => None,
+        },
+    }
+}
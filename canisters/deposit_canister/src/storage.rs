@@ -0,0 +1,575 @@
+//! Stable memory layout for the deposit canister.
+//!
+//! Every persistent collection lives in its own virtual memory so that state
+//! survives canister upgrades without a `pre_upgrade` serialization step.
+//! Records are written inside a versioned envelope: when a record layout
+//! changes, add a new variant and convert older variants on read.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use canister_shared::ordered_key::{put_principal, take_principal, take_u64, ORDERED_PRINCIPAL_LEN};
+
+use crate::ledger::{AccountBlockKey, AccountKey, Allowance, AllowanceKey, Currency, LedgerBlock, Subaccount};
+use crate::reputation::AgentRating;
+use crate::{
+    AgentBalance, ConfigChange, CurrencyBalance, DepositDispute, DepositTransaction, DisputeOutcome, MonthlySettlement,
+    PartyDepositKey, SettlementKey, SettlementPayout, SettlementPeriod, StaffRole, TransactionStatus,
+    VolumeKey, VolumeWindow,
+};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Current layout of the records stored by this canister.
+/// Bump this whenever a new envelope variant is introduced.
+pub const SCHEMA_VERSION: u32 = 8;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const AGENT_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const SETTLEMENTS_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const NEXT_DEPOSIT_ID_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const NEXT_SETTLEMENT_ID_MEMORY_ID: MemoryId = MemoryId::new(5);
+// 6 and 7 held UGX-only velocity windows keyed by principal; read once by the
+// v6 migration, then retired
+pub const LEGACY_USER_VOLUMES_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const LEGACY_AGENT_VOLUMES_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const USER_VOLUMES_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub const AGENT_VOLUMES_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub const DEPOSIT_CODES_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const DEPOSIT_EXPIRIES_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const DISPUTES_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const LEDGER_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const LEDGER_BLOCKS_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const LEDGER_ACCOUNT_BLOCKS_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const CONFIRMED_DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(14);
+// 15 held the v4 settlement index, keyed without a currency; retired
+pub const SETTLEMENT_INDEX_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub const CONFIG_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub const LEDGER_ALLOWANCES_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub const AGENT_RATINGS_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub const AGENT_TALLIES_MEMORY_ID: MemoryId = MemoryId::new(28);
+pub const FRAUD_ALERTS_MEMORY_ID: MemoryId = MemoryId::new(29);
+pub const DEPOSIT_ALERTS_MEMORY_ID: MemoryId = MemoryId::new(30);
+pub const SETTLEMENT_PERIODS_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub const USER_DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub const AGENT_DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub const STAFF_ROLES_MEMORY_ID: MemoryId = MemoryId::new(20);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+fn encode<T: CandidType>(value: &T) -> Cow<'static, [u8]> {
+    Cow::Owned(candid::encode_one(value).expect("Failed to encode stable record"))
+}
+
+fn decode<T: CandidType + for<'de> Deserialize<'de>>(bytes: &[u8]) -> T {
+    candid::decode_one(bytes).expect("Failed to decode stable record")
+}
+
+// ============================================================================
+// VERSIONED ENVELOPES
+// ============================================================================
+
+/// Implements `Storable` for a record through a versioned envelope enum.
+/// Once a record has more than one variant, write the impl by hand instead.
+macro_rules! versioned_storable {
+    ($record:ty, $envelope:ident) => {
+        #[derive(CandidType, Deserialize)]
+        enum $envelope {
+            V1($record),
+        }
+
+        impl Storable for $record {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                encode(&$envelope::V1(self.clone()))
+            }
+
+            fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+                match decode(&bytes) {
+                    $envelope::V1(record) => record,
+                }
+            }
+
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    };
+}
+
+versioned_storable!(LedgerBlock, StoredLedgerBlock);
+versioned_storable!(SettlementPeriod, StoredSettlementPeriod);
+versioned_storable!(StaffRole, StoredStaffRole);
+versioned_storable!(ConfigChange, StoredConfigChange);
+versioned_storable!(Allowance, StoredAllowance);
+versioned_storable!(AgentRating, StoredAgentRating);
+
+// ============================================================================
+// KEYS
+// ============================================================================
+
+/// Implements `Storable` for a map key. Key encodings are frozen: changing
+/// one would orphan every entry already stored under it.
+macro_rules! candid_storable_key {
+    ($key:ty) => {
+        impl Storable for $key {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                encode(self)
+            }
+
+            fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+                decode(&bytes)
+            }
+
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    };
+}
+
+candid_storable_key!(AccountKey);
+candid_storable_key!(AllowanceKey);
+candid_storable_key!(SettlementKey);
+candid_storable_key!(VolumeKey);
+
+// Keys that are range-scanned use the ordered encoding from `canister_shared`.
+
+fn currency_tag(currency: Currency) -> u8 {
+    match currency {
+        Currency::UGX => 0,
+        Currency::KES => 1,
+        Currency::TZS => 2,
+        Currency::NGN => 3,
+        Currency::GHS => 4,
+    }
+}
+
+fn currency_from_tag(tag: u8) -> Currency {
+    match tag {
+        0 => Currency::UGX,
+        1 => Currency::KES,
+        2 => Currency::TZS,
+        3 => Currency::NGN,
+        4 => Currency::GHS,
+        _ => panic!("Unknown currency tag {}", tag),
+    }
+}
+
+impl Storable for AccountBlockKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut buf = Vec::with_capacity(Self::SIZE as usize);
+        buf.push(currency_tag(self.account.currency));
+        put_principal(&mut buf, &self.account.owner);
+        buf.extend_from_slice(&self.account.subaccount);
+        buf.extend_from_slice(&self.block_index.to_be_bytes());
+        Cow::Owned(buf)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let currency = currency_from_tag(bytes[0]);
+        let (owner, rest) = take_principal(&bytes[1..]);
+        let (subaccount, rest) = rest.split_at(32);
+        let subaccount: Subaccount = subaccount.try_into().expect("32-byte subaccount");
+        let (block_index, _) = take_u64(rest);
+        AccountBlockKey { account: AccountKey { currency, owner, subaccount }, block_index }
+    }
+
+    const BOUND: Bound = Bound::Bounded { max_size: Self::SIZE, is_fixed_size: true };
+}
+
+impl AccountBlockKey {
+    const SIZE: u32 = 1 + ORDERED_PRINCIPAL_LEN + 32 + 8;
+}
+
+impl Storable for PartyDepositKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut buf = Vec::with_capacity(Self::SIZE as usize);
+        put_principal(&mut buf, &self.party);
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        Cow::Owned(buf)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (party, rest) = take_principal(&bytes);
+        let (timestamp, rest) = take_u64(rest);
+        let (id, _) = take_u64(rest);
+        PartyDepositKey { party, timestamp, id }
+    }
+
+    const BOUND: Bound = Bound::Bounded { max_size: Self::SIZE, is_fixed_size: true };
+}
+
+impl PartyDepositKey {
+    const SIZE: u32 = ORDERED_PRINCIPAL_LEN + 8 + 8;
+}
+
+// ============================================================================
+// DEPOSIT RECORDS
+// ============================================================================
+
+/// Validity given to deposits created before codes had an expiry (schema v1).
+const LEGACY_DEPOSIT_VALIDITY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Schema v1: sequential codes, no expiry.
+#[derive(CandidType, Deserialize)]
+struct DepositTransactionV1 {
+    id: u64,
+    user_principal: Principal,
+    agent_principal: Principal,
+    amount_ugx: u64,
+    commission_ugx: u64,
+    deposit_code: String,
+    timestamp: u64,
+    status: TransactionStatus,
+}
+
+impl From<DepositTransactionV1> for DepositTransactionV2 {
+    fn from(v1: DepositTransactionV1) -> Self {
+        DepositTransactionV2 {
+            id: v1.id,
+            user_principal: v1.user_principal,
+            agent_principal: v1.agent_principal,
+            amount_ugx: v1.amount_ugx,
+            commission_ugx: v1.commission_ugx,
+            deposit_code: v1.deposit_code,
+            timestamp: v1.timestamp,
+            expires_at: v1.timestamp + LEGACY_DEPOSIT_VALIDITY_NANOS,
+            status: v1.status,
+        }
+    }
+}
+
+/// Schema v2: a single `commission_ugx`, computed from the platform fee rate.
+#[derive(CandidType, Deserialize)]
+struct DepositTransactionV2 {
+    id: u64,
+    user_principal: Principal,
+    agent_principal: Principal,
+    amount_ugx: u64,
+    commission_ugx: u64,
+    deposit_code: String,
+    timestamp: u64,
+    expires_at: u64,
+    status: TransactionStatus,
+}
+
+impl From<DepositTransactionV2> for DepositTransactionV3 {
+    fn from(v2: DepositTransactionV2) -> Self {
+        // The legacy commission was the platform fee; no agent commission was charged
+        DepositTransactionV3 {
+            id: v2.id,
+            user_principal: v2.user_principal,
+            agent_principal: v2.agent_principal,
+            amount_ugx: v2.amount_ugx,
+            platform_fee_ugx: v2.commission_ugx,
+            agent_commission_ugx: 0,
+            deposit_code: v2.deposit_code,
+            timestamp: v2.timestamp,
+            expires_at: v2.expires_at,
+            confirmed_at: None,
+            status: v2.status,
+        }
+    }
+}
+
+/// Schema v3: separate platform fee and agent commission, UGX only.
+#[derive(CandidType, Deserialize)]
+struct DepositTransactionV3 {
+    id: u64,
+    user_principal: Principal,
+    agent_principal: Principal,
+    amount_ugx: u64,
+    platform_fee_ugx: u64,
+    agent_commission_ugx: u64,
+    deposit_code: String,
+    timestamp: u64,
+    expires_at: u64,
+    confirmed_at: Option<u64>,
+    status: TransactionStatus,
+}
+
+impl From<DepositTransactionV3> for DepositTransaction {
+    fn from(v3: DepositTransactionV3) -> Self {
+        DepositTransaction {
+            id: v3.id,
+            user_principal: v3.user_principal,
+            agent_principal: v3.agent_principal,
+            currency: Currency::UGX,
+            amount: v3.amount_ugx,
+            platform_fee: v3.platform_fee_ugx,
+            agent_commission: v3.agent_commission_ugx,
+            deposit_code: v3.deposit_code,
+            timestamp: v3.timestamp,
+            expires_at: v3.expires_at,
+            confirmed_at: v3.confirmed_at,
+            status: v3.status,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredDeposit {
+    V1(DepositTransactionV1),
+    V2(DepositTransactionV2),
+    V3(DepositTransactionV3),
+    V4(DepositTransaction),
+}
+
+impl Storable for DepositTransaction {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(&StoredDeposit::V4(self.clone()))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match decode(&bytes) {
+            StoredDeposit::V1(deposit) => DepositTransactionV3::from(DepositTransactionV2::from(deposit)).into(),
+            StoredDeposit::V2(deposit) => DepositTransactionV3::from(deposit).into(),
+            StoredDeposit::V3(deposit) => deposit.into(),
+            StoredDeposit::V4(deposit) => deposit,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// AGENT BALANCE RECORDS
+// ============================================================================
+
+/// Schema v1: platform fees were booked as `total_commission_owed`.
+#[derive(CandidType, Deserialize)]
+struct AgentBalanceV1 {
+    principal: Principal,
+    total_deposits: u64,
+    total_commission_owed: u64,
+    total_commission_paid: u64,
+    last_settlement_date: Option<u64>,
+}
+
+impl From<AgentBalanceV1> for AgentBalanceV2 {
+    fn from(v1: AgentBalanceV1) -> Self {
+        // Reclassify the legacy "commission" as platform revenue. Settlements
+        // already paid out stay on record as commission the agent earned.
+        AgentBalanceV2 {
+            principal: v1.principal,
+            total_deposits: v1.total_deposits,
+            total_platform_fees: v1.total_commission_owed,
+            total_commission_owed: v1.total_commission_paid,
+            total_commission_paid: v1.total_commission_paid,
+            last_settlement_date: v1.last_settlement_date,
+        }
+    }
+}
+
+/// Schema v2: separate platform fees and commission, UGX only.
+#[derive(CandidType, Deserialize)]
+struct AgentBalanceV2 {
+    principal: Principal,
+    total_deposits: u64,
+    total_platform_fees: u64,
+    total_commission_owed: u64,
+    total_commission_paid: u64,
+    last_settlement_date: Option<u64>,
+}
+
+impl From<AgentBalanceV2> for AgentBalance {
+    fn from(v2: AgentBalanceV2) -> Self {
+        AgentBalance {
+            principal: v2.principal,
+            currencies: vec![CurrencyBalance {
+                currency: Currency::UGX,
+                total_deposits: v2.total_deposits,
+                total_platform_fees: v2.total_platform_fees,
+                total_commission_owed: v2.total_commission_owed,
+                total_commission_paid: v2.total_commission_paid,
+            }],
+            last_settlement_date: v2.last_settlement_date,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredAgentBalance {
+    V1(AgentBalanceV1),
+    V2(AgentBalanceV2),
+    V3(AgentBalance),
+}
+
+impl Storable for AgentBalance {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(&StoredAgentBalance::V3(self.clone()))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match decode(&bytes) {
+            StoredAgentBalance::V1(balance) => AgentBalanceV2::from(balance).into(),
+            StoredAgentBalance::V2(balance) => balance.into(),
+            StoredAgentBalance::V3(balance) => balance,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// SETTLEMENT RECORDS
+// ============================================================================
+
+/// Schema v1: settlements were UGX only.
+#[derive(CandidType, Deserialize)]
+struct MonthlySettlementV1 {
+    month: String,
+    agent_principal: Principal,
+    total_commission: u64,
+    paid: bool,
+    paid_date: Option<u64>,
+    payout_block_index: Option<u64>,
+    payout: Option<SettlementPayout>,
+}
+
+impl From<MonthlySettlementV1> for MonthlySettlement {
+    fn from(v1: MonthlySettlementV1) -> Self {
+        MonthlySettlement {
+            month: v1.month,
+            agent_principal: v1.agent_principal,
+            currency: Currency::UGX,
+            total_commission: v1.total_commission,
+            paid: v1.paid,
+            paid_date: v1.paid_date,
+            payout_block_index: v1.payout_block_index,
+            payout: v1.payout,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredSettlement {
+    V1(MonthlySettlementV1),
+    V2(MonthlySettlement),
+}
+
+impl Storable for MonthlySettlement {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(&StoredSettlement::V2(self.clone()))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match decode(&bytes) {
+            StoredSettlement::V1(settlement) => settlement.into(),
+            StoredSettlement::V2(settlement) => settlement,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// VELOCITY & DISPUTE RECORDS
+// ============================================================================
+
+/// Schema v1: UGX-only volume totals.
+#[derive(CandidType, Deserialize)]
+struct VolumeWindowV1 {
+    day: u64,
+    day_total_ugx: u64,
+    week: u64,
+    week_total_ugx: u64,
+}
+
+impl From<VolumeWindowV1> for VolumeWindow {
+    fn from(v1: VolumeWindowV1) -> Self {
+        VolumeWindow {
+            day: v1.day,
+            day_total: v1.day_total_ugx,
+            week: v1.week,
+            week_total: v1.week_total_ugx,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredVolumeWindow {
+    V1(VolumeWindowV1),
+    V2(VolumeWindow),
+}
+
+impl Storable for VolumeWindow {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(&StoredVolumeWindow::V2(self.clone()))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match decode(&bytes) {
+            StoredVolumeWindow::V1(window) => window.into(),
+            StoredVolumeWindow::V2(window) => window,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Schema v1: `unrecovered_ugx`.
+#[derive(CandidType, Deserialize)]
+struct DepositDisputeV1 {
+    deposit_id: u64,
+    reason: String,
+    evidence_note: String,
+    opened_by: Principal,
+    opened_at: u64,
+    status_before_dispute: TransactionStatus,
+    outcome: Option<DisputeOutcome>,
+    resolution_note: Option<String>,
+    resolved_by: Option<Principal>,
+    resolved_at: Option<u64>,
+    unrecovered_ugx: Option<u64>,
+}
+
+impl From<DepositDisputeV1> for DepositDispute {
+    fn from(v1: DepositDisputeV1) -> Self {
+        DepositDispute {
+            deposit_id: v1.deposit_id,
+            reason: v1.reason,
+            evidence_note: v1.evidence_note,
+            opened_by: v1.opened_by,
+            opened_at: v1.opened_at,
+            status_before_dispute: v1.status_before_dispute,
+            outcome: v1.outcome,
+            resolution_note: v1.resolution_note,
+            resolved_by: v1.resolved_by,
+            resolved_at: v1.resolved_at,
+            unrecovered: v1.unrecovered_ugx,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredDispute {
+    V1(DepositDisputeV1),
+    V2(DepositDispute),
+}
+
+impl Storable for DepositDispute {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        encode(&StoredDispute::V2(self.clone()))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match decode(&bytes) {
+            StoredDispute::V1(dispute) => dispute.into(),
+            StoredDispute::V2(dispute) => dispute,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -18,13 +18,48 @@ fn test_create_deposit_request() {
 
 #[test]
 fn test_deposit_code_generation() {
-    let code1 = generate_deposit_code(1);
-    let code2 = generate_deposit_code(100);
-    let code3 = generate_deposit_code(999999);
+    let code1 = generate_deposit_code(&[0, 0, 0, 0, 0, 0, 1]);
+    let code2 = generate_deposit_code(&[7, 19, 255, 42, 3, 88, 200]);
     
-    assert_eq!(code1, "DEP-00000001");
-    assert_eq!(code2, "DEP-00000100");
-    assert_eq!(code3, "DEP-00999999");
+    assert_eq!(code1.len(), "DEP-XXXX-XXXX".len());
+    assert!(code1.starts_with("DEP-"));
+    assert_ne!(code1, code2);
+    
+    // Same entropy always yields the same code
+    assert_eq!(code2, generate_deposit_code(&[7, 19, 255, 42, 3, 88, 200]));
+}
+
+#[test]
+fn test_deposit_code_roundtrips_through_normalization() {
+    let code = generate_deposit_code(&[1, 2, 3, 4, 5, 6, 7]);
+    
+    assert_eq!(normalize_deposit_code(&code), Some(code.clone()));
+    assert_eq!(normalize_deposit_code(&code.to_lowercase()), Some(code.clone()));
+    assert_eq!(normalize_deposit_code(&code.replace('-', " ")), Some(code.clone()));
+    assert_eq!(normalize_deposit_code(&code["DEP-".len()..]), Some(code.clone()));
+}
+
+#[test]
+fn test_deposit_code_checksum_catches_typos() {
+    let code = generate_deposit_code(&[9, 9, 9, 9, 9, 9, 9]);
+    let mut chars: Vec<char> = code.chars().collect();
+    
+    // Change one character of the random body
+    chars[4] = if chars[4] == 'A' { 'B' } else { 'A' };
+    let typo: String = chars.into_iter().collect();
+    
+    assert_eq!(normalize_deposit_code(&typo), None);
+    assert_eq!(normalize_deposit_code("DEP-00000001"), None);
+    assert_eq!(normalize_deposit_code(""), None);
+}
+
+#[test]
+fn test_deposit_code_accepts_look_alike_characters() {
+    // Body "0000001" -> O and I read as 0 and 1
+    let code = generate_deposit_code(&[0, 0, 0, 0, 0, 0, 1]);
+    let spoken = code.replace('0', "O").replace('1', "I");
+    
+    assert_eq!(normalize_deposit_code(&spoken), Some(code));
 }
 
 #[test]
@@ -231,3 +266,1484 @@ fn test_pending_to_confirmed() {
     let new_status = TransactionStatus::Confirmed;
     assert_eq!(new_status, TransactionStatus::Confirmed);
 }
+
+// ============================================================================
+// STABLE STORAGE TESTS
+// ============================================================================
+
+fn sample_deposit(id: u64) -> DepositTransaction {
+    DepositTransaction {
+        id,
+        user_principal: Principal::from_slice(&[1]),
+        agent_principal: Principal::from_slice(&[2]),
+        currency: Currency::UGX,
+        amount: 100_000,
+        platform_fee: 500,
+        agent_commission: 10_000,
+        deposit_code: generate_deposit_code(&id.to_le_bytes()),
+        timestamp: 1_700_000_000_000_000_000,
+        expires_at: 1_700_000_000_000_000_000 + 24 * NANOS_PER_HOUR,
+        confirmed_at: None,
+        status: TransactionStatus::Pending,
+    }
+}
+
+#[test]
+fn test_deposit_survives_stable_roundtrip() {
+    use ic_stable_structures::Storable;
+    
+    let deposit = sample_deposit(42);
+    let restored = DepositTransaction::from_bytes(deposit.to_bytes());
+    
+    assert_eq!(restored.id, 42);
+    assert_eq!(restored.deposit_code, deposit.deposit_code);
+    assert_eq!(restored.expires_at, deposit.expires_at);
+    assert_eq!(restored.currency, Currency::UGX);
+    assert_eq!(restored.amount, 100_000);
+    assert_eq!(restored.status, TransactionStatus::Pending);
+}
+
+#[test]
+fn test_agent_balance_survives_stable_roundtrip() {
+    use ic_stable_structures::Storable;
+    
+    let balance = AgentBalance {
+        principal: Principal::from_slice(&[2]),
+        currencies: vec![CurrencyBalance {
+            currency: Currency::KES,
+            total_deposits: 1_000_000,
+            total_platform_fees: 5_000,
+            total_commission_owed: 5_000,
+            total_commission_paid: 2_000,
+        }],
+        last_settlement_date: Some(7),
+    };
+    let restored = AgentBalance::from_bytes(balance.to_bytes());
+    
+    assert_eq!(restored.principal, balance.principal);
+    assert_eq!(restored.currencies, balance.currencies);
+    assert_eq!(restored.last_settlement_date, Some(7));
+}
+
+#[test]
+fn test_deposit_ids_are_never_reissued() {
+    let first = next_id(&NEXT_DEPOSIT_ID);
+    let second = next_id(&NEXT_DEPOSIT_ID);
+    
+    assert_eq!(second, first + 1);
+    assert_eq!(NEXT_DEPOSIT_ID.with(|c| *c.borrow().get()), second + 1);
+}
+
+#[test]
+fn test_deposits_map_persists_records() {
+    let deposit = sample_deposit(7);
+    DEPOSITS.with(|d| d.borrow_mut().insert(deposit.id, deposit.clone()));
+    
+    let stored = DEPOSITS.with(|d| d.borrow().get(&7)).expect("deposit stored");
+    assert_eq!(stored.deposit_code, deposit.deposit_code);
+}
+
+// ============================================================================
+// LIMIT & VELOCITY TESTS
+// ============================================================================
+
+fn test_config() -> RevenueConfig {
+    toml::from_str(CONFIG_TOML).expect("Failed to parse revenue_config.toml")
+}
+
+fn currency_config(currency: Currency) -> DepositCurrencyConfig {
+    test_config().deposit.currency(currency).cloned().expect("currency configured")
+}
+
+#[test]
+fn test_amount_bounds_enforced() {
+    let config = currency_config(Currency::UGX);
+    
+    assert_eq!(check_amount_bounds(0, &config), Err(DepositError::InvalidAmount));
+    assert_eq!(
+        check_amount_bounds(config.min_deposit - 1, &config),
+        Err(DepositError::BelowMinimum { min: config.min_deposit })
+    );
+    assert_eq!(
+        check_amount_bounds(config.max_deposit + 1, &config),
+        Err(DepositError::AboveMaximum { max: config.max_deposit })
+    );
+    assert!(check_amount_bounds(config.min_deposit, &config).is_ok());
+    assert!(check_amount_bounds(config.max_deposit, &config).is_ok());
+}
+
+#[test]
+fn test_every_currency_is_configured() {
+    let config = test_config().deposit;
+    
+    for currency in [Currency::UGX, Currency::KES, Currency::TZS, Currency::NGN, Currency::GHS] {
+        let limits = config.currency(currency).unwrap_or_else(|_| panic!("{} configured", currency.code()));
+        assert!(limits.min_deposit > 0 && limits.min_deposit <= limits.max_deposit);
+    }
+}
+
+#[test]
+fn test_limits_differ_per_currency() {
+    let ugx = currency_config(Currency::UGX);
+    let kes = currency_config(Currency::KES);
+    
+    // The same number is a different amount of money in each currency
+    assert!(check_amount_bounds(ugx.max_deposit, &ugx).is_ok());
+    assert_eq!(
+        check_amount_bounds(ugx.max_deposit, &kes),
+        Err(DepositError::AboveMaximum { max: kes.max_deposit })
+    );
+}
+
+#[test]
+fn test_unconfigured_currency_is_rejected() {
+    let mut config = test_config().deposit;
+    config.currencies.remove("GHS");
+    
+    assert!(matches!(
+        config.currency(Currency::GHS),
+        Err(DepositError::UnsupportedCurrency { currency: Currency::GHS })
+    ));
+}
+
+#[test]
+fn test_volume_window_rolls_over() {
+    let window = VolumeWindow::default().rolled_to(10).with_amount(5_000);
+    assert_eq!(window.day_total, 5_000);
+    assert_eq!(window.week_total, 5_000);
+    
+    // Next day in the same week keeps the weekly total only
+    let next_day = window.rolled_to(11);
+    assert_eq!(next_day.day_total, 0);
+    assert_eq!(next_day.week_total, 5_000);
+    
+    // A new week resets both
+    let next_week = next_day.rolled_to(14);
+    assert_eq!(next_week.day_total, 0);
+    assert_eq!(next_week.week_total, 0);
+}
+
+#[test]
+fn test_daily_velocity_cap() {
+    let window = VolumeWindow::default().rolled_to(0).with_amount(900_000);
+    
+    assert!(check_velocity(&window, 100_000, 1_000_000, 0).is_ok());
+    assert_eq!(
+        check_velocity(&window, 100_001, 1_000_000, 0),
+        Err(CapExceeded { period: CapPeriod::Daily, limit: 1_000_000, remaining: 100_000 })
+    );
+}
+
+#[test]
+fn test_weekly_velocity_cap() {
+    let window = VolumeWindow { day: 1, day_total: 0, week: 0, week_total: 4_000_000 };
+    
+    assert_eq!(
+        check_velocity(&window, 2_000_000, 0, 5_000_000),
+        Err(CapExceeded { period: CapPeriod::Weekly, limit: 5_000_000, remaining: 1_000_000 })
+    );
+}
+
+#[test]
+fn test_zero_velocity_cap_is_unlimited() {
+    let window = VolumeWindow::default().with_amount(u64::MAX / 2);
+    assert!(check_velocity(&window, u64::MAX / 4, 0, 0).is_ok());
+}
+
+// ============================================================================
+// EXPIRY TESTS
+// ============================================================================
+
+fn store_pending(deposit: &DepositTransaction) {
+    DEPOSITS.with(|d| d.borrow_mut().insert(deposit.id, deposit.clone()));
+    DEPOSIT_CODES.with(|c| c.borrow_mut().insert(deposit.deposit_code.clone(), deposit.id));
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().insert((deposit.expires_at, deposit.id), ()));
+}
+
+#[test]
+fn test_code_index_lookup() {
+    let deposit = sample_deposit(3);
+    store_pending(&deposit);
+    
+    assert_eq!(find_deposit_id_by_code(&deposit.deposit_code), Ok(3));
+    assert_eq!(find_deposit_id_by_code(&deposit.deposit_code.to_lowercase()), Ok(3));
+    assert_eq!(
+        find_deposit_id_by_code(&generate_deposit_code(&[5, 5, 5, 5, 5, 5, 5])),
+        Err(DepositError::NotFound)
+    );
+    assert_eq!(find_deposit_id_by_code("DEP-BOGUS"), Err(DepositError::InvalidCode));
+}
+
+#[test]
+fn test_stale_deposits_expire() {
+    let stale = sample_deposit(1);
+    let mut fresh = sample_deposit(2);
+    fresh.expires_at = stale.expires_at + NANOS_PER_HOUR;
+    store_pending(&stale);
+    store_pending(&fresh);
+    
+    assert_eq!(expire_stale_deposits(Principal::anonymous(), stale.expires_at), 1);
+    
+    let stale_after = DEPOSITS.with(|d| d.borrow().get(&1)).unwrap();
+    let fresh_after = DEPOSITS.with(|d| d.borrow().get(&2)).unwrap();
+    assert_eq!(stale_after.status, TransactionStatus::Expired);
+    assert_eq!(fresh_after.status, TransactionStatus::Pending);
+    
+    // Expiring again is a no-op
+    assert_eq!(expire_stale_deposits(Principal::anonymous(), stale.expires_at), 0);
+}
+
+#[test]
+fn test_expiry_releases_velocity_volume() {
+    let deposit = sample_deposit(1);
+    let day = day_index(deposit.timestamp);
+    let key = VolumeKey { owner: deposit.user_principal, currency: deposit.currency };
+    let window = VolumeWindow::default().rolled_to(day).with_amount(deposit.amount);
+    USER_VOLUMES.with(|v| v.borrow_mut().insert(key, window));
+    store_pending(&deposit);
+    
+    expire_stale_deposits(Principal::anonymous(), deposit.expires_at);
+    
+    let released = USER_VOLUMES.with(|v| v.borrow().get(&key)).unwrap();
+    assert_eq!(released.day_total, 0);
+    assert_eq!(released.week_total, 0);
+}
+
+#[test]
+fn test_v1_deposit_record_is_migrated() {
+    use ic_stable_structures::Storable;
+    use std::borrow::Cow;
+    
+    // Mirrors the schema v1 envelope in storage.rs
+    #[derive(CandidType)]
+    struct LegacyDeposit {
+        id: u64,
+        user_principal: Principal,
+        agent_principal: Principal,
+        amount_ugx: u64,
+        commission_ugx: u64,
+        deposit_code: String,
+        timestamp: u64,
+        status: TransactionStatus,
+    }
+    
+    #[derive(CandidType)]
+    enum StoredDeposit {
+        V1(LegacyDeposit),
+    }
+    
+    let bytes = candid::encode_one(StoredDeposit::V1(LegacyDeposit {
+        id: 1,
+        user_principal: Principal::from_slice(&[1]),
+        agent_principal: Principal::from_slice(&[2]),
+        amount_ugx: 50_000,
+        commission_ugx: 250,
+        deposit_code: "DEP-00000001".to_string(),
+        timestamp: 1_000,
+        status: TransactionStatus::Pending,
+    })).unwrap();
+    
+    let migrated = DepositTransaction::from_bytes(Cow::Owned(bytes));
+    assert_eq!(migrated.deposit_code, "DEP-00000001");
+    assert_eq!(migrated.expires_at, 1_000 + 24 * NANOS_PER_HOUR);
+    assert_eq!(migrated.currency, Currency::UGX);
+    assert_eq!(migrated.platform_fee, 250);
+    assert_eq!(migrated.agent_commission, 0);
+}
+
+// ============================================================================
+// CANCELLATION & DISPUTE TESTS
+// ============================================================================
+
+/// The agent's UGX totals.
+fn agent_balance_of(agent: Principal) -> CurrencyBalance {
+    let mut balance = AGENT_BALANCES.with(|b| b.borrow().get(&agent)).expect("agent balance");
+    balance.in_currency_mut(Currency::UGX).clone()
+}
+
+#[test]
+fn test_dispute_reason_validation() {
+    assert!(validate_dispute_reason("Agent never handed over receipt").is_ok());
+    assert!(matches!(validate_dispute_reason("   "), Err(DepositError::InvalidInput { .. })));
+    assert!(matches!(
+        validate_dispute_reason(&"x".repeat(MAX_DISPUTE_REASON_LEN + 1)),
+        Err(DepositError::InvalidInput { .. })
+    ));
+    assert!(validate_dispute_note("").is_ok());
+    assert!(validate_dispute_note(&"x".repeat(MAX_DISPUTE_NOTE_LEN + 1)).is_err());
+}
+
+#[test]
+fn test_dispute_on_pending_deposit_resolved_as_confirmed() {
+    let mut deposit = sample_deposit(1);
+    deposit.status = TransactionStatus::Disputed;
+    
+    settle_disputed_deposit(&mut deposit, &TransactionStatus::Pending, &DisputeOutcome::Confirmed, 0);
+    
+    assert_eq!(deposit.status, TransactionStatus::Confirmed);
+    assert_eq!(ledger::balance_of(&Account::of(deposit.user_principal), Currency::UGX), 89_500);
+    let balance = agent_balance_of(deposit.agent_principal);
+    assert_eq!(balance.total_deposits, deposit.amount);
+    assert_eq!(balance.total_platform_fees, deposit.platform_fee);
+    assert_eq!(balance.total_commission_owed, deposit.agent_commission);
+}
+
+#[test]
+fn test_dispute_on_confirmed_deposit_resolved_as_cancelled() {
+    let mut deposit = sample_deposit(1);
+    credit_user(&deposit, 0);
+    update_agent_balance(&deposit);
+    deposit.status = TransactionStatus::Disputed;
+    
+    let unrecovered = settle_disputed_deposit(&mut deposit, &TransactionStatus::Confirmed, &DisputeOutcome::Cancelled, 0);
+    
+    assert_eq!(deposit.status, TransactionStatus::Cancelled);
+    assert_eq!(unrecovered, 0);
+    assert_eq!(ledger::balance_of(&Account::of(deposit.user_principal), Currency::UGX), 0);
+    let balance = agent_balance_of(deposit.agent_principal);
+    assert_eq!(balance.total_deposits, 0);
+    assert_eq!(balance.total_commission_owed, 0);
+}
+
+#[test]
+fn test_confirmed_dispute_upheld_does_not_double_count() {
+    let mut deposit = sample_deposit(1);
+    update_agent_balance(&deposit);
+    deposit.status = TransactionStatus::Disputed;
+    
+    settle_disputed_deposit(&mut deposit, &TransactionStatus::Confirmed, &DisputeOutcome::Confirmed, 0);
+    
+    assert_eq!(agent_balance_of(deposit.agent_principal).total_deposits, deposit.amount);
+}
+
+#[test]
+fn test_clawback_reports_spent_credit() {
+    let mut deposit = sample_deposit(1);
+    let user = Account::of(deposit.user_principal);
+    credit_user(&deposit, 0);
+    
+    // User spends 60,000 of the 89,500 credit before the dispute
+    ledger::transfer(user, Account::of(Principal::from_slice(&[9])), Currency::UGX, 60_000, None, 1).unwrap();
+    deposit.status = TransactionStatus::Disputed;
+    
+    let unrecovered = settle_disputed_deposit(&mut deposit, &TransactionStatus::Confirmed, &DisputeOutcome::Cancelled, 2);
+    
+    assert_eq!(unrecovered, 60_000);
+    assert_eq!(ledger::balance_of(&user, Currency::UGX), 0);
+}
+
+// ============================================================================
+// FIAT LEDGER TESTS
+// ============================================================================
+
+#[test]
+fn test_confirmed_deposit_credit_excludes_fees() {
+    let deposit = sample_deposit(1);
+    assert_eq!(user_credit(&deposit), 89_500);
+}
+
+#[test]
+fn test_ledger_mint_and_transfer() {
+    let alice = Account::of(Principal::from_slice(&[1]));
+    let bob = Account::of(Principal::from_slice(&[2]));
+    
+    ledger::mint(alice, Currency::UGX, 10_000, None, 0);
+    let block = ledger::transfer(alice, bob, Currency::UGX, 4_000, None, 1).unwrap();
+    
+    assert_eq!(block, 1);
+    assert_eq!(ledger::balance_of(&alice, Currency::UGX), 6_000);
+    assert_eq!(ledger::balance_of(&bob, Currency::UGX), 4_000);
+    
+    // Balances are per currency
+    assert_eq!(ledger::balance_of(&alice, Currency::KES), 0);
+}
+
+#[test]
+fn test_ledger_rejects_overdraft() {
+    let alice = Account::of(Principal::from_slice(&[1]));
+    let bob = Account::of(Principal::from_slice(&[2]));
+    ledger::mint(alice, Currency::UGX, 1_000, None, 0);
+    
+    assert_eq!(
+        ledger::transfer(alice, bob, Currency::UGX, 1_001, None, 1),
+        Err(TransferError::InsufficientFunds { balance: 1_000 })
+    );
+    assert_eq!(ledger::transfer(alice, bob, Currency::UGX, 0, None, 1), Err(TransferError::InvalidAmount));
+}
+
+#[test]
+fn test_ledger_transfer_from_spends_allowance() {
+    let alice = Account::of(Principal::from_slice(&[1]));
+    let spender = Account::of(Principal::from_slice(&[2]));
+    let escrow = Account { owner: spender.owner, subaccount: Some([7; 32]) };
+    ledger::mint(alice, Currency::UGX, 10_000, None, 0);
+    
+    // Nothing can be moved without an allowance
+    assert_eq!(
+        ledger::transfer_from(spender, alice, escrow, Currency::UGX, 1_000, None, 1),
+        Err(TransferError::InsufficientAllowance { allowance: 0 })
+    );
+    
+    ledger::approve(alice, spender, Currency::UGX, 3_000, None, None, 1).unwrap();
+    ledger::transfer_from(spender, alice, escrow, Currency::UGX, 2_000, None, 2).unwrap();
+    
+    assert_eq!(ledger::balance_of(&alice, Currency::UGX), 8_000);
+    assert_eq!(ledger::balance_of(&escrow, Currency::UGX), 2_000);
+    assert_eq!(ledger::allowance(&alice, &spender, Currency::UGX, 2).allowance, 1_000);
+    assert_eq!(
+        ledger::transfer_from(spender, alice, escrow, Currency::UGX, 1_001, None, 3),
+        Err(TransferError::InsufficientAllowance { allowance: 1_000 })
+    );
+    
+    // Allowances are per currency
+    assert_eq!(ledger::allowance(&alice, &spender, Currency::KES, 2).allowance, 0);
+}
+
+#[test]
+fn test_ledger_allowance_expires() {
+    let alice = Account::of(Principal::from_slice(&[1]));
+    let spender = Account::of(Principal::from_slice(&[2]));
+    ledger::mint(alice, Currency::UGX, 10_000, None, 0);
+    
+    assert_eq!(
+        ledger::approve(alice, spender, Currency::UGX, 5_000, Some(10), None, 10),
+        Err(TransferError::Expired { ledger_time: 10 })
+    );
+    
+    ledger::approve(alice, spender, Currency::UGX, 5_000, Some(20), None, 10).unwrap();
+    assert_eq!(ledger::allowance(&alice, &spender, Currency::UGX, 19).allowance, 5_000);
+    assert_eq!(
+        ledger::transfer_from(spender, alice, spender, Currency::UGX, 1_000, None, 20),
+        Err(TransferError::InsufficientAllowance { allowance: 0 })
+    );
+}
+
+#[test]
+fn test_default_subaccount_is_same_account() {
+    let owner = Principal::from_slice(&[1]);
+    ledger::mint(Account::of(owner), Currency::UGX, 500, None, 0);
+    
+    let explicit = Account { owner, subaccount: Some([0; 32]) };
+    assert_eq!(ledger::balance_of(&explicit, Currency::UGX), 500);
+}
+
+#[test]
+fn test_ledger_history_pages_newest_first() {
+    let alice = Account::of(Principal::from_slice(&[1]));
+    let bob = Account::of(Principal::from_slice(&[2]));
+    ledger::mint(alice, Currency::UGX, 10_000, None, 0);
+    for i in 0..5 {
+        ledger::transfer(alice, bob, Currency::UGX, 100, None, i + 1).unwrap();
+    }
+    
+    let page1 = ledger::account_history(&alice, Currency::UGX, None, 4);
+    let indexes: Vec<u64> = page1.iter().map(|b| b.index).collect();
+    assert_eq!(indexes, vec![5, 4, 3, 2]);
+    
+    let page2 = ledger::account_history(&alice, Currency::UGX, Some(2), 4);
+    let indexes: Vec<u64> = page2.iter().map(|b| b.index).collect();
+    assert_eq!(indexes, vec![1, 0]);
+    
+    // Bob only sees the transfers
+    assert_eq!(ledger::account_history(&bob, Currency::UGX, None, 10).len(), 5);
+}
+
+#[test]
+fn test_ledger_history_orders_past_256_blocks() {
+    let alice = Account::of(Principal::from_slice(&[1]));
+    for i in 0..300 {
+        ledger::mint(alice, Currency::UGX, 1, None, i);
+    }
+    
+    let page = ledger::account_history(&alice, Currency::UGX, None, 3);
+    let indexes: Vec<u64> = page.iter().map(|b| b.index).collect();
+    assert_eq!(indexes, vec![299, 298, 297]);
+    
+    let page = ledger::account_history(&alice, Currency::UGX, Some(257), 3);
+    let indexes: Vec<u64> = page.iter().map(|b| b.index).collect();
+    assert_eq!(indexes, vec![256, 255, 254]);
+}
+
+// ============================================================================
+// FEE SPLIT TESTS
+// ============================================================================
+
+#[test]
+fn test_fees_use_their_own_rates() {
+    let config = currency_config(Currency::UGX);
+    let (platform_fee, agent_commission) = calculate_fees(100_000, &config);
+    
+    assert_eq!(platform_fee, (100_000 * config.platform_fee_basis_points) / 10000);
+    assert_eq!(agent_commission, (100_000 * config.agent_commission_basis_points) / 10000);
+    assert_eq!(platform_fee, 500);
+    assert_eq!(agent_commission, 10_000);
+}
+
+#[test]
+fn test_agent_balance_splits_fees_and_commission() {
+    let deposit = sample_deposit(1);
+    update_agent_balance(&deposit);
+    update_agent_balance(&sample_deposit(2));
+    
+    let balance = agent_balance_of(deposit.agent_principal);
+    assert_eq!(balance.total_platform_fees, 1_000);
+    assert_eq!(balance.total_commission_owed, 20_000);
+    assert_eq!(total_platform_fees(), BTreeMap::from([(Currency::UGX, 1_000)]));
+    assert_eq!(total_agent_commissions(), BTreeMap::from([(Currency::UGX, 20_000)]));
+}
+
+#[test]
+fn test_agent_balance_is_kept_per_currency() {
+    let ugx = sample_deposit(1);
+    let mut kes = sample_deposit(2);
+    kes.currency = Currency::KES;
+    kes.amount = 5_000;
+    kes.platform_fee = 25;
+    kes.agent_commission = 500;
+    update_agent_balance(&ugx);
+    update_agent_balance(&kes);
+    
+    let balance = AGENT_BALANCES.with(|b| b.borrow().get(&ugx.agent_principal)).unwrap();
+    assert_eq!(balance.currencies.len(), 2);
+    assert_eq!(agent_balance_of(ugx.agent_principal).total_deposits, 100_000);
+    assert_eq!(
+        total_platform_fees(),
+        BTreeMap::from([(Currency::UGX, 500), (Currency::KES, 25)])
+    );
+    
+    reverse_agent_balance(&kes);
+    assert_eq!(total_agent_commissions()[&Currency::KES], 0);
+    assert_eq!(total_agent_commissions()[&Currency::UGX], 10_000);
+}
+
+#[test]
+fn test_v1_agent_balance_is_reclassified() {
+    use ic_stable_structures::Storable;
+    use std::borrow::Cow;
+    
+    // Mirrors the schema v1 envelope in storage.rs
+    #[derive(CandidType)]
+    struct LegacyAgentBalance {
+        principal: Principal,
+        total_deposits: u64,
+        total_commission_owed: u64,
+        total_commission_paid: u64,
+        last_settlement_date: Option<u64>,
+    }
+    
+    #[derive(CandidType)]
+    enum StoredAgentBalance {
+        V1(LegacyAgentBalance),
+    }
+    
+    let bytes = candid::encode_one(StoredAgentBalance::V1(LegacyAgentBalance {
+        principal: Principal::from_slice(&[2]),
+        total_deposits: 2_000_000,
+        total_commission_owed: 10_000,
+        total_commission_paid: 4_000,
+        last_settlement_date: None,
+    })).unwrap();
+    
+    let migrated = AgentBalance::from_bytes(Cow::Owned(bytes));
+    assert_eq!(migrated.currencies.len(), 1);
+    let ugx = &migrated.currencies[0];
+    assert_eq!(ugx.currency, Currency::UGX);
+    assert_eq!(ugx.total_platform_fees, 10_000);
+    assert_eq!(ugx.total_commission_owed, 4_000);
+    assert_eq!(ugx.total_commission_paid, 4_000);
+}
+
+// ============================================================================
+// SETTLEMENT PERIOD TESTS
+// ============================================================================
+
+// 1_700_000_000_000_000_000 ns falls on 2023-11-14
+const NOV_2023: &str = "2023-11";
+const AFTER_NOV_2023: u64 = 1_702_000_000_000_000_000;
+
+fn store_confirmed(id: u64, confirmed_at: u64) -> DepositTransaction {
+    let mut deposit = sample_deposit(id);
+    mark_confirmed(&mut deposit, confirmed_at);
+    DEPOSITS.with(|d| d.borrow_mut().insert(id, deposit.clone()));
+    deposit
+}
+
+#[test]
+fn test_month_parsing() {
+    assert_eq!(Month::parse("2024-02"), Some(Month { year: 2024, month: 2 }));
+    assert_eq!(Month::parse("2024-13"), None);
+    assert_eq!(Month::parse("2024-00"), None);
+    assert_eq!(Month::parse("2024-2"), None);
+    assert_eq!(Month::parse("24-02"), None);
+    assert_eq!(Month::parse("2024/02"), None);
+    assert_eq!(Month::parse("+024-02"), None);
+    assert_eq!(Month::parse("1969-12"), None);
+}
+
+#[test]
+fn test_month_bounds() {
+    let jan_1970 = Month::parse("1970-01").unwrap();
+    assert_eq!(jan_1970.start_nanos(), 0);
+    assert_eq!(jan_1970.end_nanos(), 31 * NANOS_PER_DAY);
+    
+    // Leap and non-leap February
+    let feb_2024 = Month::parse("2024-02").unwrap();
+    assert_eq!(feb_2024.end_nanos() - feb_2024.start_nanos(), 29 * NANOS_PER_DAY);
+    let feb_2023 = Month::parse("2023-02").unwrap();
+    assert_eq!(feb_2023.end_nanos() - feb_2023.start_nanos(), 28 * NANOS_PER_DAY);
+    
+    // December rolls into the next year
+    let dec = Month::parse("2023-12").unwrap();
+    assert_eq!(dec.next(), Month { year: 2024, month: 1 });
+    
+    // 2023-11-01T00:00:00Z
+    assert_eq!(Month::parse(NOV_2023).unwrap().start_nanos(), 1_698_796_800_000_000_000);
+}
+
+#[test]
+fn test_settlement_counts_only_deposits_confirmed_in_period() {
+    let nov = Month::parse(NOV_2023).unwrap();
+    store_confirmed(1, nov.start_nanos());
+    store_confirmed(2, nov.end_nanos() - 1);
+    store_confirmed(3, nov.end_nanos()); // December
+    store_confirmed(4, nov.start_nanos() - 1); // October
+    
+    let settlements = generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    
+    assert_eq!(settlements.len(), 1);
+    assert_eq!(settlements[0].total_commission, 20_000);
+}
+
+#[test]
+fn test_settlements_are_split_by_currency() {
+    let nov = Month::parse(NOV_2023).unwrap();
+    let ugx = store_confirmed(1, nov.start_nanos());
+    let mut kes = sample_deposit(2);
+    kes.currency = Currency::KES;
+    kes.agent_commission = 500;
+    mark_confirmed(&mut kes, nov.start_nanos() + 1);
+    DEPOSITS.with(|d| d.borrow_mut().insert(kes.id, kes.clone()));
+    
+    let settlements = generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    
+    assert_eq!(settlements.len(), 2);
+    let ugx_id = find_settlement_id(NOV_2023, ugx.agent_principal, Currency::UGX).unwrap();
+    let kes_id = find_settlement_id(NOV_2023, kes.agent_principal, Currency::KES).unwrap();
+    assert_eq!(settlement(ugx_id).total_commission, 10_000);
+    assert_eq!(settlement(kes_id).total_commission, 500);
+    assert!(find_settlement_id(NOV_2023, ugx.agent_principal, Currency::TZS).is_err());
+}
+
+#[test]
+fn test_settlement_rejects_bad_or_open_periods() {
+    assert_eq!(
+        generate_settlements("2023-1", Principal::anonymous(), AFTER_NOV_2023).err(),
+        Some(DepositError::InvalidMonth { month: "2023-1".to_string() })
+    );
+    
+    let nov = Month::parse(NOV_2023).unwrap();
+    assert_eq!(
+        generate_settlements(NOV_2023, Principal::anonymous(), nov.end_nanos() - 1).err(),
+        Some(DepositError::PeriodNotEnded { month: NOV_2023.to_string() })
+    );
+}
+
+#[test]
+fn test_settlement_rerun_is_idempotent() {
+    let nov = Month::parse(NOV_2023).unwrap();
+    store_confirmed(1, nov.start_nanos());
+    
+    let first = generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    let second = generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023 + 1).unwrap();
+    
+    assert_eq!(first.len(), 1);
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0].total_commission, 10_000);
+    assert_eq!(SETTLEMENTS.with(|s| s.borrow().len()), 1);
+    
+    let period = SETTLEMENT_PERIODS.with(|p| p.borrow().get(&NOV_2023.to_string())).unwrap();
+    assert_eq!(period.generated_at, AFTER_NOV_2023);
+    assert_eq!(period.last_generated_at, AFTER_NOV_2023 + 1);
+}
+
+#[test]
+fn test_paid_settlement_is_not_recomputed() {
+    let nov = Month::parse(NOV_2023).unwrap();
+    let deposit = store_confirmed(1, nov.start_nanos());
+    generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    
+    SETTLEMENTS.with(|s| {
+        let mut setts = s.borrow_mut();
+        let mut settlement = setts.get(&1).unwrap();
+        settlement.paid = true;
+        setts.insert(1, settlement);
+    });
+    
+    // A late-resolved dispute adds commission to the month
+    store_confirmed(2, nov.start_nanos() + 1);
+    let settlements = generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    
+    assert_eq!(settlements.len(), 1);
+    assert_eq!(settlements[0].agent_principal, deposit.agent_principal);
+    assert_eq!(settlements[0].total_commission, 10_000);
+}
+
+#[test]
+fn test_closed_period_is_frozen() {
+    let nov = Month::parse(NOV_2023).unwrap();
+    store_confirmed(1, nov.start_nanos());
+    
+    assert!(close_settlement_period(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).is_err());
+    
+    generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    let closed = close_settlement_period(NOV_2023, Principal::anonymous(), AFTER_NOV_2023 + 1).unwrap();
+    assert_eq!(closed.closed_at, Some(AFTER_NOV_2023 + 1));
+    assert_eq!(
+        close_settlement_period(NOV_2023, Principal::anonymous(), AFTER_NOV_2023 + 2).err(),
+        Some(DepositError::PeriodAlreadyClosed { month: NOV_2023.to_string() })
+    );
+    
+    store_confirmed(2, nov.start_nanos() + 1);
+    let settlements = generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023 + 3).unwrap();
+    assert_eq!(settlements[0].total_commission, 10_000);
+}
+
+// ============================================================================
+// ON-CHAIN SETTLEMENT PAYOUT TESTS
+// ============================================================================
+
+fn payout_config() -> SettlementPayoutConfig {
+    test_config().deposit.settlement_payout.expect("payout configured")
+}
+
+fn ckusdc_ledger() -> Principal {
+    Principal::from_text(&payout_config().ledger).unwrap()
+}
+
+/// Generate November's settlement for the sample agent and return its id.
+fn unpaid_settlement() -> u64 {
+    let nov = Month::parse(NOV_2023).unwrap();
+    let deposit = store_confirmed(1, nov.start_nanos());
+    update_agent_balance(&deposit);
+    generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    find_settlement_id(NOV_2023, deposit.agent_principal, deposit.currency).unwrap()
+}
+
+fn settlement(id: u64) -> MonthlySettlement {
+    SETTLEMENTS.with(|s| s.borrow().get(&id)).unwrap()
+}
+
+#[test]
+fn test_settlement_token_amount_conversion() {
+    let config = SettlementPayoutConfig {
+        ledger: String::new(),
+        decimals: 6,
+        units_per_token: BTreeMap::from([("UGX".to_string(), 3700), ("KES".to_string(), 129), ("GHS".to_string(), 0)]),
+    };
+    
+    // 37,000 UGX = 10 USDC = 10_000_000 units
+    assert_eq!(settlement_token_amount(37_000, Currency::UGX, &config), Ok(10_000_000));
+    // Rounds down
+    assert_eq!(settlement_token_amount(1, Currency::UGX, &config), Ok(270));
+    // 1,290 KES = 10 USDC
+    assert_eq!(settlement_token_amount(1_290, Currency::KES, &config), Ok(10_000_000));
+    
+    // Missing or zero rates are refused
+    assert!(settlement_token_amount(1, Currency::GHS, &config).is_err());
+    assert!(settlement_token_amount(1, Currency::NGN, &config).is_err());
+}
+
+#[test]
+fn test_successful_payout_marks_settlement_paid() {
+    let id = unpaid_settlement();
+    let payout = begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 10).unwrap();
+    assert_eq!(payout.status, PayoutStatus::InFlight);
+    assert!(!settlement(id).paid);
+    
+    let paid = finish_settlement_payout(id, TransferOutcome::Completed { block_index: 77 }, 11).unwrap();
+    
+    assert!(paid.paid);
+    assert_eq!(paid.paid_date, Some(11));
+    assert_eq!(paid.payout_block_index, Some(77));
+    assert_eq!(agent_balance_of(paid.agent_principal).total_commission_paid, 10_000);
+    assert!(begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 12).is_err());
+}
+
+#[test]
+fn test_rejected_payout_is_not_marked_paid() {
+    let id = unpaid_settlement();
+    begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 10).unwrap();
+    
+    let result = finish_settlement_payout(id, TransferOutcome::Rejected { reason: "InsufficientFunds".into() }, 11);
+    
+    assert_eq!(result.err(), Some(DepositError::PayoutFailed { reason: "InsufficientFunds".into() }));
+    let stored = settlement(id);
+    assert!(!stored.paid);
+    assert_eq!(stored.payout_block_index, None);
+    assert!(matches!(stored.payout.unwrap().status, PayoutStatus::Failed { .. }));
+    
+    // A fresh attempt gets a new created_at_time
+    let retry = begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 20).unwrap();
+    assert_eq!(retry.created_at_time, 20);
+}
+
+#[test]
+fn test_unknown_payout_outcome_retries_with_same_arguments() {
+    let id = unpaid_settlement();
+    let first = begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 10).unwrap();
+    
+    assert_eq!(
+        finish_settlement_payout(id, TransferOutcome::Unknown { reason: "timeout".into() }, 11).err(),
+        Some(DepositError::PayoutOutcomeUnknown { reason: "timeout".into() })
+    );
+    assert!(!settlement(id).paid);
+    
+    let retry = begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 30).unwrap();
+    assert_eq!(retry.created_at_time, first.created_at_time);
+    assert_eq!(retry.amount, first.amount);
+    
+    // The ledger reports the first attempt as a duplicate
+    let outcome = icrc1::classify(Err(icrc1::Icrc1TransferError::Duplicate { duplicate_of: candid::Nat::from(5u64) }));
+    let paid = finish_settlement_payout(id, outcome, 31).unwrap();
+    assert_eq!(paid.payout_block_index, Some(5));
+    
+    // A late reply for the same transfer does not pay twice
+    finish_settlement_payout(id, TransferOutcome::Completed { block_index: 5 }, 32).unwrap();
+    assert_eq!(agent_balance_of(paid.agent_principal).total_commission_paid, 10_000);
+}
+
+#[test]
+fn test_payout_past_dedup_window_is_resolved_against_the_ledger() {
+    let id = unpaid_settlement();
+    let first = begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 10).unwrap();
+    
+    let outcome = icrc1::classify(Err(icrc1::Icrc1TransferError::TooOld));
+    assert!(matches!(finish_settlement_payout(id, outcome, 11), Err(DepositError::PayoutOutcomeUnknown { .. })));
+    assert!(payout_in_flight(&settlement(id)));
+    
+    // Not found on the ledger: a fresh attempt may be sent
+    let failed = resolve_payout(id, None, 12).unwrap();
+    assert!(!failed.paid);
+    assert!(matches!(failed.payout.unwrap().status, PayoutStatus::Failed { .. }));
+    assert_eq!(resolve_payout(id, None, 13).err(), Some(DepositError::PayoutNotInFlight));
+    let retry = begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 20).unwrap();
+    assert_ne!(retry.created_at_time, first.created_at_time);
+    
+    // Found on the ledger: paid once
+    let paid = resolve_payout(id, Some(9), 21).unwrap();
+    assert_eq!(paid.payout_block_index, Some(9));
+    assert_eq!(agent_balance_of(paid.agent_principal).total_commission_paid, 10_000);
+    assert_eq!(resolve_payout(id, Some(9), 22).err(), Some(DepositError::PayoutNotInFlight));
+}
+
+#[test]
+fn test_in_flight_settlement_is_not_recomputed() {
+    let id = unpaid_settlement();
+    begin_settlement_payout(id, ckusdc_ledger(), &payout_config(), 10).unwrap();
+    
+    let nov = Month::parse(NOV_2023).unwrap();
+    store_confirmed(2, nov.start_nanos() + 1);
+    generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    
+    assert_eq!(settlement(id).total_commission, 10_000);
+}
+
+// ============================================================================
+// DEPOSIT HISTORY QUERY TESTS
+// ============================================================================
+
+/// Store deposits 1..=count for the sample user/agent, one second apart.
+fn store_history(count: u64) {
+    for id in 1..=count {
+        let mut deposit = sample_deposit(id);
+        deposit.timestamp += id * 1_000_000_000;
+        if id % 2 == 0 {
+            deposit.status = TransactionStatus::Confirmed;
+        }
+        DEPOSITS.with(|d| d.borrow_mut().insert(id, deposit.clone()));
+        index_deposit(&deposit);
+    }
+}
+
+fn ids(page: &DepositPage) -> Vec<u64> {
+    page.deposits.iter().map(|d| d.id).collect()
+}
+
+#[test]
+fn test_deposit_history_pages_newest_first() {
+    store_history(5);
+    let user = Principal::from_slice(&[1]);
+    
+    let page = query_deposits(&USER_DEPOSITS, user, &DepositQuery { limit: Some(2), ..Default::default() });
+    assert_eq!(ids(&page), vec![5, 4]);
+    
+    let page = query_deposits(&USER_DEPOSITS, user, &DepositQuery {
+        limit: Some(2),
+        cursor: page.next_cursor,
+        ..Default::default()
+    });
+    assert_eq!(ids(&page), vec![3, 2]);
+    
+    let page = query_deposits(&USER_DEPOSITS, user, &DepositQuery {
+        limit: Some(2),
+        cursor: page.next_cursor,
+        ..Default::default()
+    });
+    assert_eq!(ids(&page), vec![1]);
+    assert_eq!(page.next_cursor, None);
+}
+
+#[test]
+fn test_deposit_history_oldest_first_with_status_filter() {
+    store_history(6);
+    let agent = Principal::from_slice(&[2]);
+    let query = DepositQuery {
+        status: Some(TransactionStatus::Confirmed),
+        order: Some(SortOrder::OldestFirst),
+        limit: Some(2),
+        ..Default::default()
+    };
+    
+    let page = query_deposits(&AGENT_DEPOSITS, agent, &query);
+    assert_eq!(ids(&page), vec![2, 4]);
+    
+    let page = query_deposits(&AGENT_DEPOSITS, agent, &DepositQuery { cursor: page.next_cursor, ..query });
+    assert_eq!(ids(&page), vec![6]);
+    assert_eq!(page.next_cursor, None);
+}
+
+#[test]
+fn test_deposit_history_time_range() {
+    store_history(5);
+    let user = Principal::from_slice(&[1]);
+    let base = sample_deposit(0).timestamp;
+    
+    // [2s, 4s) covers deposits 2 and 3
+    let page = query_deposits(&USER_DEPOSITS, user, &DepositQuery {
+        from: Some(base + 2_000_000_000),
+        to: Some(base + 4_000_000_000),
+        ..Default::default()
+    });
+    assert_eq!(ids(&page), vec![3, 2]);
+}
+
+#[test]
+fn test_deposit_history_is_scoped_to_party() {
+    store_history(3);
+    let stranger = Principal::from_slice(&[1, 1]);
+    
+    assert!(query_deposits(&USER_DEPOSITS, stranger, &DepositQuery::default()).deposits.is_empty());
+    // The agent index does not leak into the user index
+    assert!(query_deposits(&USER_DEPOSITS, Principal::from_slice(&[2]), &DepositQuery::default()).deposits.is_empty());
+}
+
+#[test]
+fn test_party_deposit_key_sorts_by_time() {
+    use ic_stable_structures::Storable;
+    
+    let party = Principal::from_slice(&[7; 29]);
+    let early = PartyDepositKey { party, timestamp: 255, id: 9 };
+    let late = PartyDepositKey { party, timestamp: 256, id: 1 };
+    
+    assert!(early.to_bytes() < late.to_bytes());
+    assert_eq!(PartyDepositKey::from_bytes(late.to_bytes()), late);
+}
+
+// ============================================================================
+// ACCESS CONTROL TESTS
+// ============================================================================
+
+#[test]
+fn test_parties_can_read_their_own_records() {
+    load_config();
+    let user = Principal::from_slice(&[1]);
+    let agent = Principal::from_slice(&[2]);
+    
+    assert!(require_party_or_staff(user, &[user, agent]).is_ok());
+    assert!(require_party_or_staff(agent, &[user, agent]).is_ok());
+    assert_eq!(
+        require_party_or_staff(Principal::from_slice(&[3]), &[user, agent]),
+        Err(DepositError::Unauthorized)
+    );
+}
+
+#[test]
+fn test_anonymous_is_never_a_party() {
+    load_config();
+    let anonymous = Principal::anonymous();
+    assert_eq!(require_party_or_staff(anonymous, &[anonymous]), Err(DepositError::Unauthorized));
+}
+
+#[test]
+fn test_staff_roles_grant_fleet_reads() {
+    load_config();
+    let auditor = Principal::from_slice(&[8]);
+    let stranger = Principal::from_slice(&[9]);
+    
+    apply_init_args(Some(DepositInitArgs { admins: vec![], auditors: vec![auditor] }));
+    
+    assert_eq!(staff_role(auditor), Some(StaffRole::Auditor));
+    assert!(require_staff(auditor).is_ok());
+    assert!(require_party_or_staff(auditor, &[stranger]).is_ok());
+    assert_eq!(require_staff(stranger), Err(DepositError::Unauthorized));
+    
+    // The company wallet is always an admin
+    let company = get_company_wallet().unwrap();
+    assert_eq!(staff_role(company), Some(StaffRole::Admin));
+}
+
+#[test]
+fn test_configured_withdrawal_canister_reads_deposit_history() {
+    load_config();
+    let withdrawal_canister = Principal::from_slice(&[5]);
+    let user = Principal::from_slice(&[1]);
+    let mut config = test_config();
+    config.deposit.withdrawal_canister = withdrawal_canister.to_text();
+    apply_config(config, Principal::from_slice(&[9]), 0);
+    
+    assert!(require_history_reader(withdrawal_canister, user).is_ok());
+    assert!(require_history_reader(user, user).is_ok());
+    assert_eq!(require_history_reader(Principal::from_slice(&[6]), user), Err(DepositError::Unauthorized));
+    
+    apply_config(test_config(), Principal::from_slice(&[9]), 0);
+    assert_eq!(require_history_reader(withdrawal_canister, user), Err(DepositError::Unauthorized));
+}
+
+// ============================================================================
+// AGENT REGISTRY TESTS
+// ============================================================================
+
+#[test]
+fn test_unconfigured_agent_registry_fails_closed() {
+    load_config();
+    if test_config().agent_registry.canister_id.is_empty() {
+        assert!(agent_registry_id().is_err());
+    } else {
+        assert!(agent_registry_id().is_ok());
+    }
+}
+
+#[test]
+fn test_agent_ineligibility_decodes_from_registry_reply() {
+    // The registry replies with `Result<(), AgentIneligible>`
+    let reply: Result<(), AgentIneligible> = Err(AgentIneligible::Suspended);
+    let bytes = candid::encode_one(&reply).unwrap();
+    
+    let decoded: Result<(), AgentIneligible> = candid::decode_one(&bytes).unwrap();
+    assert_eq!(decoded, Err(AgentIneligible::Suspended));
+}
+
+// ============================================================================
+// CONFIGURATION TESTS
+// ============================================================================
+
+#[test]
+fn test_shipped_config_passes_validation() {
+    assert_eq!(validate_config(&test_config()), Ok(()));
+}
+
+#[test]
+fn test_config_validation_rejects_excessive_fees() {
+    let mut config = test_config();
+    let ugx = config.deposit.currencies.get_mut("UGX").unwrap();
+    ugx.agent_commission_basis_points = MAX_TOTAL_FEE_BPS;
+    ugx.platform_fee_basis_points = 1;
+    
+    assert!(validate_config(&config).unwrap_err().contains("UGX"));
+}
+
+#[test]
+fn test_config_validation_rejects_bad_principals() {
+    let mut config = test_config();
+    config.company_wallet.principal = "not-a-principal".to_string();
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.company_wallet.principal = Principal::anonymous().to_text();
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.governance.sns_governance = "bogus".to_string();
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn test_config_validation_rejects_bad_limits_and_currencies() {
+    let mut config = test_config();
+    let kes = config.deposit.currencies.get_mut("KES").unwrap();
+    kes.min_deposit = kes.max_deposit + 1;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    let ugx = config.deposit.currencies["UGX"].clone();
+    config.deposit.currencies.insert("XYZ".to_string(), ugx);
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.deposit.code_validity_hours = 0;
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn test_config_change_is_recorded_and_survives_reload() {
+    load_config();
+    let governor = Principal::from_slice(&[11]);
+    let old = current_config();
+    
+    let mut new = old.clone();
+    new.deposit.currencies.get_mut("UGX").unwrap().platform_fee_basis_points = 75;
+    
+    let change = apply_config(new.clone(), governor, 1_000);
+    assert_eq!(change.id, 1);
+    assert_eq!(change.changed_by, governor);
+    assert_eq!(change.old, old);
+    assert_eq!(change.new, new);
+    
+    // The newest history entry wins over the TOML defaults, e.g. after an upgrade
+    load_config();
+    assert_eq!(current_config(), new);
+    
+    let second = apply_config(old.clone(), governor, 2_000);
+    assert_eq!(second.id, 2);
+    assert_eq!(second.old, new);
+}
+
+// ============================================================================
+// AUDIT LOG TESTS
+// ============================================================================
+
+#[test]
+fn test_expiry_is_audited() {
+    let actor = Principal::from_slice(&[12]);
+    let deposit = sample_deposit(1);
+    store_pending(&deposit);
+    
+    expire_stale_deposits(actor, deposit.expires_at);
+    
+    let events = audit::events(None, 10);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor, actor);
+    assert_eq!(events[0].action, AuditAction::DepositExpired);
+    assert_eq!(events[0].entity, AuditEntity::Deposit { id: 1 });
+    assert_eq!(events[0].before.as_deref(), Some("Pending"));
+    assert_eq!(events[0].after.as_deref(), Some("Expired"));
+    assert_eq!(events[0].timestamp, deposit.expires_at);
+}
+
+#[test]
+fn test_audit_events_page_newest_first() {
+    for id in 0..5 {
+        audit::record(
+            Principal::anonymous(),
+            AuditAction::DepositCreated,
+            AuditEntity::Deposit { id },
+            Transition::status(None, &TransactionStatus::Pending),
+            id,
+        );
+    }
+    
+    let first: Vec<u64> = audit::events(None, 2).iter().map(|e| e.id).collect();
+    assert_eq!(first, vec![4, 3]);
+    
+    let second: Vec<u64> = audit::events(Some(3), 2).iter().map(|e| e.id).collect();
+    assert_eq!(second, vec![2, 1]);
+    
+    let last: Vec<u64> = audit::events(Some(1), 2).iter().map(|e| e.id).collect();
+    assert_eq!(last, vec![0]);
+}
+
+#[test]
+fn test_settlement_generation_and_close_are_audited() {
+    let nov = Month::parse(NOV_2023).unwrap();
+    store_confirmed(1, nov.start_nanos());
+    
+    generate_settlements(NOV_2023, Principal::anonymous(), AFTER_NOV_2023).unwrap();
+    close_settlement_period(NOV_2023, Principal::anonymous(), AFTER_NOV_2023 + 1).unwrap();
+    
+    let actions: Vec<AuditAction> = audit::events(None, 10).into_iter().map(|e| e.action).collect();
+    assert_eq!(actions, vec![AuditAction::PeriodClosed, AuditAction::SettlementGenerated]);
+}
+
+// ============================================================================
+// AGENT REPUTATION TESTS
+// ============================================================================
+
+const SECOND: u64 = 1_000_000_000;
+
+fn store_outcome(id: u64, status: TransactionStatus) -> DepositTransaction {
+    let mut deposit = sample_deposit(id);
+    if status == TransactionStatus::Confirmed {
+        let confirmed_at = deposit.timestamp + 60 * id * SECOND;
+        mark_confirmed(&mut deposit, confirmed_at);
+    }
+    deposit.status = status;
+    store_deposit(&deposit);
+    index_deposit(&deposit);
+    deposit
+}
+
+#[test]
+fn test_reputation_rates_exclude_pending_requests() {
+    store_outcome(1, TransactionStatus::Confirmed);
+    store_outcome(2, TransactionStatus::Confirmed);
+    store_outcome(3, TransactionStatus::Cancelled);
+    store_outcome(4, TransactionStatus::Expired);
+    store_outcome(5, TransactionStatus::Pending);
+    
+    let reputation = agent_reputation(Principal::from_slice(&[2]));
+    
+    assert_eq!(reputation.total_requests, 5);
+    assert_eq!(reputation.pending, 1);
+    assert_eq!(reputation.cancellation_rate_bps, 2_500);
+    assert_eq!(reputation.expired_ratio_bps, 2_500);
+    assert_eq!(reputation.dispute_rate_bps, 0);
+    // Confirmed after 60s and 120s
+    assert_eq!(reputation.avg_confirmation_latency_secs, Some(90));
+    assert_eq!(reputation.average_rating_x100, None);
+}
+
+#[test]
+fn test_disputed_confirmation_is_not_counted_as_latency() {
+    store_outcome(1, TransactionStatus::Confirmed);
+    store_outcome(10, TransactionStatus::Pending);
+    DISPUTES.with(|d| d.borrow_mut().insert(10, DepositDispute {
+        deposit_id: 10,
+        reason: "Agent denies receiving cash".to_string(),
+        evidence_note: String::new(),
+        opened_by: Principal::from_slice(&[9]),
+        opened_at: sample_deposit(10).timestamp,
+        status_before_dispute: TransactionStatus::Pending,
+        outcome: Some(DisputeOutcome::Confirmed),
+        resolution_note: None,
+        resolved_by: None,
+        resolved_at: None,
+        unrecovered: None,
+    }));
+    store_outcome(10, TransactionStatus::Confirmed);
+    
+    let reputation = agent_reputation(Principal::from_slice(&[2]));
+    
+    assert_eq!(reputation.disputed, 1);
+    assert_eq!(reputation.dispute_rate_bps, 5_000);
+    assert_eq!(reputation.avg_confirmation_latency_secs, Some(60));
+}
+
+#[test]
+fn test_only_the_user_rates_a_confirmed_deposit_once() {
+    let confirmed = store_outcome(1, TransactionStatus::Confirmed);
+    store_outcome(2, TransactionStatus::Confirmed);
+    store_outcome(3, TransactionStatus::Cancelled);
+    let user = confirmed.user_principal;
+    
+    assert_eq!(submit_rating(confirmed.agent_principal, 1, 5, None, 0), Err(DepositError::Unauthorized));
+    assert!(matches!(submit_rating(user, 1, 6, None, 0), Err(DepositError::InvalidInput { .. })));
+    assert!(matches!(submit_rating(user, 3, 4, None, 0), Err(DepositError::InvalidStatus { .. })));
+    
+    let rating = submit_rating(user, 1, 5, Some("Quick and friendly".to_string()), 0).unwrap();
+    assert_eq!(rating.agent, confirmed.agent_principal);
+    assert!(matches!(submit_rating(user, 1, 1, None, 0), Err(DepositError::InvalidInput { .. })));
+    submit_rating(user, 2, 4, Some("  ".to_string()), 0).unwrap();
+    
+    let reputation = agent_reputation(confirmed.agent_principal);
+    assert_eq!(reputation.rating_count, 2);
+    assert_eq!(reputation.average_rating_x100, Some(450));
+    assert_eq!(AGENT_RATINGS.with(|r| r.borrow().get(&2)).unwrap().comment, None);
+}
+
+#[test]
+fn test_reputation_follows_status_changes() {
+    let mut deposit = store_outcome(1, TransactionStatus::Pending);
+    assert_eq!(agent_reputation(deposit.agent_principal).pending, 1);
+    
+    let confirmed_at = deposit.timestamp + 30 * SECOND;
+    mark_confirmed(&mut deposit, confirmed_at);
+    store_deposit(&deposit);
+    deposit.status = TransactionStatus::Disputed;
+    store_deposit(&deposit);
+    
+    let reputation = agent_reputation(deposit.agent_principal);
+    assert_eq!((reputation.total_requests, reputation.pending, reputation.confirmed), (1, 0, 0));
+    assert_eq!(reputation.disputed, 1);
+    assert_eq!(reputation.avg_confirmation_latency_secs, None);
+}
+
+#[test]
+fn test_migration_tallies_existing_deposits() {
+    let mut deposit = sample_deposit(1);
+    let confirmed_at = deposit.timestamp + 60 * SECOND;
+    mark_confirmed(&mut deposit, confirmed_at);
+    DEPOSITS.with(|d| d.borrow_mut().insert(1, deposit.clone()));
+    AGENT_RATINGS.with(|r| r.borrow_mut().insert(1, AgentRating {
+        deposit_id: 1,
+        agent: deposit.agent_principal,
+        user: deposit.user_principal,
+        stars: 4,
+        comment: None,
+        rated_at: 0,
+    }));
+    SCHEMA_VERSION.with(|v| v.borrow_mut().set(6).unwrap());
+    
+    migrate_schema();
+    
+    let reputation = agent_reputation(deposit.agent_principal);
+    assert_eq!((reputation.total_requests, reputation.confirmed), (1, 1));
+    assert_eq!(reputation.avg_confirmation_latency_secs, Some(60));
+    assert_eq!(reputation.average_rating_x100, Some(400));
+}
+
+#[test]
+fn test_agent_without_requests_has_empty_reputation() {
+    store_outcome(1, TransactionStatus::Cancelled);
+    
+    let reputation = agent_reputation(Principal::from_slice(&[7]));
+    
+    assert_eq!(reputation.total_requests, 0);
+    assert_eq!(reputation.cancellation_rate_bps, 0);
+    assert_eq!(reputation.avg_confirmation_latency_secs, None);
+}
+
+// ============================================================================
+// FRAUD SCREENING TESTS
+// ============================================================================
+
+const UGX_NEAR_LIMIT: u64 = 9_500_000; // max_deposit is 10,000,000
+
+fn store_request(id: u64, amount: u64, timestamp: u64) -> DepositTransaction {
+    let mut deposit = sample_deposit(id);
+    deposit.amount = amount;
+    deposit.timestamp = timestamp;
+    DEPOSITS.with(|d| d.borrow_mut().insert(id, deposit.clone()));
+    index_deposit(&deposit);
+    deposit
+}
+
+fn subject(deposit_id: Option<u64>, amount: u64) -> AlertSubject {
+    AlertSubject {
+        request_id: deposit_id,
+        user: Principal::from_slice(&[1]),
+        agent: Principal::from_slice(&[2]),
+        currency: Currency::UGX,
+        amount,
+    }
+}
+
+fn rules_of(hits: &[RuleHit]) -> Vec<FraudRule> {
+    hits.iter().map(|hit| hit.rule).collect()
+}
+
+#[test]
+fn test_repeated_near_limit_requests_are_held() {
+    let config = test_config().deposit;
+    let start = 1_700_000_000_000_000_000;
+    store_request(1, UGX_NEAR_LIMIT, start);
+    
+    let hits = screen_deposit(&config, &subject(None, UGX_NEAR_LIMIT), start + NANOS_PER_HOUR);
+    assert!(!rules_of(&hits).contains(&FraudRule::Structuring));
+    
+    store_request(2, UGX_NEAR_LIMIT, start + NANOS_PER_HOUR);
+    let hits = screen_deposit(&config, &subject(None, UGX_NEAR_LIMIT), start + 2 * NANOS_PER_HOUR);
+    assert!(rules_of(&hits).contains(&FraudRule::Structuring));
+    assert_eq!(fraud::strongest(&hits), Some(FraudAction::Hold));
+    
+    // Small requests are not structuring, and old ones fall out of the window
+    let hits = screen_deposit(&config, &subject(None, 1_000_000), start + 2 * NANOS_PER_HOUR);
+    assert!(!rules_of(&hits).contains(&FraudRule::Structuring));
+    let hits = screen_deposit(&config, &subject(None, UGX_NEAR_LIMIT), start + 30 * NANOS_PER_HOUR);
+    assert!(!rules_of(&hits).contains(&FraudRule::Structuring));
+}
+
+#[test]
+fn test_new_account_volume_is_flagged_until_the_account_ages() {
+    let config = test_config().deposit;
+    let start = 1_700_000_000_000_000_000;
+    store_request(1, 8_000_000, start);
+    store_request(2, 8_000_000, start + NANOS_PER_HOUR);
+    
+    let hits = screen_deposit(&config, &subject(None, 8_000_000), start + 2 * NANOS_PER_HOUR);
+    assert_eq!(rules_of(&hits), vec![FraudRule::NewAccountVolume]);
+    assert_eq!(fraud::strongest(&hits), Some(FraudAction::Flag));
+    
+    // Re-screening a stored deposit does not count it twice
+    let hits = screen_deposit(&config, &subject(Some(2), 8_000_000), start + 2 * NANOS_PER_HOUR);
+    assert!(hits.is_empty());
+    
+    let hits = screen_deposit(&config, &subject(None, 8_000_000), start + 73 * NANOS_PER_HOUR);
+    assert!(hits.is_empty());
+}
+
+#[test]
+fn test_unscreened_when_fraud_rules_are_absent() {
+    let mut config = test_config().deposit;
+    config.fraud = None;
+    store_request(1, UGX_NEAR_LIMIT, 0);
+    store_request(2, UGX_NEAR_LIMIT, 1);
+    
+    assert!(screen_deposit(&config, &subject(None, UGX_NEAR_LIMIT), 2).is_empty());
+}
+
+#[test]
+fn test_held_deposit_is_confirmable_once_cleared() {
+    load_config();
+    let company = Principal::from_slice(&[9]);
+    let deposit = sample_deposit(1);
+    store_pending(&deposit);
+    
+    let alert = raise_alert(deposit.agent_principal, subject(Some(1), deposit.amount), ScreeningStage::Confirm, FraudAction::Hold, vec![], 5);
+    DEPOSIT_EXPIRIES.with(|e| e.borrow_mut().remove(&(deposit.expires_at, 1)));
+    assert_eq!(
+        screen_confirmation(deposit.agent_principal, &deposit, 6),
+        Err(DepositError::HeldForReview { alert_id: alert.id })
+    );
+    
+    let now = deposit.expires_at + NANOS_PER_HOUR;
+    let reviewed = review_alert(alert.id, ReviewDecision::Clear, "Known customer".to_string(), company, now).unwrap();
+    assert_eq!(reviewed.status, AlertStatus::Cleared);
+    
+    let renewed = DEPOSITS.with(|d| d.borrow().get(&1)).unwrap();
+    assert_eq!(renewed.expires_at, now + 24 * NANOS_PER_HOUR);
+    assert!(DEPOSIT_EXPIRIES.with(|e| e.borrow().contains_key(&(renewed.expires_at, 1))));
+    assert_eq!(screen_confirmation(deposit.agent_principal, &renewed, now), Ok(()));
+    assert!(review_alert(alert.id, ReviewDecision::Reject, String::new(), company, now).is_err());
+}
+
+#[test]
+fn test_rejected_alert_cancels_pending_deposit() {
+    let company = Principal::from_slice(&[9]);
+    let deposit = sample_deposit(1);
+    store_pending(&deposit);
+    let alert = raise_alert(deposit.user_principal, subject(Some(1), deposit.amount), ScreeningStage::Create, FraudAction::Flag, vec![], 5);
+    
+    review_alert(alert.id, ReviewDecision::Reject, "Stolen identity".to_string(), company, 6).unwrap();
+    
+    let cancelled = DEPOSITS.with(|d| d.borrow().get(&1)).unwrap();
+    assert_eq!(cancelled.status, TransactionStatus::Cancelled);
+    assert!(!DEPOSIT_EXPIRIES.with(|e| e.borrow().contains_key(&(deposit.expires_at, 1))));
+    
+    let events = audit::events(None, 10);
+    assert_eq!(events[0].action, AuditAction::FraudAlertReviewed);
+    assert_eq!(events[1].action, AuditAction::DepositCancelled);
+    assert_eq!(events[2].action, AuditAction::FraudAlertRaised);
+}
+
+#[test]
+fn test_config_validation_rejects_bad_fraud_rules() {
+    let mut config = test_config();
+    config.deposit.fraud.as_mut().unwrap().near_limit_percent = 101;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.deposit.fraud.as_mut().unwrap().window_hours = 0;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.deposit.fraud.as_mut().unwrap().round_trip_hours = 24;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.deposit.withdrawal_canister = "bogus".to_string();
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_config();
+    config.deposit.fraud = None;
+    assert_eq!(validate_config(&config), Ok(()));
+}
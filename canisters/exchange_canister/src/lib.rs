@@ -51,7 +51,7 @@ struct TokenConfig {
 
 // Runtime state
 thread_local! {
-    static CONFIG: RefCell<Option<Config>> = RefCell::new(None);
+    static CONFIG: RefCell<Option<Config>> = const { RefCell::new(None) };
 }
 
 #[derive(CandidType, Deserialize, Clone)]
@@ -68,6 +68,18 @@ pub enum Token {
     CkUSDC,
 }
 
+#[derive(CandidType, Deserialize, Clone, PartialEq, Debug)]
+pub enum ExchangeError {
+    InvalidAmount,
+    SameToken,
+    Misconfigured { reason: String },
+    TransferFromUserFailed { reason: String },
+    CompanyTransferFailed { reason: String },
+    TransferToUserFailed { reason: String },
+    SwapFailed { reason: String },
+    SlippageExceeded { min_output: u64, output: u64 },
+}
+
 #[derive(CandidType, Deserialize)]
 pub struct ExchangeResult {
     pub output_amount: u64,
@@ -93,23 +105,23 @@ fn get_config() -> Config {
 }
 
 #[update]
-async fn swap_tokens(request: ExchangeRequest) -> Result<ExchangeResult, String> {
+async fn swap_tokens(request: ExchangeRequest) -> Result<ExchangeResult, ExchangeError> {
     let caller = ic_cdk::api::msg_caller();
     
     // Validate request
     if request.amount == 0 {
-        return Err("Amount must be greater than 0".to_string());
+        return Err(ExchangeError::InvalidAmount);
     }
     
     if request.from_token == request.to_token {
-        return Err("Cannot swap same token".to_string());
+        return Err(ExchangeError::SameToken);
     }
     
     let config = get_config();
     
     // Get company wallet principal from config (this is YOUR revenue!)
     let company_wallet = Principal::from_text(&config.company_wallet.principal)
-        .map_err(|e| format!("Invalid company wallet principal: {}", e))?;
+        .map_err(|e| ExchangeError::Misconfigured { reason: format!("Invalid company wallet principal: {}", e) })?;
     
     // Calculate spread from config (platform revenue)
     let spread_amount = (request.amount * config.spread.basis_points) / 10000;
@@ -126,10 +138,10 @@ async fn swap_tokens(request: ExchangeRequest) -> Result<ExchangeResult, String>
     
     // Check slippage
     if output_amount < request.min_output {
-        return Err(format!(
-            "Slippage too high. Expected at least {}, got {}",
-            request.min_output, output_amount
-        ));
+        return Err(ExchangeError::SlippageExceeded {
+            min_output: request.min_output,
+            output: output_amount,
+        });
     }
     
     // Step 4: Transfer output tokens to user
@@ -147,7 +159,7 @@ async fn transfer_from_user(
     from: Principal,
     token: Token,
     amount: u64,
-) -> Result<(), String> {
+) -> Result<(), ExchangeError> {
     let canister_id = get_token_canister(token)?;
     
     // ICRC-2 transferFrom pattern
@@ -157,14 +169,14 @@ async fn transfer_from_user(
     )
     .with_arg((from, ic_cdk::api::canister_self(), amount))
     .await
-    .map_err(|e| format!("Call failed: {:?}", e))?;
+    .map_err(|e| ExchangeError::TransferFromUserFailed { reason: format!("Call failed: {:?}", e) })?;
     
     let result: (Result<u64, String>,) = candid::decode_args(&response.into_bytes())
-        .map_err(|e| format!("Decode failed: {:?}", e))?;
+        .map_err(|e| ExchangeError::TransferFromUserFailed { reason: format!("Decode failed: {:?}", e) })?;
     
     match result.0 {
         Ok(_) => Ok(()),
-        Err(e) => Err(format!("Transfer from user failed: {}", e)),
+        Err(reason) => Err(ExchangeError::TransferFromUserFailed { reason }),
     }
 }
 
@@ -173,7 +185,7 @@ async fn transfer_to_company_wallet(
     company_wallet: Principal,
     token: Token,
     amount: u64,
-) -> Result<(), String> {
+) -> Result<(), ExchangeError> {
     let canister_id = get_token_canister(token)?;
     
     let response = Call::unbounded_wait(
@@ -182,14 +194,14 @@ async fn transfer_to_company_wallet(
     )
     .with_arg((company_wallet, amount))
     .await
-    .map_err(|e| format!("Call failed: {:?}", e))?;
+    .map_err(|e| ExchangeError::CompanyTransferFailed { reason: format!("Call failed: {:?}", e) })?;
     
     let result: (Result<u64, String>,) = candid::decode_args(&response.into_bytes())
-        .map_err(|e| format!("Decode failed: {:?}", e))?;
+        .map_err(|e| ExchangeError::CompanyTransferFailed { reason: format!("Decode failed: {:?}", e) })?;
     
     match result.0 {
         Ok(_) => Ok(()),
-        Err(e) => Err(format!("Transfer to company wallet failed: {}", e)),
+        Err(reason) => Err(ExchangeError::CompanyTransferFailed { reason }),
     }
 }
 
@@ -198,7 +210,7 @@ async fn transfer_to_user(
     to: Principal,
     token: Token,
     amount: u64,
-) -> Result<(), String> {
+) -> Result<(), ExchangeError> {
     let canister_id = get_token_canister(token)?;
     
     let response = Call::unbounded_wait(
@@ -207,14 +219,14 @@ async fn transfer_to_user(
     )
     .with_arg((to, amount))
     .await
-    .map_err(|e| format!("Call failed: {:?}", e))?;
+    .map_err(|e| ExchangeError::TransferToUserFailed { reason: format!("Call failed: {:?}", e) })?;
     
     let result: (Result<u64, String>,) = candid::decode_args(&response.into_bytes())
-        .map_err(|e| format!("Decode failed: {:?}", e))?;
+        .map_err(|e| ExchangeError::TransferToUserFailed { reason: format!("Decode failed: {:?}", e) })?;
     
     match result.0 {
         Ok(_) => Ok(()),
-        Err(e) => Err(format!("Transfer to user failed: {}", e)),
+        Err(reason) => Err(ExchangeError::TransferToUserFailed { reason }),
     }
 }
 
@@ -223,12 +235,12 @@ async fn perform_swap(
     from_token: Token,
     to_token: Token,
     amount: u64,
-) -> Result<u64, String> {
+) -> Result<u64, ExchangeError> {
     let config = get_config();
     
     // Get Sonic swap canister from config
     let sonic_canister = Principal::from_text(&config.dex.sonic.swap_canister)
-        .map_err(|e| format!("Invalid Sonic canister: {}", e))?;
+        .map_err(|e| ExchangeError::Misconfigured { reason: format!("Invalid Sonic canister: {}", e) })?;
     
     // Get token principals
     let from_principal = get_token_canister(from_token.clone())?;
@@ -265,26 +277,26 @@ async fn perform_swap(
         swap_args.deadline,
     ))
     .await
-    .map_err(|e| format!("Sonic swap failed: {:?}", e))?;
+    .map_err(|e| ExchangeError::SwapFailed { reason: format!("Call failed: {:?}", e) })?;
     
     let (amounts,): (Vec<candid::Nat>,) = candid::decode_args(&response.into_bytes())
-        .map_err(|e| format!("Decode failed: {:?}", e))?;
+        .map_err(|e| ExchangeError::SwapFailed { reason: format!("Decode failed: {:?}", e) })?;
     
     // Sonic returns array of amounts [input_amount, output_amount]
     if amounts.len() < 2 {
-        return Err("Invalid Sonic response".to_string());
+        return Err(ExchangeError::SwapFailed { reason: "Invalid Sonic response".to_string() });
     }
     
     // Get output amount (last element in array)
     let output = amounts.last().unwrap();
     let output_u64: u64 = output.0.clone().try_into()
-        .map_err(|_| "Output amount too large".to_string())?;
+        .map_err(|_| ExchangeError::SwapFailed { reason: "Output amount too large".to_string() })?;
     
     Ok(output_u64)
 }
 
 // Helper: Get token canister ID from config
-fn get_token_canister(token: Token) -> Result<Principal, String> {
+fn get_token_canister(token: Token) -> Result<Principal, ExchangeError> {
     let config = get_config();
     
     let ledger_id = match token {
@@ -293,7 +305,7 @@ fn get_token_canister(token: Token) -> Result<Principal, String> {
     };
     
     Principal::from_text(ledger_id)
-        .map_err(|e| format!("Invalid token canister ID: {}", e))
+        .map_err(|e| ExchangeError::Misconfigured { reason: format!("Invalid token canister ID: {}", e) })
 }
 
 #[query]
@@ -0,0 +1,119 @@
+//! Append-only audit log.
+//!
+//! Each canister defines its own actions and entities and keeps one
+//! `AuditLog` in stable memory. Events record who did what to which record,
+//! with the status before and after, and are never modified or removed.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Cow;
+
+/// Maximum number of events returned by a single audit query.
+pub const MAX_AUDIT_PAGE: u64 = 100;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AuditEvent<A, E> {
+    pub id: u64,
+    pub timestamp: u64,
+    pub actor: Principal,
+    pub action: A,
+    pub entity: E,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Free-form context, e.g. a dispute reason or payout block index
+    pub note: Option<String>,
+}
+
+/// What changed, as recorded on an event.
+#[derive(Default)]
+pub struct Transition {
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub note: Option<String>,
+}
+
+impl Transition {
+    pub fn new(before: Option<String>, after: Option<String>) -> Self {
+        Transition { before, after, note: None }
+    }
+
+    /// A status change; `before` is `None` when the record was just created.
+    pub fn status<S: std::fmt::Debug>(before: Option<&S>, after: &S) -> Self {
+        Transition::new(before.map(|s| format!("{:?}", s)), Some(format!("{:?}", after)))
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+}
+
+/// Append-only event log; the event id is its position.
+pub struct AuditLog<A, E, M: Memory>
+where
+    AuditEvent<A, E>: Storable,
+{
+    events: StableBTreeMap<u64, AuditEvent<A, E>, M>,
+}
+
+impl<A, E, M: Memory> AuditLog<A, E, M>
+where
+    AuditEvent<A, E>: Storable,
+{
+    pub fn init(memory: M) -> Self {
+        AuditLog { events: StableBTreeMap::init(memory) }
+    }
+
+    /// Append an event and return its id.
+    pub fn record(&mut self, actor: Principal, action: A, entity: E, transition: Transition, now: u64) -> u64 {
+        let id = self.events.len();
+        self.events.insert(id, AuditEvent {
+            id,
+            timestamp: now,
+            actor,
+            action,
+            entity,
+            before: transition.before,
+            after: transition.after,
+            note: transition.note,
+        });
+        id
+    }
+
+    /// Events newest first. `before` is an exclusive event id cursor from a
+    /// previous page.
+    pub fn events(&self, before: Option<u64>, limit: u64) -> Vec<AuditEvent<A, E>> {
+        self.events
+            .range(..before.unwrap_or(u64::MAX))
+            .rev()
+            .take(limit.min(MAX_AUDIT_PAGE) as usize)
+            .map(|(_, event)| event)
+            .collect()
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredAuditEvent<A, E> {
+    V1(AuditEvent<A, E>),
+}
+
+impl<A, E> Storable for AuditEvent<A, E>
+where
+    A: CandidType + DeserializeOwned + Clone,
+    E: CandidType + DeserializeOwned + Clone,
+{
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(StoredAuditEvent::V1(self.clone())).expect("Failed to encode stable record"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match candid::decode_one(&bytes).expect("Failed to decode stable record") {
+            StoredAuditEvent::V1(event) => event,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -0,0 +1,54 @@
+//! Currencies handled by the fiat ledger and the withdrawal canister.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::Serialize;
+use std::borrow::Cow;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::upper_case_acronyms)] // ISO 4217 codes, part of the Candid interface
+pub enum Currency {
+    UGX, // Ugandan Shilling
+    KES, // Kenyan Shilling
+    TZS, // Tanzanian Shilling
+    NGN, // Nigerian Naira
+    GHS, // Ghanaian Cedi
+}
+
+impl Currency {
+    /// ISO 4217 code, as used for the currency tables in `revenue_config.toml`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Currency::UGX => "UGX",
+            Currency::KES => "KES",
+            Currency::TZS => "TZS",
+            Currency::NGN => "NGN",
+            Currency::GHS => "GHS",
+        }
+    }
+    
+    pub fn from_code(code: &str) -> Option<Currency> {
+        match code {
+            "UGX" => Some(Currency::UGX),
+            "KES" => Some(Currency::KES),
+            "TZS" => Some(Currency::TZS),
+            "NGN" => Some(Currency::NGN),
+            "GHS" => Some(Currency::GHS),
+            _ => None,
+        }
+    }
+}
+
+// Used as a stable map key; the candid encoding is frozen.
+impl Storable for Currency {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).expect("Failed to encode stable record"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to decode stable record")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -0,0 +1,355 @@
+//! Fraud and anomaly rules.
+//!
+//! Deposit and withdrawal requests are screened when they are created and
+//! again when the agent confirms them. Each rule that fires names an action
+//! and the strongest one applies: a flagged request goes ahead and is queued
+//! for review, a held one cannot be confirmed until staff clear it, and a
+//! blocked one is refused before any funds move. Cash may already have
+//! changed hands by confirmation, so a block raised there holds the request
+//! instead.
+//!
+//! The rules only see what the calling canister passes in a `Screening`.
+//! Alerts are kept by that canister in its own review queue, together with
+//! an index of the latest alert raised for each of its requests.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use crate::currency::Currency;
+
+const NANOS_PER_HOUR: u64 = 3_600 * 1_000_000_000;
+
+// Sanity bounds for the configured rule windows
+pub const MAX_RULE_WINDOW_HOURS: u64 = 24 * 90;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FraudAction {
+    #[default]
+    Flag,
+    Hold,
+    Block,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FraudConfig {
+    // Structuring: near_limit_count requests within window_hours, each at least
+    // near_limit_percent of the per-request maximum (0 = rule off)
+    pub window_hours: u64,
+    pub near_limit_percent: u64,
+    pub near_limit_count: u64,
+    pub structuring_action: FraudAction,
+    // Accounts first seen less than new_account_hours ago whose volume in a
+    // currency exceeds new_account_volume_percent of its per-request maximum
+    pub new_account_hours: u64,
+    pub new_account_volume_percent: u64,
+    pub new_account_action: FraudAction,
+    // Round-tripping: withdrawing through an agent the user deposited with
+    // within round_trip_hours (0 = rule off). Withdrawals only
+    #[serde(default)]
+    pub round_trip_hours: u64,
+    #[serde(default)]
+    pub round_trip_action: FraudAction,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FraudRule {
+    /// Many requests just under the per-request limit
+    Structuring,
+    /// A new account moving a large volume
+    NewAccountVolume,
+    /// Cash deposited and withdrawn again through the same agent
+    RoundTrip,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RuleHit {
+    pub rule: FraudRule,
+    pub action: FraudAction,
+    pub detail: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ScreeningStage {
+    Create,
+    Confirm,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AlertStatus {
+    Open,
+    /// Reviewed and allowed; a held request can be confirmed again
+    Cleared,
+    /// Reviewed and refused; a request still pending is called off
+    Rejected,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ReviewDecision {
+    Clear,
+    Reject,
+}
+
+/// The request an alert is about.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AlertSubject {
+    /// The deposit or withdrawal id; `None` when the request was blocked
+    /// before it was created
+    pub request_id: Option<u64>,
+    pub user: Principal,
+    pub agent: Principal,
+    pub currency: Currency,
+    pub amount: u64,
+}
+
+/// An entry in the review queue.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FraudAlert {
+    pub id: u64,
+    pub subject: AlertSubject,
+    pub stage: ScreeningStage,
+    pub action: FraudAction,
+    pub hits: Vec<RuleHit>,
+    pub raised_at: u64,
+    pub status: AlertStatus,
+    pub reviewed_by: Option<Principal>,
+    pub reviewed_at: Option<u64>,
+    pub review_note: Option<String>,
+}
+
+impl FraudAlert {
+    /// Whether this alert currently stops its request from being confirmed.
+    pub fn is_holding(&self) -> bool {
+        self.status == AlertStatus::Open && self.action != FraudAction::Flag
+    }
+}
+
+/// Schema v1 subject: each canister named the request id after its own
+/// requests. Only one of the ids is ever set.
+#[derive(CandidType, Deserialize)]
+struct AlertSubjectV1 {
+    deposit_id: Option<u64>,
+    withdrawal_id: Option<u64>,
+    user: Principal,
+    agent: Principal,
+    currency: Currency,
+    amount: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct FraudAlertV1 {
+    id: u64,
+    subject: AlertSubjectV1,
+    stage: ScreeningStage,
+    action: FraudAction,
+    hits: Vec<RuleHit>,
+    raised_at: u64,
+    status: AlertStatus,
+    reviewed_by: Option<Principal>,
+    reviewed_at: Option<u64>,
+    review_note: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredFraudAlert {
+    V1(FraudAlertV1),
+    V2(FraudAlert),
+}
+
+impl Storable for FraudAlert {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(StoredFraudAlert::V2(self.clone())).expect("Failed to encode stable record"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match candid::decode_one(&bytes).expect("Failed to decode stable record") {
+            StoredFraudAlert::V1(alert) => FraudAlert {
+                id: alert.id,
+                subject: AlertSubject {
+                    request_id: alert.subject.deposit_id.or(alert.subject.withdrawal_id),
+                    user: alert.subject.user,
+                    agent: alert.subject.agent,
+                    currency: alert.subject.currency,
+                    amount: alert.subject.amount,
+                },
+                stage: alert.stage,
+                action: alert.action,
+                hits: alert.hits,
+                raised_at: alert.raised_at,
+                status: alert.status,
+                reviewed_by: alert.reviewed_by,
+                reviewed_at: alert.reviewed_at,
+                review_note: alert.review_note,
+            },
+            StoredFraudAlert::V2(alert) => alert,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Queue a new open alert; the alert id is its position. It becomes the
+/// latest alert of its request, if the request exists.
+pub fn raise_alert<M: Memory>(
+    alerts: &mut StableBTreeMap<u64, FraudAlert, M>,
+    latest: &mut StableBTreeMap<u64, u64, M>,
+    subject: AlertSubject,
+    stage: ScreeningStage,
+    action: FraudAction,
+    hits: Vec<RuleHit>,
+    now: u64,
+) -> FraudAlert {
+    let alert = FraudAlert {
+        id: alerts.len(),
+        subject,
+        stage,
+        action,
+        hits,
+        raised_at: now,
+        status: AlertStatus::Open,
+        reviewed_by: None,
+        reviewed_at: None,
+        review_note: None,
+    };
+    alerts.insert(alert.id, alert.clone());
+    if let Some(request_id) = alert.subject.request_id {
+        latest.insert(request_id, alert.id);
+    }
+    alert
+}
+
+pub fn latest_alert<M: Memory>(
+    alerts: &StableBTreeMap<u64, FraudAlert, M>,
+    latest: &StableBTreeMap<u64, u64, M>,
+    request_id: u64,
+) -> Option<FraudAlert> {
+    alerts.get(&latest.get(&request_id)?)
+}
+
+/// One of the user's earlier requests, as the rules see it.
+pub struct PastRequest {
+    pub timestamp: u64,
+    pub currency: Currency,
+    pub amount: u64,
+    /// Cancelled and expired requests moved no money
+    pub live: bool,
+}
+
+/// The request being screened and what is known about its user.
+pub struct Screening<'a> {
+    pub currency: Currency,
+    pub amount: u64,
+    /// Per-request maximum in the request's currency
+    pub max_amount: u64,
+    /// When the user's first request was made, if any
+    pub first_seen: Option<u64>,
+    /// The user's other requests, at least as far back as the longest window
+    pub history: &'a [PastRequest],
+    /// Deposits the user confirmed with the same agent within round_trip_hours;
+    /// 0 when screening deposits
+    pub agent_deposits: u64,
+    pub now: u64,
+}
+
+/// Run every rule against a request and return those that fired.
+pub fn screen(config: &FraudConfig, request: &Screening) -> Vec<RuleHit> {
+    let mut hits = Vec::new();
+    hits.extend(structuring(config, request));
+    hits.extend(new_account_volume(config, request));
+    hits.extend(round_trip(config, request));
+    hits
+}
+
+/// The action to take for a set of hits; `None` if nothing fired.
+pub fn strongest(hits: &[RuleHit]) -> Option<FraudAction> {
+    hits.iter().map(|h| h.action).max()
+}
+
+fn structuring(config: &FraudConfig, request: &Screening) -> Option<RuleHit> {
+    if config.near_limit_count == 0 || config.near_limit_percent == 0 {
+        return None;
+    }
+
+    let threshold = request.max_amount.saturating_mul(config.near_limit_percent) / 100;
+    if request.amount < threshold {
+        return None;
+    }
+
+    let since = request.now.saturating_sub(config.window_hours.saturating_mul(NANOS_PER_HOUR));
+    let earlier = request
+        .history
+        .iter()
+        .filter(|r| r.timestamp >= since && r.currency == request.currency && r.amount >= threshold)
+        .count() as u64;
+
+    (earlier + 1 >= config.near_limit_count).then(|| RuleHit {
+        rule: FraudRule::Structuring,
+        action: config.structuring_action,
+        detail: format!(
+            "{} requests of at least {} {:?} within {} hours",
+            earlier + 1,
+            threshold,
+            request.currency,
+            config.window_hours
+        ),
+    })
+}
+
+fn new_account_volume(config: &FraudConfig, request: &Screening) -> Option<RuleHit> {
+    if config.new_account_hours == 0 || config.new_account_volume_percent == 0 {
+        return None;
+    }
+
+    let first_seen = request.first_seen.unwrap_or(request.now).min(request.now);
+    if request.now - first_seen >= config.new_account_hours.saturating_mul(NANOS_PER_HOUR) {
+        return None;
+    }
+
+    let volume = request
+        .history
+        .iter()
+        .filter(|r| r.live && r.timestamp >= first_seen && r.currency == request.currency)
+        .fold(request.amount, |total, r| total.saturating_add(r.amount));
+    let limit = request.max_amount.saturating_mul(config.new_account_volume_percent) / 100;
+
+    (volume > limit).then(|| RuleHit {
+        rule: FraudRule::NewAccountVolume,
+        action: config.new_account_action,
+        detail: format!(
+            "{} {:?} from an account first seen less than {} hours ago",
+            volume, request.currency, config.new_account_hours
+        ),
+    })
+}
+
+fn round_trip(config: &FraudConfig, request: &Screening) -> Option<RuleHit> {
+    if config.round_trip_hours == 0 || request.agent_deposits == 0 {
+        return None;
+    }
+
+    Some(RuleHit {
+        rule: FraudRule::RoundTrip,
+        action: config.round_trip_action,
+        detail: format!(
+            "{} deposits with the same agent within {} hours",
+            request.agent_deposits, config.round_trip_hours
+        ),
+    })
+}
+
+pub fn validate(config: &FraudConfig) -> Result<(), String> {
+    let windows = [config.window_hours, config.new_account_hours, config.round_trip_hours];
+    if windows.iter().any(|hours| *hours > MAX_RULE_WINDOW_HOURS) {
+        return Err(format!("fraud: rule windows must be at most {} hours", MAX_RULE_WINDOW_HOURS));
+    }
+    if config.near_limit_percent > 100 {
+        return Err("fraud: near_limit_percent must be at most 100".to_string());
+    }
+    if config.near_limit_count > 0 && config.window_hours == 0 {
+        return Err("fraud: window_hours must be positive when structuring is checked".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,11 @@
+//! Types and logic shared by the deposit and withdrawal canisters, so both
+//! report to clients in the same shape.
+
+pub mod audit;
+pub mod currency;
+pub mod fraud;
+pub mod ordered_key;
+pub mod reputation;
+
+#[cfg(test)]
+mod tests;
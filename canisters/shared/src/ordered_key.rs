@@ -0,0 +1,29 @@
+//! Byte encodings for stable map keys that are range-scanned.
+//!
+//! Range scans need an encoding that sorts like the key itself, which candid
+//! does not give (it writes integers little-endian). Integers are written
+//! big-endian; principals as a length byte followed by the bytes zero-padded
+//! to the maximum length.
+
+use candid::Principal;
+
+const MAX_PRINCIPAL_LEN: usize = 29;
+pub const ORDERED_PRINCIPAL_LEN: u32 = 1 + MAX_PRINCIPAL_LEN as u32;
+
+pub fn put_principal(buf: &mut Vec<u8>, principal: &Principal) {
+    let bytes = principal.as_slice();
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+    buf.resize(buf.len() + MAX_PRINCIPAL_LEN - bytes.len(), 0);
+}
+
+pub fn take_principal(bytes: &[u8]) -> (Principal, &[u8]) {
+    let len = bytes[0] as usize;
+    let principal = Principal::from_slice(&bytes[1..1 + len]);
+    (principal, &bytes[1 + MAX_PRINCIPAL_LEN..])
+}
+
+pub fn take_u64(bytes: &[u8]) -> (u64, &[u8]) {
+    let (value, rest) = bytes.split_at(8);
+    (u64::from_be_bytes(value.try_into().expect("8 bytes")), rest)
+}
@@ -0,0 +1,188 @@
+//! Agent reputation.
+//!
+//! An agent's reputation is derived from the outcome of every request it
+//! received (how fast it settled, how often requests were cancelled, disputed
+//! or left to expire) and from the star ratings users leave once a request is
+//! confirmed. Rates are in basis points of the requests that are no longer
+//! pending.
+//!
+//! Each canister keeps one `Tally` per agent and moves it along as requests
+//! change, so reading a reputation never scans the agent's history.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::Serialize;
+use std::borrow::Cow;
+
+pub const MIN_STARS: u8 = 1;
+pub const MAX_STARS: u8 = 5;
+pub const MAX_RATING_COMMENT_LEN: usize = 280;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentReputation {
+    pub agent: Principal,
+    pub total_requests: u64,
+    pub pending: u64,
+    pub confirmed: u64,
+    pub cancelled: u64,
+    pub expired: u64,
+    /// Requests that were disputed, whatever the dispute's outcome. Withdrawals
+    /// have no dispute process, so this is always 0 there
+    pub disputed: u64,
+    /// Mean time from request to confirmation; `None` until one is confirmed
+    pub avg_confirmation_latency_secs: Option<u64>,
+    pub cancellation_rate_bps: u64,
+    pub dispute_rate_bps: u64,
+    pub expired_ratio_bps: u64,
+    pub rating_count: u64,
+    /// Mean stars times 100 (450 = 4.5 stars); `None` until rated
+    pub average_rating_x100: Option<u64>,
+}
+
+/// Where a request stands, as far as its agent's reputation goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Pending,
+    Confirmed,
+    Cancelled,
+    Expired,
+    /// Frozen by a dispute; counted only through `RequestOutcome::disputed`
+    UnderReview,
+}
+
+/// What one request contributes to its agent's tally.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestOutcome {
+    pub stage: Stage,
+    pub disputed: bool,
+    /// Time from request to confirmation in nanoseconds, when it reflects the
+    /// agent's speed
+    pub confirmation_latency: Option<u64>,
+}
+
+/// Running totals for one agent.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Tally {
+    total: u64,
+    pending: u64,
+    confirmed: u64,
+    cancelled: u64,
+    expired: u64,
+    disputed: u64,
+    latency_secs_total: u64,
+    latency_count: u64,
+    stars_total: u64,
+    rating_count: u64,
+}
+
+impl Tally {
+    /// Move a request from what it contributed before (`None` if it is new)
+    /// to what it contributes now.
+    pub fn update_request(&mut self, before: Option<&RequestOutcome>, after: &RequestOutcome) {
+        if before == Some(after) {
+            return;
+        }
+        if let Some(before) = before {
+            self.remove_request(before);
+        }
+        self.add_request(after);
+    }
+
+    pub fn add_request(&mut self, outcome: &RequestOutcome) {
+        self.total += 1;
+        if let Some(count) = self.stage_count(outcome.stage) {
+            *count += 1;
+        }
+        if outcome.disputed {
+            self.disputed += 1;
+        }
+        if let Some(nanos) = outcome.confirmation_latency {
+            self.latency_secs_total += nanos / NANOS_PER_SECOND;
+            self.latency_count += 1;
+        }
+    }
+
+    pub fn remove_request(&mut self, outcome: &RequestOutcome) {
+        self.total = self.total.saturating_sub(1);
+        if let Some(count) = self.stage_count(outcome.stage) {
+            *count = count.saturating_sub(1);
+        }
+        if outcome.disputed {
+            self.disputed = self.disputed.saturating_sub(1);
+        }
+        if let Some(nanos) = outcome.confirmation_latency {
+            self.latency_secs_total = self.latency_secs_total.saturating_sub(nanos / NANOS_PER_SECOND);
+            self.latency_count = self.latency_count.saturating_sub(1);
+        }
+    }
+
+    pub fn add_rating(&mut self, stars: u8) {
+        self.stars_total += stars as u64;
+        self.rating_count += 1;
+    }
+
+    fn stage_count(&mut self, stage: Stage) -> Option<&mut u64> {
+        match stage {
+            Stage::Pending => Some(&mut self.pending),
+            Stage::Confirmed => Some(&mut self.confirmed),
+            Stage::Cancelled => Some(&mut self.cancelled),
+            Stage::Expired => Some(&mut self.expired),
+            Stage::UnderReview => None,
+        }
+    }
+
+    pub fn finish(&self, agent: Principal) -> AgentReputation {
+        let settled = self.total.saturating_sub(self.pending);
+        let rate = |count: u64| (count * 10_000).checked_div(settled).unwrap_or(0);
+
+        AgentReputation {
+            agent,
+            total_requests: self.total,
+            pending: self.pending,
+            confirmed: self.confirmed,
+            cancelled: self.cancelled,
+            expired: self.expired,
+            disputed: self.disputed,
+            avg_confirmation_latency_secs: self.latency_secs_total.checked_div(self.latency_count),
+            cancellation_rate_bps: rate(self.cancelled),
+            dispute_rate_bps: rate(self.disputed),
+            expired_ratio_bps: rate(self.expired),
+            rating_count: self.rating_count,
+            average_rating_x100: (self.stars_total * 100).checked_div(self.rating_count),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredTally {
+    V1(Tally),
+}
+
+impl Storable for Tally {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(StoredTally::V1(self.clone())).expect("Failed to encode stable record"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match candid::decode_one(&bytes).expect("Failed to decode stable record") {
+            StoredTally::V1(tally) => tally,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub fn validate_rating(stars: u8, comment: Option<&str>) -> Result<(), String> {
+    if !(MIN_STARS..=MAX_STARS).contains(&stars) {
+        return Err(format!("Rating must be between {} and {} stars", MIN_STARS, MAX_STARS));
+    }
+
+    if comment.is_some_and(|c| c.chars().count() > MAX_RATING_COMMENT_LEN) {
+        return Err(format!("Comment exceeds {} characters", MAX_RATING_COMMENT_LEN));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,252 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::Storable;
+
+use crate::currency::Currency;
+use crate::fraud::*;
+use crate::reputation::{RequestOutcome, Stage, Tally};
+
+// ============================================================================
+// FRAUD ALERT STORAGE TESTS
+// ============================================================================
+
+/// A deposit canister alert as written before subjects named `request_id`.
+#[derive(CandidType, Deserialize)]
+struct DepositSubjectV1 {
+    deposit_id: Option<u64>,
+    user: Principal,
+    agent: Principal,
+    currency: Currency,
+    amount: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct DepositAlertV1 {
+    id: u64,
+    subject: DepositSubjectV1,
+    stage: ScreeningStage,
+    action: FraudAction,
+    hits: Vec<RuleHit>,
+    raised_at: u64,
+    status: AlertStatus,
+    reviewed_by: Option<Principal>,
+    reviewed_at: Option<u64>,
+    review_note: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredDepositAlert {
+    V1(DepositAlertV1),
+}
+
+#[test]
+fn test_v1_alerts_keep_their_request_id() {
+    let stored = StoredDepositAlert::V1(DepositAlertV1 {
+        id: 3,
+        subject: DepositSubjectV1 {
+            deposit_id: Some(42),
+            user: Principal::from_slice(&[1]),
+            agent: Principal::from_slice(&[2]),
+            currency: Currency::KES,
+            amount: 500,
+        },
+        stage: ScreeningStage::Confirm,
+        action: FraudAction::Hold,
+        hits: vec![],
+        raised_at: 9,
+        status: AlertStatus::Open,
+        reviewed_by: None,
+        reviewed_at: None,
+        review_note: None,
+    });
+    let bytes = candid::encode_one(stored).unwrap();
+    
+    let alert = FraudAlert::from_bytes(bytes.into());
+    assert_eq!(alert.subject.request_id, Some(42));
+    assert_eq!(alert.subject.currency, Currency::KES);
+    assert!(alert.is_holding());
+    assert_eq!(FraudAlert::from_bytes(alert.to_bytes()), alert);
+}
+
+// ============================================================================
+// FRAUD RULE TESTS
+// ============================================================================
+
+const HOUR: u64 = 3_600 * 1_000_000_000;
+const NOW: u64 = 1_000 * HOUR;
+const MAX_AMOUNT: u64 = 1_000;
+
+fn rules() -> FraudConfig {
+    FraudConfig {
+        window_hours: 24,
+        near_limit_percent: 90,
+        near_limit_count: 3,
+        structuring_action: FraudAction::Hold,
+        new_account_hours: 72,
+        new_account_volume_percent: 200,
+        new_account_action: FraudAction::Flag,
+        round_trip_hours: 24,
+        round_trip_action: FraudAction::Block,
+    }
+}
+
+fn past(timestamp: u64, amount: u64) -> PastRequest {
+    PastRequest { timestamp, currency: Currency::UGX, amount, live: true }
+}
+
+fn screening(amount: u64, first_seen: Option<u64>, history: &[PastRequest], agent_deposits: u64) -> Vec<FraudRule> {
+    let request = Screening {
+        currency: Currency::UGX,
+        amount,
+        max_amount: MAX_AMOUNT,
+        first_seen,
+        history,
+        agent_deposits,
+        now: NOW,
+    };
+    screen(&rules(), &request).iter().map(|hit| hit.rule).collect()
+}
+
+#[test]
+fn test_structuring_counts_requests_at_exactly_the_threshold() {
+    // 90% of 1,000
+    let history = [past(NOW - HOUR, 900), past(NOW - 2 * HOUR, 900)];
+    
+    assert_eq!(screening(900, Some(0), &history, 0), vec![FraudRule::Structuring]);
+    assert!(screening(899, Some(0), &history, 0).is_empty());
+    
+    let history = [past(NOW - HOUR, 900), past(NOW - 2 * HOUR, 899)];
+    assert!(screening(900, Some(0), &history, 0).is_empty());
+}
+
+#[test]
+fn test_structuring_window_includes_its_start() {
+    let history = [past(NOW - 24 * HOUR, 950), past(NOW - HOUR, 950)];
+    assert_eq!(screening(950, Some(0), &history, 0), vec![FraudRule::Structuring]);
+    
+    let history = [past(NOW - 24 * HOUR - 1, 950), past(NOW - HOUR, 950)];
+    assert!(screening(950, Some(0), &history, 0).is_empty());
+}
+
+#[test]
+fn test_new_account_volume_ends_at_the_window_edge() {
+    // Above 200% of the maximum
+    let history = [past(NOW - HOUR, 1_000), past(NOW - 2 * HOUR, 1_000)];
+    
+    let just_inside = NOW - 72 * HOUR + 1;
+    assert_eq!(screening(1, Some(just_inside), &history, 0), vec![FraudRule::NewAccountVolume]);
+    
+    let at_edge = NOW - 72 * HOUR;
+    assert!(screening(1, Some(at_edge), &history, 0).is_empty());
+    
+    // Exactly at the limit is not above it
+    assert!(screening(0, Some(just_inside), &history, 0).is_empty());
+}
+
+#[test]
+fn test_new_account_volume_treats_a_first_request_as_new() {
+    assert_eq!(screening(2_001, None, &[], 0), vec![FraudRule::NewAccountVolume]);
+    assert!(screening(2_000, None, &[], 0).is_empty());
+}
+
+#[test]
+fn test_round_trip_reports_the_agent_deposit_count() {
+    assert!(screening(100, Some(0), &[], 0).is_empty());
+    
+    let request = Screening {
+        currency: Currency::UGX,
+        amount: 100,
+        max_amount: MAX_AMOUNT,
+        first_seen: Some(0),
+        history: &[],
+        agent_deposits: 2,
+        now: NOW,
+    };
+    let hits = screen(&rules(), &request);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].rule, FraudRule::RoundTrip);
+    assert_eq!(hits[0].action, FraudAction::Block);
+    assert!(hits[0].detail.starts_with("2 deposits"));
+    
+    let off = FraudConfig { round_trip_hours: 0, ..rules() };
+    assert!(screen(&off, &request).is_empty());
+}
+
+#[test]
+fn test_strongest_action_wins() {
+    let hit = |action| RuleHit { rule: FraudRule::Structuring, action, detail: String::new() };
+    
+    assert_eq!(strongest(&[]), None);
+    assert_eq!(strongest(&[hit(FraudAction::Flag)]), Some(FraudAction::Flag));
+    assert_eq!(strongest(&[hit(FraudAction::Flag), hit(FraudAction::Hold)]), Some(FraudAction::Hold));
+    assert_eq!(
+        strongest(&[hit(FraudAction::Hold), hit(FraudAction::Block), hit(FraudAction::Flag)]),
+        Some(FraudAction::Block)
+    );
+}
+
+// ============================================================================
+// REPUTATION TALLY TESTS
+// ============================================================================
+
+fn outcome(stage: Stage, disputed: bool, confirmation_latency: Option<u64>) -> RequestOutcome {
+    RequestOutcome { stage, disputed, confirmation_latency }
+}
+
+fn every_outcome() -> Vec<RequestOutcome> {
+    vec![
+        outcome(Stage::Pending, false, None),
+        outcome(Stage::Confirmed, false, Some(90 * 1_000_000_000)),
+        outcome(Stage::Confirmed, true, None),
+        outcome(Stage::Cancelled, false, None),
+        outcome(Stage::Expired, false, None),
+        outcome(Stage::UnderReview, true, None),
+    ]
+}
+
+#[test]
+fn test_removing_a_request_undoes_adding_it() {
+    for request in every_outcome() {
+        let mut tally = Tally::default();
+        tally.add_request(&request);
+        assert_ne!(tally, Tally::default());
+        tally.remove_request(&request);
+        assert_eq!(tally, Tally::default(), "{:?}", request);
+    }
+}
+
+#[test]
+fn test_moving_a_request_matches_adding_its_new_outcome() {
+    let outcomes = every_outcome();
+    for before in &outcomes {
+        for after in &outcomes {
+            let mut moved = Tally::default();
+            moved.add_request(&outcome(Stage::Confirmed, false, Some(1_000_000_000)));
+            let mut expected = moved.clone();
+            
+            moved.update_request(None, before);
+            moved.update_request(Some(before), after);
+            expected.add_request(after);
+            assert_eq!(moved, expected, "{:?} -> {:?}", before, after);
+        }
+    }
+}
+
+#[test]
+fn test_finished_tally_rates_only_settled_requests() {
+    let mut tally = Tally::default();
+    for request in every_outcome() {
+        tally.add_request(&request);
+    }
+    tally.add_rating(4);
+    tally.add_rating(5);
+    
+    let reputation = tally.finish(Principal::from_slice(&[2]));
+    assert_eq!(reputation.total_requests, 6);
+    assert_eq!(reputation.pending, 1);
+    // Rates are over the 5 requests no longer pending
+    assert_eq!(reputation.cancellation_rate_bps, 2_000);
+    assert_eq!(reputation.expired_ratio_bps, 2_000);
+    assert_eq!(reputation.dispute_rate_bps, 4_000);
+    assert_eq!(reputation.avg_confirmation_latency_secs, Some(90));
+    assert_eq!(reputation.average_rating_x100, Some(450));
+}
@@ -0,0 +1,26 @@
+//! Client for the agent registry canister.
+//!
+//! Withdrawals may only name agents that are registered, active and KYC-approved.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::call::Call;
+use serde::Serialize;
+
+/// Mirrors `AgentIneligible` in the agent registry canister.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AgentIneligible {
+    NotRegistered,
+    Suspended,
+    KycNotApproved,
+}
+
+/// Ask the registry whether `agent` may take part in a transaction. The outer
+/// error means the registry could not be reached or answered unexpectedly.
+pub async fn check_agent(registry: Principal, agent: Principal) -> Result<Result<(), AgentIneligible>, String> {
+    let response = Call::unbounded_wait(registry, "check_agent")
+        .with_arg(agent)
+        .await
+        .map_err(|e| format!("Call failed: {:?}", e))?;
+
+    candid::decode_one(&response.into_bytes()).map_err(|e| format!("Decode failed: {:?}", e))
+}
@@ -0,0 +1,69 @@
+//! Append-only audit log.
+//!
+//! Every withdrawal status change and config change appends an event
+//! recording who did it, to what, and the status before and after. Events
+//! are never modified or removed. The log itself lives in `canister_shared`;
+//! this module names the actions and records it covers here.
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_shared::audit::AuditLog;
+use serde::Serialize;
+use std::cell::RefCell;
+
+pub use canister_shared::audit::{Transition, MAX_AUDIT_PAGE};
+
+use crate::storage::{self, Memory};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AuditAction {
+    WithdrawalCreated,
+    WithdrawalConfirmed,
+    WithdrawalCancelled,
+    WithdrawalExpired,
+    /// The expiry timer could not refund the escrow; retried next run
+    WithdrawalExpiryFailed,
+    ReleaseLocked,
+    AgentTierChanged,
+    CashFloatDeclared,
+    AgentPayoutPaid,
+    AgentPayoutFailed,
+    RevenueSwept,
+    RevenueSweepFailed,
+    FraudAlertRaised,
+    /// A fraud rule could not run, e.g. the deposit history was unreachable
+    FraudCheckSkipped,
+    FraudAlertReviewed,
+    ConfigChanged,
+}
+
+/// The record an event applies to.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AuditEntity {
+    Withdrawal { id: u64 },
+    Agent { principal: Principal },
+    AgentPayout { id: u64 },
+    RevenueSweep { id: u64 },
+    FraudAlert { id: u64 },
+    Config { change_id: u64 },
+    /// The canister as a whole, for failures not tied to one record
+    Canister,
+}
+
+pub type AuditEvent = canister_shared::audit::AuditEvent<AuditAction, AuditEntity>;
+
+thread_local! {
+    static EVENTS: RefCell<AuditLog<AuditAction, AuditEntity, Memory>> = RefCell::new(
+        AuditLog::init(storage::memory(storage::AUDIT_LOG_MEMORY_ID))
+    );
+}
+
+/// Append an event and return its id.
+pub fn record(actor: Principal, action: AuditAction, entity: AuditEntity, transition: Transition, now: u64) -> u64 {
+    EVENTS.with(|e| e.borrow_mut().record(actor, action, entity, transition, now))
+}
+
+/// Events newest first. `before` is an exclusive event id cursor from a
+/// previous page.
+pub fn events(before: Option<u64>, limit: u64) -> Vec<AuditEvent> {
+    EVENTS.with(|e| e.borrow().events(before, limit))
+}
@@ -0,0 +1,72 @@
+//! Client for the deposit canister's deposit history.
+//!
+//! Used by the fraud rules to spot cash deposited and withdrawn again through
+//! the same agent. The deposit canister only shows a user's deposits to the
+//! parties and staff, so its `withdrawal_canister` setting must name this
+//! canister.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::call::Call;
+
+use crate::Currency;
+
+/// Largest page the deposit canister returns.
+const MAX_DEPOSIT_PAGE: u64 = 100;
+
+/// Mirrors `TransactionStatus` in the deposit canister.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum DepositStatus {
+    Pending,
+    Confirmed,
+    Cancelled,
+    Expired,
+    Disputed,
+}
+
+/// The fields of the deposit canister's `DepositQuery` used here; the others
+/// are optional and decode as absent.
+#[derive(CandidType)]
+struct DepositQuery {
+    from: Option<u64>,
+    status: Option<DepositStatus>,
+    limit: Option<u64>,
+}
+
+/// The fields of a deposit the fraud rules need.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Deposit {
+    pub id: u64,
+    pub agent_principal: Principal,
+    pub currency: Currency,
+    pub amount: u64,
+    pub confirmed_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct DepositPage {
+    deposits: Vec<Deposit>,
+}
+
+/// Mirrors the one `DepositError` the history query returns.
+#[derive(CandidType, Deserialize, Debug)]
+enum DepositError {
+    Unauthorized,
+}
+
+/// The newest confirmed deposits `user` created since `since`. The error
+/// means the deposit canister could not be reached or refused the query.
+pub async fn confirmed_since(deposit_canister: Principal, user: Principal, since: u64) -> Result<Vec<Deposit>, String> {
+    let query = DepositQuery {
+        from: Some(since),
+        status: Some(DepositStatus::Confirmed),
+        limit: Some(MAX_DEPOSIT_PAGE),
+    };
+    let response = Call::unbounded_wait(deposit_canister, "get_user_deposits")
+        .with_args(&(user, query))
+        .await
+        .map_err(|e| format!("Call failed: {:?}", e))?;
+
+    let page: Result<DepositPage, DepositError> =
+        candid::decode_one(&response.into_bytes()).map_err(|e| format!("Decode failed: {:?}", e))?;
+    page.map(|p| p.deposits).map_err(|e| format!("Deposit canister refused the query: {:?}", e))
+}
@@ -0,0 +1,76 @@
+//! Client for the fiat ledger held by the deposit canister.
+//!
+//! Withdrawals escrow the user's digital balance with an ICRC-2
+//! `transfer_from` into a per-withdrawal subaccount of this canister, then
+//! release it with plain transfers out of that subaccount.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::call::Call;
+use serde::Serialize;
+
+use crate::Currency;
+
+pub type Subaccount = [u8; 32];
+
+/// Mirrors `Account` in the deposit canister's ledger.
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    pub fn of(owner: Principal) -> Self {
+        Account { owner, subaccount: None }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub currency: Currency,
+    pub amount: u64,
+    pub memo: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub currency: Currency,
+    pub amount: u64,
+    pub memo: Option<Vec<u8>>,
+}
+
+/// Mirrors `TransferError` in the deposit canister's ledger.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TransferError {
+    InsufficientFunds { balance: u64 },
+    InvalidAmount,
+    AnonymousCaller,
+    MemoTooLong { max_len: u64 },
+    InsufficientAllowance { allowance: u64 },
+    Expired { ledger_time: u64 },
+}
+
+/// Move funds out of one of this canister's subaccounts. The outer error
+/// means the ledger could not be reached or answered unexpectedly.
+pub async fn transfer(ledger: Principal, args: TransferArgs) -> Result<Result<u64, TransferError>, String> {
+    call(ledger, "transfer", args).await
+}
+
+/// Pull funds from an account that approved this canister.
+pub async fn transfer_from(ledger: Principal, args: TransferFromArgs) -> Result<Result<u64, TransferError>, String> {
+    call(ledger, "transfer_from", args).await
+}
+
+async fn call<A: CandidType>(ledger: Principal, method: &str, args: A) -> Result<Result<u64, TransferError>, String> {
+    let response = Call::unbounded_wait(ledger, method)
+        .with_arg(args)
+        .await
+        .map_err(|e| format!("Call failed: {:?}", e))?;
+
+    candid::decode_one(&response.into_bytes()).map_err(|e| format!("Decode failed: {:?}", e))
+}
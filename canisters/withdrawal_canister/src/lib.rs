@@ -1,31 +1,108 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk_macros::*;
-use serde::{Serialize, Deserialize as SerdeDeserialize};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::Serialize;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-// Configuration loaded from shared TOML
+pub use canister_shared::currency::Currency;
+
+mod agent_registry;
+mod audit;
+mod deposit_history;
+mod fiat_ledger;
+mod fraud;
+mod notifications;
+mod pricing;
+mod release;
+mod reputation;
+mod storage;
+
+use agent_registry::AgentIneligible;
+use audit::{AuditAction, AuditEntity, AuditEvent, Transition};
+use fiat_ledger::{Account, Subaccount, TransferArgs, TransferError, TransferFromArgs};
+use fraud::{AlertStatus, AlertSubject, FraudAction, FraudAlert, FraudConfig, FraudRule, ReviewDecision, RuleHit, ScreeningStage};
+use notifications::{Notification, NotificationKind};
+use pricing::{AgentFeeBreakdown, LocationTier, PricingConfig, Urgency};
+use release::ReleaseLock;
+use reputation::{AgentRating, AgentReputation, Tally};
+use storage::Memory;
+
+// Default configuration from the shared TOML; see CONFIGURATION below for runtime changes
 const CONFIG_TOML: &str = include_str!("../../revenue_config.toml");
 
-#[derive(SerdeDeserialize, Clone)]
-struct RevenueConfig {
-    company_wallet: CompanyWalletConfig,
-    withdrawal: WithdrawalConfig,
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RevenueConfig {
+    pub company_wallet: CompanyWalletConfig,
+    pub agent_registry: AgentRegistryConfig,
+    pub governance: GovernanceConfig,
+    pub withdrawal: WithdrawalConfig,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CompanyWalletConfig {
+    pub principal: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentRegistryConfig {
+    pub canister_id: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GovernanceConfig {
+    // SNS governance canister allowed to change the config (empty = controllers only)
+    pub sns_governance: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct WithdrawalConfig {
+    // Fiat ledger (deposit canister) holding the escrowed balances; empty = withdrawals disabled
+    pub fiat_ledger: String,
+    // Deposit canister read by the round-trip fraud rule; empty = rule skipped
+    pub deposit_canister: String,
+    // How long a quote's fees can be locked in by create_withdrawal_request
+    pub quote_validity_seconds: u64,
+    // Hours a withdrawal code stays valid before the request expires and is refunded
+    pub code_validity_hours: u64,
+    // Principal allowed to poll notifications for SMS delivery (the satellite); empty = none
+    pub notifier: String,
+    // Wrong release secrets an agent may enter before the withdrawal is locked
+    pub max_release_attempts: u32,
+    // Agent fee pricing by location tier, time and urgency
+    pub pricing: PricingConfig,
+    // Periodic transfer of collected platform fees to the company wallet
+    pub revenue_sweep: RevenueSweepConfig,
+    // Fraud rules screening requests on creation and confirmation
+    pub fraud: FraudConfig,
+    // Fees and limits per currency code; other currencies are not accepted
+    pub currencies: BTreeMap<String, WithdrawalCurrencyConfig>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RevenueSweepConfig {
+    pub interval_hours: u64,
+    // DAO treasury principal receiving dao_share_bps of each sweep; empty = none
+    pub dao_treasury: String,
+    pub dao_share_bps: u64,
 }
 
-#[derive(SerdeDeserialize, Clone)]
-struct CompanyWalletConfig {
-    principal: String,
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct WithdrawalCurrencyConfig {
+    // Local time of the currency's country, for night and weekend pricing
+    pub utc_offset_hours: i64,
+    pub platform_fee_basis_points: u64,
+    pub min_withdrawal: u64,
+    pub max_withdrawal: u64,
 }
 
-#[derive(SerdeDeserialize, Clone)]
-struct WithdrawalConfig {
-    agent_commission_basis_points: u64,
-    platform_fee_basis_points: u64,
-    #[allow(dead_code)]
-    min_withdrawal_ugx: u64,
-    #[allow(dead_code)]
-    max_withdrawal_ugx: u64,
+impl WithdrawalConfig {
+    fn currency(&self, currency: Currency) -> Result<&WithdrawalCurrencyConfig, WithdrawalError> {
+        self.currencies
+            .get(currency.code())
+            .ok_or(WithdrawalError::UnsupportedCurrency { currency })
+    }
 }
 
 // ============================================================================
@@ -37,12 +114,31 @@ pub struct WithdrawalTransaction {
     pub id: u64,
     pub user_principal: Principal,
     pub agent_principal: Principal,
-    pub amount_ugx: u64,
-    pub platform_fee_ugx: u64,      // 0.5% of amount + 10% of agent fee
-    pub agent_fee_ugx: u64,          // Dynamic 2-12% (agent keeps 90%)
+    pub currency: Currency,
+    pub amount: u64,
+    pub platform_fee: u64,      // platform_fee_basis_points of the amount only
+    pub agent_fee: u64,          // Priced by location, time and urgency; all of it goes to the agent
     pub withdrawal_code: String,
     pub timestamp: u64,
+    pub expires_at: u64,
+    pub confirmed_at: Option<u64>,
     pub status: TransactionStatus,
+    pub escrow: Option<Escrow>,
+}
+
+/// The user's funds held on the fiat ledger while a withdrawal is open.
+/// Each step's block index is recorded so an interrupted release resumes
+/// where it stopped instead of paying twice.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Escrow {
+    pub subaccount: Subaccount,
+    pub lock_block: u64,
+    /// Cash-equivalent paid to the agent on confirmation
+    pub payout_block: Option<u64>,
+    /// Platform and agent fees moved to the fee account on confirmation
+    pub fee_block: Option<u64>,
+    /// Full amount returned to the user on cancellation or expiry
+    pub refund_block: Option<u64>,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -50,55 +146,508 @@ pub enum TransactionStatus {
     Pending,
     Confirmed,
     Cancelled,
+    Expired,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct AgentEarnings {
     pub principal: Principal,
+    pub currencies: Vec<CurrencyEarnings>,   // One entry per currency the agent has paid out
+    pub last_withdrawal_date: Option<u64>,
+}
+
+/// An agent's withdrawal totals in a single currency.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CurrencyEarnings {
+    pub currency: Currency,
     pub total_withdrawals_processed: u64,
     pub total_fees_earned: u64,
     pub total_fees_withdrawn: u64,
-    pub last_withdrawal_date: Option<u64>,
+}
+
+/// A payout of an agent's earned fees from the fee account.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentPayout {
+    pub id: u64,
+    pub agent: Principal,
+    pub currency: Currency,
+    pub amount: u64,
+    pub to: Account,
+    pub requested_at: u64,
+    pub status: AgentPayoutStatus,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AgentPayoutStatus {
+    /// Transfer in progress; the amount is already counted as withdrawn
+    Pending,
+    Paid { block_index: u64 },
+    /// Nothing was transferred and the amount is available again
+    Failed { reason: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct WithdrawEarningsRequest {
+    pub currency: Currency,
+    pub amount: u64,
+    /// Where to send the fees; defaults to the agent's own account
+    pub to: Option<Account>,
+}
+
+/// Cash an agent has declared for paying out withdrawals in one currency.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CashFloat {
+    pub amount: u64,
+    /// Where the agent pays out, as matched by `find_agents_with_liquidity`
+    pub city: String,
+    pub updated_at: u64,
+}
+
+/// An agent's cash float and how much of it pending withdrawals have claimed.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentLiquidity {
+    pub agent: Principal,
+    pub currency: Currency,
+    pub float: CashFloat,
+    pub reserved: u64,
+    pub available: u64,
+}
+
+/// An agent's position in one currency, as a stable map key.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AgentCurrencyKey {
+    pub agent: Principal,
+    pub currency: Currency,
+}
+
+/// Per-user withdrawal index key, ordered by creation time.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserWithdrawalKey {
+    pub user: Principal,
+    pub timestamp: u64,
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct DeclareCashFloatRequest {
+    pub currency: Currency,
+    pub amount: u64,
+    pub city: String,
+}
+
+/// One transfer of collected platform fees out of the fee account. It covers
+/// the fee accruals from `from_accrual` up to (not including) `to_accrual`;
+/// each leg's block index is recorded so a retry never pays twice.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RevenueSweep {
+    pub id: u64,
+    pub currency: Currency,
+    pub from_accrual: u64,
+    pub to_accrual: u64,
+    pub amount: u64,
+    pub company_wallet: Principal,
+    pub company_amount: u64,
+    pub company_block: Option<u64>,
+    pub dao_treasury: Option<Principal>,
+    pub dao_amount: u64,
+    pub dao_block: Option<u64>,
+    pub started_at: u64,
+    pub completed_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Platform fee of a confirmed withdrawal, waiting to be swept.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FeeAccrual {
+    pub withdrawal_id: u64,
+    pub currency: Currency,
+    pub platform_fee: u64,
+}
+
+/// A total in one currency, as returned by the revenue queries.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CurrencyAmount {
+    pub currency: Currency,
+    pub amount: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum WithdrawalError {
+    Unauthorized,
+    InvalidAmount,
+    UnsupportedCurrency { currency: Currency },
+    BelowMinimum { min: u64 },
+    AboveMaximum { max: u64 },
+    NotFound,
+    AlreadyProcessed { status: TransactionStatus },
+    WrongAgent,
+    /// Another call is already moving this withdrawal's escrow
+    EscrowBusy,
+    /// The agent has already been paid; the withdrawal can only be confirmed
+    EscrowReleased,
+    InsufficientFunds { balance: u64 },
+    /// The user has not approved this canister for enough of their balance
+    InsufficientAllowance { allowance: u64 },
+    EscrowTransferFailed { reason: String },
+    LedgerUnavailable { reason: String },
+    InvalidInput { reason: String },
+    Misconfigured { reason: String },
+    AgentIneligible { reason: AgentIneligible },
+    AgentRegistryUnavailable { reason: String },
+    QuoteNotFound,
+    QuoteExpired,
+    /// The quote was for a different agent, currency or amount
+    QuoteMismatch,
+    InsufficientEarnings { available: u64 },
+    /// The withdrawal code's validity window has passed
+    CodeExpired,
+    CodeGenerationFailed,
+    WrongReleaseCode { attempts_left: u32 },
+    /// Too many wrong release secrets; the withdrawal can only be cancelled
+    ReleaseLocked,
+    /// The agent's unreserved cash cannot cover the payout; `alternatives`
+    /// are agents in the same city who can
+    InsufficientAgentLiquidity { available: u64, alternatives: Vec<Principal> },
+    SweepInProgress,
+    /// Some sweep transfers failed; they are retried on the next sweep
+    SweepIncomplete { failed: Vec<u64> },
+    /// Refused by the fraud rules; no funds were escrowed
+    TransactionBlocked { rules: Vec<FraudRule> },
+    /// Frozen until staff review the alert
+    HeldForReview { alert_id: u64 },
 }
 
 #[derive(CandidType, Deserialize)]
 pub struct CreateWithdrawalRequest {
     pub user_principal: Principal,
     pub agent_principal: Principal,
-    pub amount_ugx: u64,
+    pub currency: Currency,
+    pub amount: u64,
+    pub urgency: Urgency,
+    /// Quote from `quote_withdrawal` whose fees to charge; its urgency
+    /// replaces `urgency`. Without one the fees are priced on creation.
+    pub quote_id: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct QuoteWithdrawalRequest {
+    pub agent_principal: Principal,
+    pub currency: Currency,
+    pub amount: u64,
+    pub urgency: Urgency,
+}
+
+/// The fees a withdrawal will be charged if created with this quote before
+/// `expires_at`.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct WithdrawalQuote {
+    pub id: u64,
+    pub user_principal: Principal,
+    pub agent_principal: Principal,
+    pub currency: Currency,
+    pub amount: u64,
+    pub platform_fee: u64,
+    pub agent_fee: u64,
+    /// Cash the user receives from the agent
+    pub net_cash: u64,
+    pub breakdown: AgentFeeBreakdown,
+    pub expires_at: u64,
 }
 
 #[derive(CandidType, Deserialize)]
 pub struct ConfirmWithdrawalRequest {
     pub withdrawal_code: String,
     pub agent_principal: Principal,
+    /// The one-time secret the user received by SMS
+    pub release_code: String,
+}
+
+/// A runtime configuration change, kept as an audit trail.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ConfigChange {
+    pub id: u64,
+    pub changed_by: Principal,
+    pub changed_at: u64,
+    pub old: RevenueConfig,
+    pub new: RevenueConfig,
 }
 
 // ============================================================================
 // STATE
 // ============================================================================
 
+// Config is rebuilt on every install/upgrade from the TOML defaults plus the
+// latest runtime change; everything else lives in stable memory (see
+// storage.rs) and survives upgrades untouched.
 thread_local! {
-    static CONFIG: RefCell<Option<RevenueConfig>> = RefCell::new(None);
-    static WITHDRAWALS: RefCell<HashMap<u64, WithdrawalTransaction>> = RefCell::new(HashMap::new());
-    static AGENT_EARNINGS: RefCell<HashMap<Principal, AgentEarnings>> = RefCell::new(HashMap::new());
-    static NEXT_WITHDRAWAL_ID: RefCell<u64> = RefCell::new(1);
+    static CONFIG: RefCell<Option<RevenueConfig>> = const { RefCell::new(None) };
+
+    static SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::SCHEMA_VERSION_MEMORY_ID), 0)
+            .expect("Failed to init schema version")
+    );
+
+    static WITHDRAWALS: RefCell<StableBTreeMap<u64, WithdrawalTransaction, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::WITHDRAWALS_MEMORY_ID))
+    );
+
+    // (expires_at, withdrawal id) for every pending withdrawal, soonest first
+    static WITHDRAWAL_EXPIRIES: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::WITHDRAWAL_EXPIRIES_MEMORY_ID))
+    );
+
+    // Every withdrawal by user, oldest first; read by the fraud rules
+    static USER_WITHDRAWALS: RefCell<StableBTreeMap<UserWithdrawalKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::USER_WITHDRAWALS_MEMORY_ID))
+    );
+
+    // withdrawal code -> withdrawal id
+    static WITHDRAWAL_CODES: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::WITHDRAWAL_CODES_MEMORY_ID))
+    );
+
+    static NEXT_WITHDRAWAL_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::NEXT_WITHDRAWAL_ID_MEMORY_ID), 1)
+            .expect("Failed to init withdrawal id counter")
+    );
+
+    static AGENT_EARNINGS: RefCell<StableBTreeMap<Principal, AgentEarnings, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_EARNINGS_MEMORY_ID))
+    );
+
+    // Withdrawals whose escrow is being moved by an in-progress call
+    static ESCROW_IN_FLIGHT: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::ESCROW_IN_FLIGHT_MEMORY_ID))
+    );
+
+    // change id -> runtime config change; the newest entry is the live config
+    static CONFIG_HISTORY: RefCell<StableBTreeMap<u64, ConfigChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::CONFIG_HISTORY_MEMORY_ID))
+    );
+
+    // Location tier of each agent's area, set by governance; unlisted agents are Urban
+    static AGENT_LOCATION_TIERS: RefCell<StableBTreeMap<Principal, LocationTier, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_LOCATION_TIERS_MEMORY_ID))
+    );
+
+    // Unredeemed quotes; expired ones are dropped as new quotes are issued
+    static QUOTES: RefCell<StableBTreeMap<u64, WithdrawalQuote, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::QUOTES_MEMORY_ID))
+    );
+
+    // (expires_at, quote id) for every stored quote, oldest first
+    static QUOTE_EXPIRIES: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::QUOTE_EXPIRIES_MEMORY_ID))
+    );
+
+    static NEXT_QUOTE_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::NEXT_QUOTE_ID_MEMORY_ID), 1)
+            .expect("Failed to init quote id counter")
+    );
+
+    // Every earnings payout, oldest first; the id is its position
+    static AGENT_PAYOUTS: RefCell<StableBTreeMap<u64, AgentPayout, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_PAYOUTS_MEMORY_ID))
+    );
+
+    // Release secret hashes of pending withdrawals; never exposed
+    static RELEASE_LOCKS: RefCell<StableBTreeMap<u64, ReleaseLock, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::RELEASE_LOCKS_MEMORY_ID))
+    );
+
+    static CASH_FLOATS: RefCell<StableBTreeMap<AgentCurrencyKey, CashFloat, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::CASH_FLOATS_MEMORY_ID))
+    );
+
+    // Cash claimed by the agent's pending withdrawals and by requests being
+    // created; kept up to date as withdrawals change status
+    static RESERVED_CASH: RefCell<StableBTreeMap<AgentCurrencyKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::RESERVED_CASH_MEMORY_ID))
+    );
+
+    // Platform fees in confirmation order; the key is the accrual index
+    static FEE_ACCRUALS: RefCell<StableBTreeMap<u64, FeeAccrual, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::FEE_ACCRUALS_MEMORY_ID))
+    );
+
+    // Per currency, the accrual index up to which fees belong to a sweep
+    static SWEEP_WATERMARKS: RefCell<StableBTreeMap<Currency, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::SWEEP_WATERMARKS_MEMORY_ID))
+    );
+
+    // Per currency, the total of the fees past its watermark
+    static UNSWEPT_FEES: RefCell<StableBTreeMap<Currency, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::UNSWEPT_FEES_MEMORY_ID))
+    );
+
+    // Oldest first; the id is its position
+    static REVENUE_SWEEPS: RefCell<StableBTreeMap<u64, RevenueSweep, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::REVENUE_SWEEPS_MEMORY_ID))
+    );
+
+    static LAST_SWEEP_AT: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::LAST_SWEEP_AT_MEMORY_ID), 0)
+            .expect("Failed to init last sweep time")
+    );
+
+    // Not persisted: no call is in flight across an upgrade
+    static SWEEP_RUNNING: RefCell<bool> = const { RefCell::new(false) };
+
+    // withdrawal id -> the user's rating of the agent; at most one per withdrawal
+    static AGENT_RATINGS: RefCell<StableBTreeMap<u64, AgentRating, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_RATINGS_MEMORY_ID))
+    );
+
+    // Reputation totals per agent, moved along as withdrawals and ratings change
+    static AGENT_TALLIES: RefCell<StableBTreeMap<Principal, Tally, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::AGENT_TALLIES_MEMORY_ID))
+    );
+
+    // Fraud review queue; the alert id is its position
+    static FRAUD_ALERTS: RefCell<StableBTreeMap<u64, FraudAlert, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::FRAUD_ALERTS_MEMORY_ID))
+    );
+
+    // withdrawal id -> its latest fraud alert
+    static WITHDRAWAL_ALERTS: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::WITHDRAWAL_ALERTS_MEMORY_ID))
+    );
 }
 
 // ============================================================================
 // INITIALIZATION
 // ============================================================================
 
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// How often to check whether a revenue sweep is due
+const REVENUE_SWEEP_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 #[init]
 fn init() {
-    // Load configuration from shared TOML
-    let config: RevenueConfig = toml::from_str(CONFIG_TOML)
+    load_config();
+    SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(storage::SCHEMA_VERSION)
+            .expect("Failed to write schema version");
+    });
+    start_timers();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    load_config();
+    migrate_schema();
+    start_timers();
+}
+
+fn load_config() {
+    // Load the defaults from shared TOML; a config set at runtime overrides them
+    let defaults: RevenueConfig = toml::from_str(CONFIG_TOML)
         .expect("Failed to parse revenue_config.toml");
     
+    let config = CONFIG_HISTORY.with(|h| h.borrow().last_key_value())
+        .map(|(_, change)| change.new)
+        .unwrap_or(defaults);
+    
     CONFIG.with(|c| *c.borrow_mut() = Some(config));
 }
 
-fn get_config() -> RevenueConfig {
+/// Bring stable records up to `storage::SCHEMA_VERSION`.
+///
+/// Older envelope variants are converted on read, so migrating means
+/// rewriting each record once in the current layout and filling in indexes
+/// added since. Builds before v1 kept everything on the heap, so there is
+/// nothing older to convert.
+fn migrate_schema() {
+    let stored = SCHEMA_VERSION.with(|v| *v.borrow().get());
+    
+    if stored > storage::SCHEMA_VERSION {
+        ic_cdk::trap(format!(
+            "Stable schema v{} is newer than this build (v{}); refusing to downgrade",
+            stored,
+            storage::SCHEMA_VERSION
+        ));
+    }
+    
+    if stored == storage::SCHEMA_VERSION {
+        return;
+    }
+    
+    // v2: schedule expiry for withdrawals created before v2
+    if stored < 2 {
+        WITHDRAWALS.with(|withdrawals| {
+            for (id, withdrawal) in withdrawals.borrow().iter() {
+                if withdrawal.status == TransactionStatus::Pending {
+                    WITHDRAWAL_EXPIRIES.with(|e| e.borrow_mut().insert((withdrawal.expires_at, id), ()));
+                }
+            }
+        });
+    }
+    
+    // v3: index withdrawals created before v3 by user
+    if stored < 3 {
+        WITHDRAWALS.with(|withdrawals| {
+            for (_, withdrawal) in withdrawals.borrow().iter() {
+                index_withdrawal(&withdrawal);
+            }
+        });
+    }
+    
+    // v4: index codes of withdrawals created before v4
+    if stored < 4 {
+        WITHDRAWALS.with(|withdrawals| {
+            for (id, withdrawal) in withdrawals.borrow().iter() {
+                WITHDRAWAL_CODES.with(|c| c.borrow_mut().insert(withdrawal.withdrawal_code, id));
+            }
+        });
+    }
+    
+    // v5: fraud alert subjects name their withdrawal `request_id`
+    if stored < 5 {
+        FRAUD_ALERTS.with(|alerts| {
+            let mut alerts = alerts.borrow_mut();
+            let all: Vec<_> = alerts.iter().collect();
+            for (id, alert) in all {
+                alerts.insert(id, alert);
+            }
+        });
+    }
+    
+    SCHEMA_VERSION.with(|v| {
+        v.borrow_mut()
+            .set(storage::SCHEMA_VERSION)
+            .expect("Failed to write schema version");
+    });
+}
+
+// Timers do not survive upgrades, so they must be re-armed on every install
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(EXPIRY_SWEEP_INTERVAL, || {
+        ic_cdk::futures::spawn(expire_stale_withdrawals());
+    });
+    ic_cdk_timers::set_timer_interval(REVENUE_SWEEP_CHECK_INTERVAL, || {
+        let interval = current_config().withdrawal.revenue_sweep.interval_hours * NANOS_PER_HOUR;
+        if ic_cdk::api::time() >= LAST_SWEEP_AT.with(|l| *l.borrow().get()) + interval {
+            ic_cdk::futures::spawn(async {
+                match sweep_platform_fees().await {
+                    // Failed sweeps are audited one by one; a running sweep is not a failure
+                    Ok(_) | Err(WithdrawalError::SweepIncomplete { .. }) | Err(WithdrawalError::SweepInProgress) => {}
+                    Err(error) => {
+                        audit::record(
+                            ic_cdk::api::canister_self(),
+                            AuditAction::RevenueSweepFailed,
+                            AuditEntity::Canister,
+                            Transition::default().with_note(format!("{:?}", error)),
+                            ic_cdk::api::time(),
+                        );
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn current_config() -> RevenueConfig {
     CONFIG.with(|c| {
         c.borrow()
             .clone()
@@ -107,163 +656,1582 @@ fn get_config() -> RevenueConfig {
 }
 
 fn get_company_wallet() -> Result<Principal, String> {
-    let config = get_config();
+    let config = current_config();
     Principal::from_text(&config.company_wallet.principal)
         .map_err(|e| format!("Invalid company wallet principal: {}", e))
 }
 
+fn agent_registry_id() -> Result<Principal, String> {
+    let config = current_config();
+    if config.agent_registry.canister_id.is_empty() {
+        return Err("Agent registry canister is not configured".to_string());
+    }
+    Principal::from_text(&config.agent_registry.canister_id)
+        .map_err(|e| format!("Invalid agent registry canister id: {}", e))
+}
+
+fn fiat_ledger_id() -> Result<Principal, String> {
+    let config = current_config();
+    if config.withdrawal.fiat_ledger.is_empty() {
+        return Err("Fiat ledger canister is not configured".to_string());
+    }
+    Principal::from_text(&config.withdrawal.fiat_ledger)
+        .map_err(|e| format!("Invalid fiat ledger canister id: {}", e))
+}
+
 // ============================================================================
 // WITHDRAWAL FLOW
 // ============================================================================
 
 #[update]
-fn create_withdrawal_request(request: CreateWithdrawalRequest) -> Result<WithdrawalTransaction, String> {
+async fn create_withdrawal_request(request: CreateWithdrawalRequest) -> Result<WithdrawalTransaction, WithdrawalError> {
     let caller = ic_cdk::api::msg_caller();
     
     // Verify caller is the user
     if caller != request.user_principal {
-        return Err("Caller must be the user".to_string());
+        return Err(WithdrawalError::Unauthorized);
     }
     
-    if request.amount_ugx == 0 {
-        return Err("Amount must be greater than 0".to_string());
-    }
+    let config = current_config();
+    let currency_config = config.withdrawal.currency(request.currency)?;
+    check_amount_bounds(request.amount, currency_config)?;
+    let ledger = fiat_ledger_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    
+    // Fees shown to the user are the fees charged. A quote is held for this
+    // request and only used up once the funds are escrowed; on any earlier
+    // failure it is released for a retry.
+    let reservation = request.quote_id
+        .map(|quote_id| reserve_quote(quote_id, &request, ic_cdk::api::time()))
+        .transpose()?;
+    let quote = match &reservation {
+        Some(reservation) => reservation.quote().clone(),
+        None => quote(
+            &config,
+            request.user_principal,
+            request.agent_principal,
+            request.currency,
+            request.amount,
+            request.urgency,
+            ic_cdk::api::time(),
+        )?,
+    };
     
     // Generate unique withdrawal code
-    let withdrawal_id = NEXT_WITHDRAWAL_ID.with(|id| {
-        let current = *id.borrow();
-        *id.borrow_mut() = current + 1;
-        current
-    });
+    let withdrawal_id = next_id(&NEXT_WITHDRAWAL_ID);
     
-    let withdrawal_code = generate_withdrawal_code(withdrawal_id);
+    // Claim the agent's cash now so concurrent requests cannot overbook it
+    let hold = LiquidityHold::acquire(request.agent_principal, request.currency, quote.net_cash)?;
+    
+    // Only registered, active, KYC-approved agents may pay out withdrawals
+    let registry = agent_registry_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    agent_registry::check_agent(registry, request.agent_principal)
+        .await
+        .map_err(|reason| WithdrawalError::AgentRegistryUnavailable { reason })?
+        .map_err(|reason| WithdrawalError::AgentIneligible { reason })?;
     
-    // Calculate fees from config
-    let config = get_config();
+    // Screen before any funds move; a blocked request leaves only its alert
+    let subject = AlertSubject {
+        request_id: None,
+        user: request.user_principal,
+        agent: request.agent_principal,
+        currency: request.currency,
+        amount: request.amount,
+    };
+    let agent_deposits = agent_deposits(&config.withdrawal, &subject, ic_cdk::api::time()).await;
+    let hits = screen_withdrawal(&config.withdrawal, &subject, agent_deposits, ic_cdk::api::time());
+    let verdict = fraud::strongest(&hits);
+    if verdict == Some(FraudAction::Block) {
+        let rules = hits.iter().map(|hit| hit.rule).collect();
+        raise_alert(caller, subject, ScreeningStage::Create, FraudAction::Block, hits, ic_cdk::api::time());
+        return Err(WithdrawalError::TransactionBlocked { rules });
+    }
     
-    // Platform fee from config
-    let platform_fee = (request.amount_ugx * config.withdrawal.platform_fee_basis_points) / 10000;
+    let entropy = ic_cdk::management_canister::raw_rand()
+        .await
+        .map_err(|_| WithdrawalError::CodeGenerationFailed)?;
+    let (release_secret, release_lock) = release::new_secret(&entropy[..release::ENTROPY_LEN]);
+    
+    // Lock the user's funds before the withdrawal exists, so a request can
+    // never be opened against money the user does not have
+    let subaccount = escrow_subaccount(withdrawal_id);
+    let lock_block = fiat_ledger::transfer_from(ledger, TransferFromArgs {
+        spender_subaccount: None,
+        from: Account::of(request.user_principal),
+        to: escrow_account(subaccount),
+        currency: request.currency,
+        amount: request.amount,
+        memo: Some(withdrawal_id.to_be_bytes().to_vec()),
+    })
+    .await
+    .map_err(|reason| WithdrawalError::LedgerUnavailable { reason })?
+    .map_err(ledger_error)?;
+    if let Some(reservation) = reservation {
+        reservation.consume();
+    }
     
-    // Agent fee from config
-    let agent_fee = (request.amount_ugx * config.withdrawal.agent_commission_basis_points) / 10000;
+    let withdrawal_code = generate_withdrawal_code(withdrawal_id);
+    let (platform_fee, agent_fee) = (quote.platform_fee, quote.agent_fee);
+    let now = ic_cdk::api::time();
     
     let transaction = WithdrawalTransaction {
         id: withdrawal_id,
         user_principal: request.user_principal,
         agent_principal: request.agent_principal,
-        amount_ugx: request.amount_ugx,
-        platform_fee_ugx: platform_fee,
-        agent_fee_ugx: agent_fee,
+        currency: request.currency,
+        amount: request.amount,
+        platform_fee,
+        agent_fee,
         withdrawal_code: withdrawal_code.clone(),
-        timestamp: ic_cdk::api::time(),
+        timestamp: now,
+        expires_at: now + config.withdrawal.code_validity_hours * NANOS_PER_HOUR,
+        confirmed_at: None,
         status: TransactionStatus::Pending,
+        escrow: Some(Escrow {
+            subaccount,
+            lock_block,
+            payout_block: None,
+            fee_block: None,
+            refund_block: None,
+        }),
     };
     
-    WITHDRAWALS.with(|withdrawals| {
-        withdrawals.borrow_mut().insert(withdrawal_id, transaction.clone());
-    });
+    store_withdrawal(&transaction);
+    hold.keep();
+    RELEASE_LOCKS.with(|l| l.borrow_mut().insert(withdrawal_id, release_lock));
+    
+    // Only the user gets the release secret; the agent needs it from them in person
+    notifications::push(
+        transaction.user_principal,
+        withdrawal_id,
+        &withdrawal_code,
+        transaction.currency,
+        transaction.amount,
+        NotificationKind::ReleaseCode { code: release_secret },
+        now,
+    );
+    audit::record(
+        caller,
+        AuditAction::WithdrawalCreated,
+        AuditEntity::Withdrawal { id: withdrawal_id },
+        Transition::status(None, &transaction.status).with_note(format!("Escrowed in block {}", lock_block)),
+        transaction.timestamp,
+    );
+    
+    if let Some(action) = verdict {
+        let subject = AlertSubject { request_id: Some(withdrawal_id), ..subject };
+        raise_alert(caller, subject, ScreeningStage::Create, action, hits, now);
+        if action == FraudAction::Hold {
+            notify_parties(&transaction, NotificationKind::HeldForReview, now);
+        }
+    }
     
     Ok(transaction)
 }
 
+/// Agent confirms they handed over the cash. Releases the escrow: the agent
+/// receives the cash they paid out, the fees move to the fee account.
 #[update]
-fn confirm_withdrawal(request: ConfirmWithdrawalRequest) -> Result<WithdrawalTransaction, String> {
+async fn confirm_withdrawal(request: ConfirmWithdrawalRequest) -> Result<WithdrawalTransaction, WithdrawalError> {
     let caller = ic_cdk::api::msg_caller();
     
     // Verify caller is the agent
     if caller != request.agent_principal {
-        return Err("Only the assigned agent can confirm".to_string());
+        return Err(WithdrawalError::Unauthorized);
     }
     
     // Find withdrawal by code
-    let withdrawal_id = WITHDRAWALS.with(|withdrawals| {
-        withdrawals.borrow()
-            .iter()
-            .find(|(_, w)| w.withdrawal_code == request.withdrawal_code)
-            .map(|(id, _)| *id)
-    }).ok_or("Withdrawal code not found".to_string())?;
+    let withdrawal_id = WITHDRAWAL_CODES.with(|c| c.borrow().get(&request.withdrawal_code))
+        .ok_or(WithdrawalError::NotFound)?;
+    
+    let withdrawal = pending_withdrawal(withdrawal_id)?;
+    if withdrawal.agent_principal != request.agent_principal {
+        return Err(WithdrawalError::WrongAgent);
+    }
+    check_not_expired(&withdrawal, ic_cdk::api::time())?;
+    check_release_code(&withdrawal, &request.release_code, caller, ic_cdk::api::time())?;
+    
+    let ledger = fiat_ledger_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    
+    // Re-screen unless staff have already cleared this withdrawal
+    if !check_not_held(withdrawal_id)? {
+        let subject = alert_subject(&withdrawal);
+        let agent_deposits = agent_deposits(&current_config().withdrawal, &subject, ic_cdk::api::time()).await;
+        screen_confirmation(caller, &withdrawal, agent_deposits, ic_cdk::api::time())?;
+    }
+    
+    // It may have been cancelled while the rules ran
+    let withdrawal = pending_withdrawal(withdrawal_id)?;
+    let _guard = EscrowGuard::acquire(withdrawal_id)?;
+    release_escrow(ledger, &withdrawal).await?;
     
     // Update withdrawal status
-    let transaction = WITHDRAWALS.with(|withdrawals| {
-        let mut wds = withdrawals.borrow_mut();
-        let withdrawal = wds.get_mut(&withdrawal_id)
-            .ok_or("Withdrawal not found".to_string())?;
-        
-        if withdrawal.status != TransactionStatus::Pending {
-            return Err("Withdrawal already processed".to_string());
-        }
-        
-        if withdrawal.agent_principal != request.agent_principal {
-            return Err("Wrong agent".to_string());
-        }
-        
-        withdrawal.status = TransactionStatus::Confirmed;
-        Ok(withdrawal.clone())
-    })?;
+    let now = ic_cdk::api::time();
+    let transaction = mark_confirmed(withdrawal_id, now)?;
+    audit::record(
+        caller,
+        AuditAction::WithdrawalConfirmed,
+        AuditEntity::Withdrawal { id: withdrawal_id },
+        Transition::status(Some(&TransactionStatus::Pending), &transaction.status),
+        now,
+    );
     
-    // Update agent earnings
-    update_agent_earnings(
-        request.agent_principal,
-        transaction.amount_ugx,
-        transaction.agent_fee_ugx,
+    // Update agent earnings; the cash paid out has left the agent's float
+    update_agent_earnings(&transaction, now);
+    spend_cash_float(&transaction);
+    record_fee_accrual(&transaction);
+    
+    Ok(transaction)
+}
+
+/// Call off a pending withdrawal and refund the escrowed amount to the user.
+/// Either side may cancel before the agent has been paid; both are notified.
+#[update]
+async fn cancel_withdrawal(withdrawal_id: u64) -> Result<WithdrawalTransaction, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    let withdrawal = WITHDRAWALS.with(|w| w.borrow().get(&withdrawal_id))
+        .ok_or(WithdrawalError::NotFound)?;
+    if caller != withdrawal.user_principal && caller != withdrawal.agent_principal {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    let withdrawal = pending_withdrawal(withdrawal_id)?;
+    let ledger = fiat_ledger_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    let _guard = EscrowGuard::acquire(withdrawal_id)?;
+    refund_escrow(ledger, &withdrawal).await?;
+    
+    let transaction = set_status(withdrawal_id, TransactionStatus::Cancelled)?;
+    let now = ic_cdk::api::time();
+    audit::record(
+        caller,
+        AuditAction::WithdrawalCancelled,
+        AuditEntity::Withdrawal { id: withdrawal_id },
+        Transition::status(Some(&TransactionStatus::Pending), &transaction.status),
+        now,
     );
+    notify_parties(&transaction, NotificationKind::WithdrawalCancelled { by: caller }, now);
     
     Ok(transaction)
 }
 
-// ============================================================================
-// AGENT EARNINGS MANAGEMENT
-// ============================================================================
+fn pending_withdrawal(withdrawal_id: u64) -> Result<WithdrawalTransaction, WithdrawalError> {
+    let withdrawal = WITHDRAWALS.with(|w| w.borrow().get(&withdrawal_id))
+        .ok_or(WithdrawalError::NotFound)?;
+    
+    if withdrawal.status != TransactionStatus::Pending {
+        return Err(WithdrawalError::AlreadyProcessed { status: withdrawal.status });
+    }
+    
+    Ok(withdrawal)
+}
 
-fn update_agent_earnings(agent: Principal, withdrawal_amount: u64, agent_fee: u64) {
-    AGENT_EARNINGS.with(|earnings| {
-        let mut earns = earnings.borrow_mut();
-        let earning = earns.entry(agent).or_insert(AgentEarnings {
-            principal: agent,
-            total_withdrawals_processed: 0,
-            total_fees_earned: 0,
-            total_fees_withdrawn: 0,
-            last_withdrawal_date: None,
-        });
-        
-        earning.total_withdrawals_processed += withdrawal_amount;
-        earning.total_fees_earned += agent_fee;
-        earning.last_withdrawal_date = Some(ic_cdk::api::time());
-    });
+/// A withdrawal whose agent has already been paid may still be confirmed
+/// after expiry, so the interrupted release can finish.
+fn check_not_expired(withdrawal: &WithdrawalTransaction, now: u64) -> Result<(), WithdrawalError> {
+    let paid = withdrawal.escrow.as_ref().is_some_and(|e| e.payout_block.is_some());
+    if now >= withdrawal.expires_at && !paid {
+        return Err(WithdrawalError::CodeExpired);
+    }
+    Ok(())
 }
 
-#[query]
-fn get_agent_earnings(agent: Principal) -> Option<AgentEarnings> {
-    AGENT_EARNINGS.with(|earnings| {
-        earnings.borrow().get(&agent).cloned()
-    })
+/// Verify the user's release secret. The withdrawal locking is audited and
+/// both parties are told, since it may mean someone is guessing.
+fn check_release_code(
+    withdrawal: &WithdrawalTransaction,
+    release_code: &str,
+    actor: Principal,
+    now: u64,
+) -> Result<(), WithdrawalError> {
+    let max_attempts = current_config().withdrawal.max_release_attempts;
+    let result = RELEASE_LOCKS.with(|l| {
+        let mut locks = l.borrow_mut();
+        let mut lock = locks.get(&withdrawal.id).ok_or(WithdrawalError::ReleaseLocked)?;
+        let was_locked = lock.locked;
+        let result = release::verify(&mut lock, release_code, max_attempts).map_err(|e| (e, !was_locked && lock.locked));
+        locks.insert(withdrawal.id, lock);
+        Ok(result)
+    })?;
+    
+    match result {
+        Ok(()) => Ok(()),
+        Err((error, newly_locked)) => {
+            if newly_locked {
+                audit::record(
+                    actor,
+                    AuditAction::ReleaseLocked,
+                    AuditEntity::Withdrawal { id: withdrawal.id },
+                    Transition::default().with_note(format!("{} wrong release codes", max_attempts)),
+                    now,
+                );
+                notify_parties(withdrawal, NotificationKind::ReleaseLocked, now);
+            }
+            Err(error)
+        }
+    }
 }
 
-#[query]
-fn get_all_agent_earnings() -> Vec<AgentEarnings> {
-    AGENT_EARNINGS.with(|earnings| {
-        earnings.borrow().values().cloned().collect()
-    })
+/// Leave `Pending`; the release secret is no longer needed and the agent's
+/// cash is no longer claimed.
+fn set_status(withdrawal_id: u64, status: TransactionStatus) -> Result<WithdrawalTransaction, WithdrawalError> {
+    RELEASE_LOCKS.with(|l| l.borrow_mut().remove(&withdrawal_id));
+    let mut was_pending = false;
+    let withdrawal = update_withdrawal(withdrawal_id, |withdrawal| {
+        was_pending = withdrawal.status == TransactionStatus::Pending;
+        withdrawal.status = status;
+    })?;
+    if was_pending {
+        unreserve_cash(withdrawal.agent_principal, withdrawal.currency, escrow_split(&withdrawal).0);
+    }
+    Ok(withdrawal)
 }
 
-// ============================================================================
-// QUERY FUNCTIONS
-// ============================================================================
+/// Stamp a withdrawal as confirmed, recording when for the agent's reputation.
+fn mark_confirmed(withdrawal_id: u64, now: u64) -> Result<WithdrawalTransaction, WithdrawalError> {
+    set_status(withdrawal_id, TransactionStatus::Confirmed)?;
+    update_withdrawal(withdrawal_id, |withdrawal| withdrawal.confirmed_at = Some(now))
+}
 
-#[query]
-fn get_withdrawal(id: u64) -> Option<WithdrawalTransaction> {
-    WITHDRAWALS.with(|withdrawals| {
-        withdrawals.borrow().get(&id).cloned()
-    })
+fn update_withdrawal(
+    withdrawal_id: u64,
+    update: impl FnOnce(&mut WithdrawalTransaction),
+) -> Result<WithdrawalTransaction, WithdrawalError> {
+    let mut withdrawal = WITHDRAWALS.with(|w| w.borrow().get(&withdrawal_id)).ok_or(WithdrawalError::NotFound)?;
+    update(&mut withdrawal);
+    store_withdrawal(&withdrawal);
+    Ok(withdrawal)
 }
 
-#[query]
+/// Write a withdrawal, moving its expiry slot and its agent's reputation
+/// tally along with it. New withdrawals are indexed by user and code.
+fn store_withdrawal(withdrawal: &WithdrawalTransaction) {
+    let before = WITHDRAWALS.with(|w| w.borrow_mut().insert(withdrawal.id, withdrawal.clone()));
+    if before.is_none() {
+        index_withdrawal(withdrawal);
+    }
+    WITHDRAWAL_EXPIRIES.with(|e| {
+        let mut expiries = e.borrow_mut();
+        if let Some(before) = before.as_ref().filter(|b| b.status == TransactionStatus::Pending) {
+            expiries.remove(&(before.expires_at, before.id));
+        }
+        if withdrawal.status == TransactionStatus::Pending {
+            expiries.insert((withdrawal.expires_at, withdrawal.id), ());
+        }
+    });
+    let before = before.map(|b| reputation::outcome(&b));
+    let after = reputation::outcome(withdrawal);
+    update_tally(withdrawal.agent_principal, |t| t.update_request(before.as_ref(), &after));
+}
+
+fn index_withdrawal(withdrawal: &WithdrawalTransaction) {
+    let key = UserWithdrawalKey { user: withdrawal.user_principal, timestamp: withdrawal.timestamp, id: withdrawal.id };
+    USER_WITHDRAWALS.with(|i| i.borrow_mut().insert(key, ()));
+    WITHDRAWAL_CODES.with(|c| c.borrow_mut().insert(withdrawal.withdrawal_code.clone(), withdrawal.id));
+}
+
+fn update_tally(agent: Principal, update: impl FnOnce(&mut Tally)) {
+    AGENT_TALLIES.with(|t| {
+        let mut tallies = t.borrow_mut();
+        let mut tally = tallies.get(&agent).unwrap_or_default();
+        update(&mut tally);
+        tallies.insert(agent, tally);
+    });
+}
+
+// ============================================================================
+// AGENT LIQUIDITY
+// ============================================================================
+
+const MAX_CITY_LEN: usize = 64;
+const MAX_LIQUIDITY_MATCHES: usize = 20;
+const MAX_ALTERNATIVES: usize = 5;
+
+/// Declare the cash the calling agent holds for withdrawals in `currency`,
+/// replacing any earlier declaration.
+#[update]
+async fn declare_cash_float(request: DeclareCashFloatRequest) -> Result<AgentLiquidity, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    current_config().withdrawal.currency(request.currency)?;
+    let city = request.city.trim().to_string();
+    if city.is_empty() || city.len() > MAX_CITY_LEN {
+        return Err(WithdrawalError::InvalidInput {
+            reason: format!("City must be 1 to {} characters", MAX_CITY_LEN),
+        });
+    }
+    
+    // Only agents who may take withdrawals are offered to users
+    let registry = agent_registry_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    agent_registry::check_agent(registry, caller)
+        .await
+        .map_err(|reason| WithdrawalError::AgentRegistryUnavailable { reason })?
+        .map_err(|reason| WithdrawalError::AgentIneligible { reason })?;
+    
+    set_cash_float(caller, request.currency, request.amount, city, ic_cdk::api::time());
+    Ok(agent_liquidity(caller, request.currency).expect("Cash float was just set"))
+}
+
+#[query]
+fn get_cash_float(agent: Principal, currency: Currency) -> Option<AgentLiquidity> {
+    agent_liquidity(agent, currency)
+}
+
+/// Agents with at least `amount` of unreserved cash in `currency`, most
+/// available first, optionally only those in the city named by `location`.
+#[query]
+fn find_agents_with_liquidity(amount: u64, currency: Currency, location: Option<String>) -> Vec<AgentLiquidity> {
+    let city = location.as_deref().filter(|l| !l.trim().is_empty());
+    agents_with_liquidity(amount, currency, city, MAX_LIQUIDITY_MATCHES)
+}
+
+fn set_cash_float(agent: Principal, currency: Currency, amount: u64, city: String, now: u64) {
+    let old = CASH_FLOATS.with(|f| {
+        f.borrow_mut().insert(AgentCurrencyKey { agent, currency }, CashFloat { amount, city, updated_at: now })
+    });
+    audit::record(
+        agent,
+        AuditAction::CashFloatDeclared,
+        AuditEntity::Agent { principal: agent },
+        Transition::status(old.map(|f| f.amount).as_ref(), &amount).with_note(currency.code()),
+        now,
+    );
+}
+
+fn agent_liquidity(agent: Principal, currency: Currency) -> Option<AgentLiquidity> {
+    let float = CASH_FLOATS.with(|f| f.borrow().get(&AgentCurrencyKey { agent, currency }))?;
+    let reserved = reserved_cash(agent, currency);
+    Some(AgentLiquidity {
+        agent,
+        currency,
+        available: float.amount.saturating_sub(reserved),
+        float,
+        reserved,
+    })
+}
+
+/// Cash claimed by the agent's pending withdrawals and by requests being created.
+fn reserved_cash(agent: Principal, currency: Currency) -> u64 {
+    RESERVED_CASH.with(|r| r.borrow().get(&AgentCurrencyKey { agent, currency }).unwrap_or(0))
+}
+
+fn reserve_cash(agent: Principal, currency: Currency, cash: u64) {
+    let key = AgentCurrencyKey { agent, currency };
+    RESERVED_CASH.with(|r| {
+        let mut reserved = r.borrow_mut();
+        let total = reserved.get(&key).unwrap_or(0).saturating_add(cash);
+        reserved.insert(key, total);
+    });
+}
+
+fn unreserve_cash(agent: Principal, currency: Currency, cash: u64) {
+    let key = AgentCurrencyKey { agent, currency };
+    RESERVED_CASH.with(|r| {
+        let mut reserved = r.borrow_mut();
+        match reserved.get(&key).unwrap_or(0).saturating_sub(cash) {
+            0 => reserved.remove(&key),
+            total => reserved.insert(key, total),
+        };
+    });
+}
+
+fn agents_with_liquidity(amount: u64, currency: Currency, city: Option<&str>, limit: usize) -> Vec<AgentLiquidity> {
+    let candidates: Vec<Principal> = CASH_FLOATS.with(|f| {
+        f.borrow()
+            .iter()
+            .filter(|(key, float)| key.currency == currency && in_city(city, &float.city))
+            .map(|(key, _)| key.agent)
+            .collect()
+    });
+    
+    let mut matches: Vec<AgentLiquidity> = candidates
+        .into_iter()
+        .filter_map(|agent| agent_liquidity(agent, currency))
+        .filter(|l| l.available >= amount)
+        .collect();
+    matches.sort_by(|a, b| b.available.cmp(&a.available).then(a.agent.cmp(&b.agent)));
+    matches.truncate(limit);
+    matches
+}
+
+fn in_city(filter: Option<&str>, city: &str) -> bool {
+    match filter {
+        Some(filter) => filter.trim().eq_ignore_ascii_case(city),
+        None => true,
+    }
+}
+
+/// Reduce the float by the cash handed over on a confirmed withdrawal.
+fn spend_cash_float(withdrawal: &WithdrawalTransaction) {
+    let (cash, _) = escrow_split(withdrawal);
+    let key = AgentCurrencyKey { agent: withdrawal.agent_principal, currency: withdrawal.currency };
+    CASH_FLOATS.with(|f| {
+        let mut floats = f.borrow_mut();
+        if let Some(mut float) = floats.get(&key) {
+            float.amount = float.amount.saturating_sub(cash);
+            floats.insert(key, float);
+        }
+    });
+}
+
+/// Cash claimed for a withdrawal while it is being created. Once the
+/// withdrawal is stored as pending the claim is kept until it leaves
+/// `Pending`; if creation fails it is released on drop.
+struct LiquidityHold {
+    agent: Principal,
+    currency: Currency,
+    cash: u64,
+    kept: bool,
+}
+
+impl LiquidityHold {
+    /// Agents without a declared float in `currency` are not tracked, so
+    /// their requests are not checked; the claim still counts once they
+    /// declare one.
+    fn acquire(agent: Principal, currency: Currency, cash: u64) -> Result<Self, WithdrawalError> {
+        if let Some(liquidity) = agent_liquidity(agent, currency).filter(|l| cash > l.available) {
+            let alternatives = Some(liquidity.float.city)
+                .map(|city| {
+                    agents_with_liquidity(cash, currency, Some(&city), MAX_ALTERNATIVES + 1)
+                        .into_iter()
+                        .map(|l| l.agent)
+                        .filter(|a| *a != agent)
+                        .take(MAX_ALTERNATIVES)
+                        .collect()
+                })
+                .unwrap_or_default();
+            return Err(WithdrawalError::InsufficientAgentLiquidity { available: liquidity.available, alternatives });
+        }
+        
+        reserve_cash(agent, currency, cash);
+        Ok(LiquidityHold { agent, currency, cash, kept: false })
+    }
+    
+    /// The withdrawal is pending; its claim now lasts until it leaves `Pending`.
+    fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for LiquidityHold {
+    fn drop(&mut self) {
+        if !self.kept {
+            unreserve_cash(self.agent, self.currency, self.cash);
+        }
+    }
+}
+
+// ============================================================================
+// EXPIRY
+// ============================================================================
+
+const NANOS_PER_HOUR: u64 = 3_600 * 1_000_000_000;
+
+/// Refund and expire every pending withdrawal past its validity window.
+/// Withdrawals whose refund fails stay pending, with the failure audited,
+/// and are retried next sweep.
+async fn expire_stale_withdrawals() {
+    let Ok(ledger) = fiat_ledger_id() else { return };
+    
+    for withdrawal_id in due_for_expiry(ic_cdk::api::time()) {
+        if let Err(error) = expire_withdrawal(ledger, withdrawal_id).await {
+            audit::record(
+                ic_cdk::api::canister_self(),
+                AuditAction::WithdrawalExpiryFailed,
+                AuditEntity::Withdrawal { id: withdrawal_id },
+                Transition::default().with_note(format!("{:?}", error)),
+                ic_cdk::api::time(),
+            );
+        }
+    }
+}
+
+/// Pending withdrawals past expiry whose escrow is not being moved and whose
+/// agent has not been paid.
+fn due_for_expiry(now: u64) -> Vec<u64> {
+    let due: Vec<u64> = WITHDRAWAL_EXPIRIES.with(|e| {
+        e.borrow().range(..=(now, u64::MAX)).map(|((_, id), _)| id).collect()
+    });
+    WITHDRAWALS.with(|w| {
+        let withdrawals = w.borrow();
+        due.into_iter()
+            .filter_map(|id| withdrawals.get(&id))
+            .filter(|w| w.status == TransactionStatus::Pending)
+            .filter(|w| w.escrow.as_ref().is_none_or(|e| e.payout_block.is_none()))
+            .filter(|w| !ESCROW_IN_FLIGHT.with(|f| f.borrow().contains_key(&w.id)))
+            // Held withdrawals must not lapse while under review
+            .filter(|w| latest_alert(w.id).is_none_or(|alert| !alert.is_holding()))
+            .map(|w| w.id)
+            .collect()
+    })
+}
+
+async fn expire_withdrawal(ledger: Principal, withdrawal_id: u64) -> Result<(), WithdrawalError> {
+    let withdrawal = pending_withdrawal(withdrawal_id)?;
+    let _guard = EscrowGuard::acquire(withdrawal_id)?;
+    if withdrawal.escrow.is_some() {
+        refund_escrow(ledger, &withdrawal).await?;
+    }
+    
+    mark_expired(withdrawal_id, ic_cdk::api::canister_self(), ic_cdk::api::time())
+}
+
+fn mark_expired(withdrawal_id: u64, actor: Principal, now: u64) -> Result<(), WithdrawalError> {
+    let transaction = set_status(withdrawal_id, TransactionStatus::Expired)?;
+    audit::record(
+        actor,
+        AuditAction::WithdrawalExpired,
+        AuditEntity::Withdrawal { id: withdrawal_id },
+        Transition::status(Some(&TransactionStatus::Pending), &transaction.status),
+        now,
+    );
+    notify_parties(&transaction, NotificationKind::WithdrawalExpired, now);
+    Ok(())
+}
+
+// ============================================================================
+// NOTIFICATIONS
+// ============================================================================
+
+/// Queue the same notification for the user and the agent.
+fn notify_parties(withdrawal: &WithdrawalTransaction, kind: NotificationKind, now: u64) {
+    for recipient in [withdrawal.user_principal, withdrawal.agent_principal] {
+        notifications::push(
+            recipient,
+            withdrawal.id,
+            &withdrawal.withdrawal_code,
+            withdrawal.currency,
+            withdrawal.amount,
+            kind.clone(),
+            now,
+        );
+    }
+}
+
+/// Notifications to forward by SMS, oldest first (the configured notifier,
+/// controllers or SNS governance). Pass the last id already handled as `after`.
+#[query]
+fn get_notifications(after: Option<u64>, limit: u64) -> Result<Vec<Notification>, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if !is_notifier(caller) && require_governance(caller).is_err() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    Ok(notifications::since(after, limit))
+}
+
+/// Delete notifications up to and including `up_to` once they have been
+/// sent, so delivered release secrets are not kept (the configured notifier,
+/// controllers or SNS governance). Returns how many were deleted.
+#[update]
+fn acknowledge_notifications(up_to: u64) -> Result<u64, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if !is_notifier(caller) && require_governance(caller).is_err() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    Ok(notifications::acknowledge(up_to))
+}
+
+fn is_notifier(caller: Principal) -> bool {
+    let notifier = current_config().withdrawal.notifier;
+    !notifier.is_empty() && Principal::from_text(&notifier).is_ok_and(|p| p == caller)
+}
+
+// ============================================================================
+// ESCROW
+// ============================================================================
+
+// Subaccount tags of this canister on the fiat ledger
+const ESCROW_SUBACCOUNT_TAG: u8 = 1;
+const FEE_SUBACCOUNT_TAG: u8 = 2;
+
+/// Each withdrawal escrows into its own subaccount: tag byte, then the id.
+fn escrow_subaccount(withdrawal_id: u64) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    subaccount[0] = ESCROW_SUBACCOUNT_TAG;
+    subaccount[24..].copy_from_slice(&withdrawal_id.to_be_bytes());
+    subaccount
+}
+
+/// Fees collected on confirmed withdrawals, pending payout to agents and the company.
+fn fee_subaccount() -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    subaccount[0] = FEE_SUBACCOUNT_TAG;
+    subaccount
+}
+
+fn escrow_account(subaccount: Subaccount) -> Account {
+    Account { owner: ic_cdk::api::canister_self(), subaccount: Some(subaccount) }
+}
+
+/// Split an escrowed amount into (agent payout, fees). The agent handed over
+/// the amount minus fees in cash and is reimbursed exactly that.
+fn escrow_split(withdrawal: &WithdrawalTransaction) -> (u64, u64) {
+    let fees = withdrawal.platform_fee + withdrawal.agent_fee;
+    (withdrawal.amount.saturating_sub(fees), fees.min(withdrawal.amount))
+}
+
+fn ledger_error(error: TransferError) -> WithdrawalError {
+    match error {
+        TransferError::InsufficientFunds { balance } => WithdrawalError::InsufficientFunds { balance },
+        TransferError::InsufficientAllowance { allowance } => WithdrawalError::InsufficientAllowance { allowance },
+        other => WithdrawalError::EscrowTransferFailed { reason: format!("{:?}", other) },
+    }
+}
+
+/// Keeps two calls from moving the same escrow at once.
+struct EscrowGuard(u64);
+
+impl EscrowGuard {
+    fn acquire(withdrawal_id: u64) -> Result<Self, WithdrawalError> {
+        if ESCROW_IN_FLIGHT.with(|f| f.borrow_mut().insert(withdrawal_id, ())).is_some() {
+            return Err(WithdrawalError::EscrowBusy);
+        }
+        Ok(EscrowGuard(withdrawal_id))
+    }
+}
+
+impl Drop for EscrowGuard {
+    fn drop(&mut self) {
+        ESCROW_IN_FLIGHT.with(|f| f.borrow_mut().remove(&self.0));
+    }
+}
+
+fn update_escrow(withdrawal_id: u64, update: impl FnOnce(&mut Escrow)) {
+    let _ = update_withdrawal(withdrawal_id, |w| {
+        if let Some(escrow) = w.escrow.as_mut() {
+            update(escrow);
+        }
+    });
+}
+
+fn current_escrow(withdrawal_id: u64) -> Result<Escrow, WithdrawalError> {
+    WITHDRAWALS.with(|w| w.borrow().get(&withdrawal_id).and_then(|w| w.escrow))
+        .ok_or(WithdrawalError::NotFound)
+}
+
+async fn escrow_transfer(
+    ledger: Principal,
+    withdrawal: &WithdrawalTransaction,
+    escrow: &Escrow,
+    to: Account,
+    amount: u64,
+) -> Result<u64, WithdrawalError> {
+    fiat_ledger::transfer(ledger, TransferArgs {
+        from_subaccount: Some(escrow.subaccount),
+        to,
+        currency: withdrawal.currency,
+        amount,
+        memo: Some(withdrawal.id.to_be_bytes().to_vec()),
+    })
+    .await
+    .map_err(|reason| WithdrawalError::LedgerUnavailable { reason })?
+    .map_err(ledger_error)
+}
+
+/// Pay the agent, then move the fees. Steps already recorded are skipped.
+async fn release_escrow(ledger: Principal, withdrawal: &WithdrawalTransaction) -> Result<(), WithdrawalError> {
+    let (payout, fees) = escrow_split(withdrawal);
+    let escrow = current_escrow(withdrawal.id)?;
+    
+    if escrow.payout_block.is_none() {
+        let block = escrow_transfer(ledger, withdrawal, &escrow, Account::of(withdrawal.agent_principal), payout).await?;
+        update_escrow(withdrawal.id, |e| e.payout_block = Some(block));
+    }
+    
+    if escrow.fee_block.is_none() && fees > 0 {
+        let to = Account { owner: ic_cdk::api::canister_self(), subaccount: Some(fee_subaccount()) };
+        let block = escrow_transfer(ledger, withdrawal, &escrow, to, fees).await?;
+        update_escrow(withdrawal.id, |e| e.fee_block = Some(block));
+    }
+    
+    Ok(())
+}
+
+/// Return the full escrowed amount to the user.
+async fn refund_escrow(ledger: Principal, withdrawal: &WithdrawalTransaction) -> Result<(), WithdrawalError> {
+    let escrow = current_escrow(withdrawal.id)?;
+    
+    // Once the agent is paid the cash has changed hands
+    if escrow.payout_block.is_some() {
+        return Err(WithdrawalError::EscrowReleased);
+    }
+    
+    if escrow.refund_block.is_none() {
+        let to = Account::of(withdrawal.user_principal);
+        let block = escrow_transfer(ledger, withdrawal, &escrow, to, withdrawal.amount).await?;
+        update_escrow(withdrawal.id, |e| e.refund_block = Some(block));
+    }
+    
+    Ok(())
+}
+
+fn check_amount_bounds(amount: u64, config: &WithdrawalCurrencyConfig) -> Result<(), WithdrawalError> {
+    if amount == 0 {
+        return Err(WithdrawalError::InvalidAmount);
+    }
+    
+    if amount < config.min_withdrawal {
+        return Err(WithdrawalError::BelowMinimum { min: config.min_withdrawal });
+    }
+    
+    if amount > config.max_withdrawal {
+        return Err(WithdrawalError::AboveMaximum { max: config.max_withdrawal });
+    }
+    
+    Ok(())
+}
+
+/// Split a withdrawal amount into (platform fee, agent fee).
+fn calculate_fees(amount: u64, config: &WithdrawalCurrencyConfig, agent_fee_bps: u64) -> (u64, u64) {
+    let platform_fee = (amount * config.platform_fee_basis_points) / 10000;
+    let agent_fee = (amount * agent_fee_bps) / 10000;
+    (platform_fee, agent_fee)
+}
+
+// ============================================================================
+// PRICING
+// ============================================================================
+
+/// Lock in the fees for a withdrawal before creating it: passing the quote's
+/// id to `create_withdrawal_request` before it expires charges these fees.
+/// This has to be an update call, since a query cannot store the quote; use
+/// `preview_withdrawal_fees` to only show the fees.
+#[update]
+fn quote_withdrawal(request: QuoteWithdrawalRequest) -> Result<WithdrawalQuote, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if caller == Principal::anonymous() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    let now = ic_cdk::api::time();
+    let quote = quote(
+        &current_config(),
+        caller,
+        request.agent_principal,
+        request.currency,
+        request.amount,
+        request.urgency,
+        now,
+    )?;
+    Ok(issue_quote(quote, now))
+}
+
+/// The fees a withdrawal would be charged right now, without storing a
+/// quote. The returned quote has id 0 and cannot be redeemed.
+#[query]
+fn preview_withdrawal_fees(request: QuoteWithdrawalRequest) -> Result<WithdrawalQuote, WithdrawalError> {
+    quote(
+        &current_config(),
+        ic_cdk::api::msg_caller(),
+        request.agent_principal,
+        request.currency,
+        request.amount,
+        request.urgency,
+        ic_cdk::api::time(),
+    )
+}
+
+/// Price a withdrawal at `now`. The quote is not stored; see `issue_quote`.
+fn quote(
+    config: &RevenueConfig,
+    user: Principal,
+    agent: Principal,
+    currency: Currency,
+    amount: u64,
+    urgency: Urgency,
+    now: u64,
+) -> Result<WithdrawalQuote, WithdrawalError> {
+    let currency_config = config.withdrawal.currency(currency)?;
+    check_amount_bounds(amount, currency_config)?;
+    
+    let breakdown = pricing::agent_fee_bps(
+        &config.withdrawal.pricing,
+        agent_location_tier(agent),
+        urgency,
+        now,
+        currency_config.utc_offset_hours,
+    );
+    let (platform_fee, agent_fee) = calculate_fees(amount, currency_config, breakdown.agent_fee_bps);
+    
+    Ok(WithdrawalQuote {
+        id: 0,
+        user_principal: user,
+        agent_principal: agent,
+        currency,
+        amount,
+        platform_fee,
+        agent_fee,
+        net_cash: amount.saturating_sub(platform_fee + agent_fee),
+        breakdown,
+        expires_at: now.saturating_add(config.withdrawal.quote_validity_seconds * 1_000_000_000),
+    })
+}
+
+/// Store a quote under a new id so it can be redeemed.
+fn issue_quote(mut quote: WithdrawalQuote, now: u64) -> WithdrawalQuote {
+    drop_expired_quotes(now);
+    quote.id = next_id(&NEXT_QUOTE_ID);
+    store_quote(&quote);
+    quote
+}
+
+fn store_quote(quote: &WithdrawalQuote) {
+    QUOTES.with(|q| q.borrow_mut().insert(quote.id, quote.clone()));
+    QUOTE_EXPIRIES.with(|e| e.borrow_mut().insert((quote.expires_at, quote.id), ()));
+}
+
+fn remove_quote(quote: &WithdrawalQuote) {
+    QUOTES.with(|q| q.borrow_mut().remove(&quote.id));
+    QUOTE_EXPIRIES.with(|e| e.borrow_mut().remove(&(quote.expires_at, quote.id)));
+}
+
+fn drop_expired_quotes(now: u64) {
+    let expired: Vec<(u64, u64)> = QUOTE_EXPIRIES.with(|e| {
+        e.borrow().range(..(now.saturating_add(1), 0)).map(|(key, _)| key).collect()
+    });
+    for (expires_at, quote_id) in expired {
+        QUOTES.with(|q| q.borrow_mut().remove(&quote_id));
+        QUOTE_EXPIRIES.with(|e| e.borrow_mut().remove(&(expires_at, quote_id)));
+    }
+}
+
+/// Hold a quote for the withdrawal being created. Quotes belong to the user
+/// they were issued to, so anyone else's id is reported as not found.
+fn reserve_quote(quote_id: u64, request: &CreateWithdrawalRequest, now: u64) -> Result<QuoteReservation, WithdrawalError> {
+    let quote = QUOTES.with(|q| q.borrow().get(&quote_id))
+        .filter(|q| q.user_principal == request.user_principal)
+        .ok_or(WithdrawalError::QuoteNotFound)?;
+    
+    if now >= quote.expires_at {
+        remove_quote(&quote);
+        return Err(WithdrawalError::QuoteExpired);
+    }
+    if quote.agent_principal != request.agent_principal
+        || quote.currency != request.currency
+        || quote.amount != request.amount
+    {
+        return Err(WithdrawalError::QuoteMismatch);
+    }
+    
+    remove_quote(&quote);
+    Ok(QuoteReservation(Some(quote)))
+}
+
+/// A quote taken out of the store while its withdrawal is being created, so
+/// no concurrent request can use it too. Unless consumed it goes back when
+/// dropped, on every early return and on a trap in a later callback.
+struct QuoteReservation(Option<WithdrawalQuote>);
+
+impl QuoteReservation {
+    fn quote(&self) -> &WithdrawalQuote {
+        self.0.as_ref().expect("Reservation holds its quote until consumed")
+    }
+    
+    /// The withdrawal was created at the quoted fees; the quote is used up.
+    fn consume(mut self) -> WithdrawalQuote {
+        self.0.take().expect("Reservation holds its quote until consumed")
+    }
+}
+
+impl Drop for QuoteReservation {
+    fn drop(&mut self) {
+        if let Some(quote) = self.0.take() {
+            store_quote(&quote);
+        }
+    }
+}
+
+#[query]
+fn get_agent_location_tier(agent: Principal) -> LocationTier {
+    agent_location_tier(agent)
+}
+
+/// Set the location tier an agent's fees are priced at (controllers or SNS
+/// governance only).
+#[update]
+fn set_agent_location_tier(agent: Principal, tier: LocationTier) -> Result<(), WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    require_governance(caller)?;
+    
+    set_location_tier(agent, tier, caller, ic_cdk::api::time());
+    Ok(())
+}
+
+fn agent_location_tier(agent: Principal) -> LocationTier {
+    AGENT_LOCATION_TIERS.with(|t| t.borrow().get(&agent).unwrap_or_default())
+}
+
+fn set_location_tier(agent: Principal, tier: LocationTier, actor: Principal, now: u64) {
+    let old = AGENT_LOCATION_TIERS.with(|t| t.borrow_mut().insert(agent, tier));
+    audit::record(
+        actor,
+        AuditAction::AgentTierChanged,
+        AuditEntity::Agent { principal: agent },
+        Transition::status(Some(&old.unwrap_or_default()), &tier),
+        now,
+    );
+}
+
+// ============================================================================
+// AGENT EARNINGS MANAGEMENT
+// ============================================================================
+
+impl AgentEarnings {
+    fn new(principal: Principal) -> Self {
+        AgentEarnings { principal, currencies: vec![], last_withdrawal_date: None }
+    }
+    
+    /// Totals in `currency`, added on first use.
+    fn in_currency_mut(&mut self, currency: Currency) -> &mut CurrencyEarnings {
+        let index = match self.currencies.iter().position(|e| e.currency == currency) {
+            Some(index) => index,
+            None => {
+                self.currencies.push(CurrencyEarnings {
+                    currency,
+                    total_withdrawals_processed: 0,
+                    total_fees_earned: 0,
+                    total_fees_withdrawn: 0,
+                });
+                self.currencies.len() - 1
+            }
+        };
+        &mut self.currencies[index]
+    }
+}
+
+fn update_agent_earnings(withdrawal: &WithdrawalTransaction, now: u64) {
+    let agent = withdrawal.agent_principal;
+    AGENT_EARNINGS.with(|earnings| {
+        let mut earns = earnings.borrow_mut();
+        let mut earning = earns.get(&agent).unwrap_or_else(|| AgentEarnings::new(agent));
+        
+        let totals = earning.in_currency_mut(withdrawal.currency);
+        totals.total_withdrawals_processed += withdrawal.amount;
+        totals.total_fees_earned += withdrawal.agent_fee;
+        earning.last_withdrawal_date = Some(now);
+        earns.insert(agent, earning);
+    });
+}
+
+impl CurrencyEarnings {
+    fn available(&self) -> u64 {
+        self.total_fees_earned.saturating_sub(self.total_fees_withdrawn)
+    }
+}
+
+/// Pay out earned fees to the calling agent, or to `to` if given. The amount
+/// counts as withdrawn while the transfer runs, and is restored if it fails.
+#[update]
+async fn withdraw_agent_earnings(request: WithdrawEarningsRequest) -> Result<AgentPayout, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    
+    if request.amount == 0 {
+        return Err(WithdrawalError::InvalidAmount);
+    }
+    let to = request.to.unwrap_or(Account::of(caller));
+    if to.owner == Principal::anonymous() {
+        return Err(WithdrawalError::InvalidInput { reason: "Payout account must not be anonymous".to_string() });
+    }
+    let ledger = fiat_ledger_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    
+    let payout = start_agent_payout(caller, request.currency, request.amount, to, ic_cdk::api::time())?;
+    
+    // Agent fees are collected in the fee account when withdrawals are confirmed
+    let outcome = fiat_ledger::transfer(ledger, TransferArgs {
+        from_subaccount: Some(fee_subaccount()),
+        to,
+        currency: payout.currency,
+        amount: payout.amount,
+        memo: Some(payout.id.to_be_bytes().to_vec()),
+    })
+    .await
+    .map_err(|reason| WithdrawalError::LedgerUnavailable { reason })
+    .and_then(|result| result.map_err(ledger_error));
+    
+    finish_agent_payout(payout.id, outcome, caller, ic_cdk::api::time())
+}
+
+/// Reserve `amount` of the agent's available earnings and record a pending payout.
+fn start_agent_payout(
+    agent: Principal,
+    currency: Currency,
+    amount: u64,
+    to: Account,
+    now: u64,
+) -> Result<AgentPayout, WithdrawalError> {
+    AGENT_EARNINGS.with(|earnings| {
+        let mut earns = earnings.borrow_mut();
+        let earning = earns.get(&agent);
+        let available = earning.as_ref()
+            .and_then(|e| e.currencies.iter().find(|c| c.currency == currency))
+            .map_or(0, |c| c.available());
+        if amount > available {
+            return Err(WithdrawalError::InsufficientEarnings { available });
+        }
+        
+        if let Some(mut earning) = earning {
+            earning.in_currency_mut(currency).total_fees_withdrawn += amount;
+            earns.insert(agent, earning);
+        }
+        Ok(())
+    })?;
+    
+    let payout = AGENT_PAYOUTS.with(|p| {
+        let mut payouts = p.borrow_mut();
+        let payout = AgentPayout {
+            id: payouts.len(),
+            agent,
+            currency,
+            amount,
+            to,
+            requested_at: now,
+            status: AgentPayoutStatus::Pending,
+        };
+        payouts.insert(payout.id, payout.clone());
+        payout
+    });
+    Ok(payout)
+}
+
+/// Record the transfer outcome. A failed payout gives the amount back.
+fn finish_agent_payout(
+    payout_id: u64,
+    outcome: Result<u64, WithdrawalError>,
+    actor: Principal,
+    now: u64,
+) -> Result<AgentPayout, WithdrawalError> {
+    let status = match &outcome {
+        Ok(block_index) => AgentPayoutStatus::Paid { block_index: *block_index },
+        Err(error) => AgentPayoutStatus::Failed { reason: format!("{:?}", error) },
+    };
+    
+    let payout = AGENT_PAYOUTS.with(|p| {
+        let mut payouts = p.borrow_mut();
+        let mut payout = payouts.get(&payout_id).ok_or(WithdrawalError::NotFound)?;
+        payout.status = status;
+        payouts.insert(payout_id, payout.clone());
+        Ok(payout)
+    })?;
+    
+    if outcome.is_err() {
+        AGENT_EARNINGS.with(|earnings| {
+            let mut earns = earnings.borrow_mut();
+            if let Some(mut earning) = earns.get(&payout.agent) {
+                let totals = earning.in_currency_mut(payout.currency);
+                totals.total_fees_withdrawn = totals.total_fees_withdrawn.saturating_sub(payout.amount);
+                earns.insert(payout.agent, earning);
+            }
+        });
+    }
+    
+    let action = match outcome {
+        Ok(_) => AuditAction::AgentPayoutPaid,
+        Err(_) => AuditAction::AgentPayoutFailed,
+    };
+    audit::record(
+        actor,
+        action,
+        AuditEntity::AgentPayout { id: payout_id },
+        Transition::status(Some(&AgentPayoutStatus::Pending), &payout.status),
+        now,
+    );
+    
+    outcome.map(|_| payout)
+}
+
+/// An agent's earnings payouts, newest first (the agent, controllers, SNS
+/// governance or the company wallet).
+#[query]
+fn get_agent_payouts(agent: Principal) -> Result<Vec<AgentPayout>, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if caller != agent && require_governance(caller).is_err() && Some(caller) != get_company_wallet().ok() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    Ok(AGENT_PAYOUTS.with(|p| {
+        p.borrow().iter().rev().map(|(_, p)| p).filter(|p| p.agent == agent).collect()
+    }))
+}
+
+#[query]
+fn get_agent_earnings(agent: Principal) -> Option<AgentEarnings> {
+    AGENT_EARNINGS.with(|earnings| {
+        earnings.borrow().get(&agent)
+    })
+}
+
+#[query]
+fn get_all_agent_earnings() -> Vec<AgentEarnings> {
+    AGENT_EARNINGS.with(|earnings| {
+        earnings.borrow().iter().map(|(_, e)| e).collect()
+    })
+}
+
+// ============================================================================
+// FRAUD REVIEW
+// ============================================================================
+
+const MAX_REVIEW_NOTE_LEN: usize = 2000;
+
+fn alert_subject(withdrawal: &WithdrawalTransaction) -> AlertSubject {
+    AlertSubject {
+        request_id: Some(withdrawal.id),
+        user: withdrawal.user_principal,
+        agent: withdrawal.agent_principal,
+        currency: withdrawal.currency,
+        amount: withdrawal.amount,
+    }
+}
+
+/// Confirmed deposits the user made with the same agent within the
+/// round-trip window, from the deposit canister. The rule is skipped while
+/// no deposit canister is configured, and skipped with an audit event when
+/// its history cannot be read.
+async fn agent_deposits(config: &WithdrawalConfig, subject: &AlertSubject, now: u64) -> u64 {
+    if config.fraud.round_trip_hours == 0 || config.deposit_canister.is_empty() {
+        return 0;
+    }
+    
+    let since = now.saturating_sub(config.fraud.round_trip_hours.saturating_mul(NANOS_PER_HOUR));
+    let deposits = match Principal::from_text(&config.deposit_canister) {
+        Ok(deposit_canister) => deposit_history::confirmed_since(deposit_canister, subject.user, since).await,
+        Err(e) => Err(format!("Invalid deposit canister id: {}", e)),
+    };
+    match deposits {
+        Ok(deposits) => round_trips(&deposits, subject.agent, since),
+        Err(error) => {
+            audit::record(
+                ic_cdk::api::canister_self(),
+                AuditAction::FraudCheckSkipped,
+                subject.request_id.map_or(AuditEntity::Canister, |id| AuditEntity::Withdrawal { id }),
+                Transition::default().with_note(format!("Round-trip check for {}: {}", subject.user, error)),
+                now,
+            );
+            0
+        }
+    }
+}
+
+/// How many of the user's deposits went through `agent` since `since`.
+fn round_trips(deposits: &[deposit_history::Deposit], agent: Principal, since: u64) -> u64 {
+    deposits
+        .iter()
+        .filter(|d| d.agent_principal == agent && d.confirmed_at.is_some_and(|at| at >= since))
+        .count() as u64
+}
+
+/// Run the fraud rules against a withdrawal request. When an existing
+/// withdrawal is re-screened it is left out of the user's history.
+fn screen_withdrawal(config: &WithdrawalConfig, subject: &AlertSubject, agent_deposits: u64, now: u64) -> Vec<RuleHit> {
+    let Ok(currency_config) = config.currency(subject.currency) else {
+        return vec![];
+    };
+    let rules = &config.fraud;
+    let lookback = rules.window_hours.max(rules.new_account_hours).saturating_mul(NANOS_PER_HOUR);
+    
+    let key = |timestamp, id| UserWithdrawalKey { user: subject.user, timestamp, id };
+    let other = |k: &UserWithdrawalKey| Some(k.id) != subject.request_id;
+    
+    let (first_seen, ids) = USER_WITHDRAWALS.with(|i| {
+        let i = i.borrow();
+        let first_seen = i.range(key(0, 0)..=key(u64::MAX, u64::MAX))
+            .map(|(k, _)| k)
+            .find(other)
+            .map(|k| k.timestamp);
+        let ids: Vec<u64> = i.range(key(now.saturating_sub(lookback), 0)..=key(u64::MAX, u64::MAX))
+            .map(|(k, _)| k)
+            .filter(other)
+            .map(|k| k.id)
+            .collect();
+        (first_seen, ids)
+    });
+    
+    let history: Vec<fraud::PastRequest> = WITHDRAWALS.with(|w| {
+        let withdrawals = w.borrow();
+        ids.iter()
+            .filter_map(|id| withdrawals.get(id))
+            .map(|w| fraud::PastRequest {
+                timestamp: w.timestamp,
+                currency: w.currency,
+                amount: w.amount,
+                live: !matches!(w.status, TransactionStatus::Cancelled | TransactionStatus::Expired),
+            })
+            .collect()
+    });
+    
+    fraud::screen(rules, &fraud::Screening {
+        currency: subject.currency,
+        amount: subject.amount,
+        max_amount: currency_config.max_withdrawal,
+        first_seen,
+        history: &history,
+        agent_deposits,
+        now,
+    })
+}
+
+/// Fails while an open alert holds the withdrawal. Returns whether staff
+/// have cleared it, in which case it is not screened again.
+fn check_not_held(withdrawal_id: u64) -> Result<bool, WithdrawalError> {
+    match latest_alert(withdrawal_id) {
+        Some(alert) if alert.is_holding() => Err(WithdrawalError::HeldForReview { alert_id: alert.id }),
+        Some(alert) => Ok(alert.status == AlertStatus::Cleared),
+        None => Ok(false),
+    }
+}
+
+/// Re-screen a withdrawal the agent is confirming.
+fn screen_confirmation(
+    actor: Principal,
+    withdrawal: &WithdrawalTransaction,
+    agent_deposits: u64,
+    now: u64,
+) -> Result<(), WithdrawalError> {
+    let subject = alert_subject(withdrawal);
+    let hits = screen_withdrawal(&current_config().withdrawal, &subject, agent_deposits, now);
+    let Some(action) = fraud::strongest(&hits) else {
+        return Ok(());
+    };
+    
+    if action == FraudAction::Flag {
+        // Still queued from creation; one open alert per withdrawal is enough
+        if !latest_alert(withdrawal.id).is_some_and(|alert| alert.status == AlertStatus::Open) {
+            raise_alert(actor, subject, ScreeningStage::Confirm, action, hits, now);
+        }
+        return Ok(());
+    }
+    
+    // The cash may already have changed hands, so a block holds the withdrawal instead
+    let alert = raise_alert(actor, subject, ScreeningStage::Confirm, FraudAction::Hold, hits, now);
+    notify_parties(withdrawal, NotificationKind::HeldForReview, now);
+    Err(WithdrawalError::HeldForReview { alert_id: alert.id })
+}
+
+fn raise_alert(
+    actor: Principal,
+    subject: AlertSubject,
+    stage: ScreeningStage,
+    action: FraudAction,
+    hits: Vec<RuleHit>,
+    now: u64,
+) -> FraudAlert {
+    let rules: Vec<String> = hits.iter().map(|hit| format!("{:?}", hit.rule)).collect();
+    let alert = FRAUD_ALERTS.with(|a| {
+        WITHDRAWAL_ALERTS.with(|w| fraud::raise_alert(&mut a.borrow_mut(), &mut w.borrow_mut(), subject, stage, action, hits, now))
+    });
+    
+    audit::record(
+        actor,
+        AuditAction::FraudAlertRaised,
+        AuditEntity::FraudAlert { id: alert.id },
+        Transition::status(None, &action).with_note(rules.join(", ")),
+        now,
+    );
+    
+    alert
+}
+
+fn latest_alert(withdrawal_id: u64) -> Option<FraudAlert> {
+    FRAUD_ALERTS.with(|a| WITHDRAWAL_ALERTS.with(|w| fraud::latest_alert(&a.borrow(), &w.borrow(), withdrawal_id)))
+}
+
+fn require_reviewer(caller: Principal) -> Result<(), WithdrawalError> {
+    if require_governance(caller).is_err() && Some(caller) != get_company_wallet().ok() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Close an alert from the review queue (company wallet, controllers or SNS
+/// governance). Clearing a hold lets the withdrawal be confirmed, with a
+/// fresh code validity window; rejecting refunds and cancels the withdrawal
+/// if it is still pending.
+#[update]
+async fn review_fraud_alert(alert_id: u64, decision: ReviewDecision, note: String) -> Result<FraudAlert, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    require_reviewer(caller)?;
+    if note.chars().count() > MAX_REVIEW_NOTE_LEN {
+        return Err(WithdrawalError::InvalidInput {
+            reason: format!("Note exceeds {} characters", MAX_REVIEW_NOTE_LEN),
+        });
+    }
+    
+    let alert = FRAUD_ALERTS.with(|a| a.borrow().get(&alert_id))
+        .ok_or(WithdrawalError::NotFound)?;
+    if alert.status != AlertStatus::Open {
+        return Err(WithdrawalError::InvalidInput { reason: "Alert was already reviewed".to_string() });
+    }
+    
+    // Refund first: if it fails the alert stays open and can be reviewed again
+    let pending = alert.subject.request_id.and_then(|id| pending_withdrawal(id).ok());
+    if let (ReviewDecision::Reject, Some(withdrawal)) = (decision, pending) {
+        let ledger = fiat_ledger_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+        let _guard = EscrowGuard::acquire(withdrawal.id)?;
+        refund_escrow(ledger, &withdrawal).await?;
+        
+        let transaction = set_status(withdrawal.id, TransactionStatus::Cancelled)?;
+        let now = ic_cdk::api::time();
+        audit::record(
+            caller,
+            AuditAction::WithdrawalCancelled,
+            AuditEntity::Withdrawal { id: withdrawal.id },
+            Transition::status(Some(&TransactionStatus::Pending), &transaction.status)
+                .with_note(format!("Rejected in fraud review of alert {}", alert_id)),
+            now,
+        );
+        notify_parties(&transaction, NotificationKind::WithdrawalCancelled { by: caller }, now);
+    }
+    
+    close_alert(alert_id, decision, note, caller, ic_cdk::api::time())
+}
+
+fn close_alert(
+    alert_id: u64,
+    decision: ReviewDecision,
+    note: String,
+    reviewer: Principal,
+    now: u64,
+) -> Result<FraudAlert, WithdrawalError> {
+    let mut alert = FRAUD_ALERTS.with(|a| a.borrow().get(&alert_id))
+        .ok_or(WithdrawalError::NotFound)?;
+    if alert.status != AlertStatus::Open {
+        return Err(WithdrawalError::InvalidInput { reason: "Alert was already reviewed".to_string() });
+    }
+    
+    let was_holding = alert.is_holding();
+    alert.status = match decision {
+        ReviewDecision::Clear => AlertStatus::Cleared,
+        ReviewDecision::Reject => AlertStatus::Rejected,
+    };
+    alert.reviewed_by = Some(reviewer);
+    alert.reviewed_at = Some(now);
+    alert.review_note = Some(note);
+    FRAUD_ALERTS.with(|a| a.borrow_mut().insert(alert_id, alert.clone()));
+    
+    // The code may have lapsed while under review
+    if decision == ReviewDecision::Clear && was_holding {
+        let validity = current_config().withdrawal.code_validity_hours * NANOS_PER_HOUR;
+        if let Some(withdrawal_id) = alert.subject.request_id {
+            let _ = update_withdrawal(withdrawal_id, |withdrawal| {
+                if withdrawal.status == TransactionStatus::Pending {
+                    withdrawal.expires_at = now + validity;
+                }
+            });
+        }
+    }
+    
+    audit::record(
+        reviewer,
+        AuditAction::FraudAlertReviewed,
+        AuditEntity::FraudAlert { id: alert_id },
+        Transition::status(Some(&AlertStatus::Open), &alert.status),
+        now,
+    );
+    
+    Ok(alert)
+}
+
+#[query]
+fn get_fraud_alert(alert_id: u64) -> Result<FraudAlert, WithdrawalError> {
+    require_reviewer(ic_cdk::api::msg_caller())?;
+    FRAUD_ALERTS.with(|a| a.borrow().get(&alert_id)).ok_or(WithdrawalError::NotFound)
+}
+
+/// The review queue: alerts not yet reviewed, oldest first.
+#[query]
+fn get_open_fraud_alerts() -> Result<Vec<FraudAlert>, WithdrawalError> {
+    require_reviewer(ic_cdk::api::msg_caller())?;
+    
+    Ok(FRAUD_ALERTS.with(|a| {
+        a.borrow()
+            .iter()
+            .map(|(_, alert)| alert)
+            .filter(|alert| alert.status == AlertStatus::Open)
+            .collect()
+    }))
+}
+
+// ============================================================================
+// AGENT REPUTATION
+// ============================================================================
+
+/// Rate the agent that handled a confirmed withdrawal, once per withdrawal.
+#[update]
+fn rate_agent(withdrawal_id: u64, stars: u8, comment: Option<String>) -> Result<AgentRating, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    submit_rating(caller, withdrawal_id, stars, comment, ic_cdk::api::time())
+}
+
+fn submit_rating(
+    user: Principal,
+    withdrawal_id: u64,
+    stars: u8,
+    comment: Option<String>,
+    now: u64,
+) -> Result<AgentRating, WithdrawalError> {
+    reputation::validate_rating(stars, comment.as_deref())
+        .map_err(|reason| WithdrawalError::InvalidInput { reason })?;
+    
+    let withdrawal = WITHDRAWALS.with(|w| w.borrow().get(&withdrawal_id))
+        .ok_or(WithdrawalError::NotFound)?;
+    
+    if user != withdrawal.user_principal {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    if withdrawal.status != TransactionStatus::Confirmed {
+        return Err(WithdrawalError::InvalidInput {
+            reason: "Only confirmed withdrawals can be rated".to_string(),
+        });
+    }
+    
+    if AGENT_RATINGS.with(|r| r.borrow().contains_key(&withdrawal_id)) {
+        return Err(WithdrawalError::InvalidInput { reason: "Withdrawal was already rated".to_string() });
+    }
+    
+    let rating = AgentRating {
+        withdrawal_id,
+        agent: withdrawal.agent_principal,
+        user,
+        stars,
+        comment: comment.filter(|c| !c.trim().is_empty()),
+        rated_at: now,
+    };
+    AGENT_RATINGS.with(|r| r.borrow_mut().insert(withdrawal_id, rating.clone()));
+    update_tally(rating.agent, |t| t.add_rating(stars));
+    
+    Ok(rating)
+}
+
+/// Public so users can compare agents before choosing one.
+#[query]
+fn get_agent_reputation(agent: Principal) -> AgentReputation {
+    agent_reputation(agent)
+}
+
+fn agent_reputation(agent: Principal) -> AgentReputation {
+    AGENT_TALLIES.with(|t| t.borrow().get(&agent).unwrap_or_default().finish(agent))
+}
+
+// ============================================================================
+// QUERY FUNCTIONS
+// ============================================================================
+
+#[query]
+fn get_withdrawal(id: u64) -> Option<WithdrawalTransaction> {
+    WITHDRAWALS.with(|withdrawals| {
+        withdrawals.borrow().get(&id)
+    })
+}
+
+#[query]
 fn get_user_withdrawals(user: Principal) -> Vec<WithdrawalTransaction> {
     WITHDRAWALS.with(|withdrawals| {
         withdrawals.borrow()
-            .values()
+            .iter()
+            .map(|(_, w)| w)
             .filter(|w| w.user_principal == user)
-            .cloned()
             .collect()
     })
 }
@@ -272,9 +2240,9 @@ fn get_user_withdrawals(user: Principal) -> Vec<WithdrawalTransaction> {
 fn get_agent_withdrawals(agent: Principal) -> Vec<WithdrawalTransaction> {
     WITHDRAWALS.with(|withdrawals| {
         withdrawals.borrow()
-            .values()
+            .iter()
+            .map(|(_, w)| w)
             .filter(|w| w.agent_principal == agent)
-            .cloned()
             .collect()
     })
 }
@@ -283,43 +2251,456 @@ fn get_agent_withdrawals(agent: Principal) -> Vec<WithdrawalTransaction> {
 fn get_pending_withdrawals(agent: Principal) -> Vec<WithdrawalTransaction> {
     WITHDRAWALS.with(|withdrawals| {
         withdrawals.borrow()
-            .values()
+            .iter()
+            .map(|(_, w)| w)
             .filter(|w| w.agent_principal == agent && w.status == TransactionStatus::Pending)
-            .cloned()
             .collect()
     })
 }
 
+/// Platform fees on confirmed withdrawals, per currency.
 #[query]
-fn get_total_platform_revenue() -> u64 {
+fn get_total_platform_revenue() -> Vec<CurrencyAmount> {
+    let mut totals = BTreeMap::new();
     WITHDRAWALS.with(|withdrawals| {
-        withdrawals.borrow()
-            .values()
-            .filter(|w| w.status == TransactionStatus::Confirmed)
-            .map(|w| w.platform_fee_ugx)
-            .sum()
-    })
+        for (_, w) in withdrawals.borrow().iter().filter(|(_, w)| w.status == TransactionStatus::Confirmed) {
+            *totals.entry(w.currency).or_insert(0) += w.platform_fee;
+        }
+    });
+    currency_amounts(totals)
 }
 
+/// Fees earned by agents, per currency.
 #[query]
-fn get_total_agent_earnings() -> u64 {
+fn get_total_agent_earnings() -> Vec<CurrencyAmount> {
+    let mut totals = BTreeMap::new();
     AGENT_EARNINGS.with(|earnings| {
-        earnings.borrow()
-            .values()
-            .map(|e| e.total_fees_earned)
-            .sum()
+        for (_, earning) in earnings.borrow().iter() {
+            for entry in earning.currencies {
+                *totals.entry(entry.currency).or_insert(0) += entry.total_fees_earned;
+            }
+        }
+    });
+    currency_amounts(totals)
+}
+
+fn currency_amounts(totals: BTreeMap<Currency, u64>) -> Vec<CurrencyAmount> {
+    totals
+        .into_iter()
+        .map(|(currency, amount)| CurrencyAmount { currency, amount })
+        .collect()
+}
+
+/// (platform fee, urban base agent fee) in basis points. The agent fee
+/// actually charged depends on location, time and urgency; see `quote_withdrawal`.
+#[query]
+fn get_fee_split(currency: Currency) -> Result<(u64, u64), WithdrawalError> {
+    let config = current_config();
+    let currency_config = config.withdrawal.currency(currency)?;
+    Ok((currency_config.platform_fee_basis_points, config.withdrawal.pricing.tiers.urban))
+}
+
+#[query]
+fn get_company_wallet_principal() -> Result<Principal, WithdrawalError> {
+    get_company_wallet().map_err(|reason| WithdrawalError::Misconfigured { reason })
+}
+
+// ============================================================================
+// REVENUE SWEEP
+// ============================================================================
+
+/// Sweep collected platform fees now instead of waiting for the timer
+/// (controllers, SNS governance or the company wallet).
+#[update]
+async fn sweep_platform_revenue() -> Result<Vec<RevenueSweep>, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if require_governance(caller).is_err() && Some(caller) != get_company_wallet().ok() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    sweep_platform_fees().await
+}
+
+/// Revenue sweeps, newest first (controllers, SNS governance or the company
+/// wallet). Pass the lowest sweep id of the previous page as `before`.
+#[query]
+fn get_revenue_sweeps(before: Option<u64>, limit: u64) -> Result<Vec<RevenueSweep>, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if require_governance(caller).is_err() && Some(caller) != get_company_wallet().ok() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    Ok(REVENUE_SWEEPS.with(|s| {
+        s.borrow()
+            .range(..before.unwrap_or(u64::MAX))
+            .rev()
+            .take(limit.min(audit::MAX_AUDIT_PAGE) as usize)
+            .map(|(_, sweep)| sweep)
+            .collect()
+    }))
+}
+
+/// Platform fees collected but not yet claimed by a sweep, per currency.
+#[query]
+fn get_unswept_platform_revenue() -> Vec<CurrencyAmount> {
+    currency_amounts(unswept_fees())
+}
+
+fn unswept_fees() -> BTreeMap<Currency, u64> {
+    UNSWEPT_FEES.with(|u| u.borrow().iter().filter(|(_, amount)| *amount > 0).collect())
+}
+
+fn record_fee_accrual(withdrawal: &WithdrawalTransaction) {
+    if withdrawal.platform_fee == 0 {
+        return;
+    }
+    FEE_ACCRUALS.with(|a| {
+        let mut accruals = a.borrow_mut();
+        let index = accruals.len();
+        accruals.insert(index, FeeAccrual {
+            withdrawal_id: withdrawal.id,
+            currency: withdrawal.currency,
+            platform_fee: withdrawal.platform_fee,
+        });
+    });
+    UNSWEPT_FEES.with(|u| {
+        let mut unswept = u.borrow_mut();
+        let total = unswept.get(&withdrawal.currency).unwrap_or(0) + withdrawal.platform_fee;
+        unswept.insert(withdrawal.currency, total);
+    });
+}
+
+fn sweep_watermark(currency: Currency) -> u64 {
+    SWEEP_WATERMARKS.with(|w| w.borrow().get(&currency).unwrap_or(0))
+}
+
+/// Split a sweep into (company, DAO treasury) amounts.
+fn split_sweep(amount: u64, dao_share_bps: u64) -> (u64, u64) {
+    let dao_amount = (amount * dao_share_bps) / 10000;
+    (amount - dao_amount, dao_amount)
+}
+
+/// Sweep every currency: finish earlier sweeps that had a failed transfer,
+/// then claim newly accrued fees. Only one sweep runs at a time.
+async fn sweep_platform_fees() -> Result<Vec<RevenueSweep>, WithdrawalError> {
+    let ledger = fiat_ledger_id().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    let company = get_company_wallet().map_err(|reason| WithdrawalError::Misconfigured { reason })?;
+    let sweep_config = current_config().withdrawal.revenue_sweep;
+    let dao = if sweep_config.dao_share_bps > 0 {
+        let treasury = Principal::from_text(&sweep_config.dao_treasury)
+            .map_err(|e| WithdrawalError::Misconfigured { reason: format!("Invalid DAO treasury principal: {}", e) })?;
+        Some((treasury, sweep_config.dao_share_bps))
+    } else {
+        None
+    };
+    
+    let _running = SweepGuard::acquire()?;
+    let now = ic_cdk::api::time();
+    LAST_SWEEP_AT.with(|l| l.borrow_mut().set(now).expect("Failed to persist last sweep time"));
+    
+    let mut due = unfinished_sweeps();
+    due.extend(claim_new_sweeps(company, dao, now));
+    
+    let mut swept = vec![];
+    let mut failed = vec![];
+    for sweep_id in due {
+        match run_sweep(ledger, sweep_id).await {
+            Ok(sweep) => swept.push(sweep),
+            Err(_) => failed.push(sweep_id),
+        }
+    }
+    
+    if failed.is_empty() {
+        Ok(swept)
+    } else {
+        Err(WithdrawalError::SweepIncomplete { failed })
+    }
+}
+
+fn unfinished_sweeps() -> Vec<u64> {
+    REVENUE_SWEEPS.with(|s| {
+        s.borrow().iter().filter(|(_, s)| s.completed_at.is_none()).map(|(id, _)| id).collect()
+    })
+}
+
+/// Create a sweep for each currency with fees past its watermark, and move
+/// the watermark past them so no later sweep can claim the same fees.
+fn claim_new_sweeps(company: Principal, dao: Option<(Principal, u64)>, now: u64) -> Vec<u64> {
+    let to_accrual = FEE_ACCRUALS.with(|a| a.borrow().len());
+    
+    unswept_fees()
+        .into_iter()
+        .map(|(currency, amount)| {
+            let (company_amount, dao_amount) = split_sweep(amount, dao.map_or(0, |(_, bps)| bps));
+            let sweep_id = REVENUE_SWEEPS.with(|s| {
+                let mut sweeps = s.borrow_mut();
+                let id = sweeps.len();
+                sweeps.insert(id, RevenueSweep {
+                    id,
+                    currency,
+                    from_accrual: sweep_watermark(currency),
+                    to_accrual,
+                    amount,
+                    company_wallet: company,
+                    company_amount,
+                    company_block: None,
+                    dao_treasury: dao.map(|(treasury, _)| treasury),
+                    dao_amount,
+                    dao_block: None,
+                    started_at: now,
+                    completed_at: None,
+                    last_error: None,
+                });
+                id
+            });
+            SWEEP_WATERMARKS.with(|w| w.borrow_mut().insert(currency, to_accrual));
+            UNSWEPT_FEES.with(|u| u.borrow_mut().remove(&currency));
+            sweep_id
+        })
+        .collect()
+}
+
+/// Pay whichever legs of a sweep are still unpaid.
+async fn run_sweep(ledger: Principal, sweep_id: u64) -> Result<RevenueSweep, WithdrawalError> {
+    let sweep = REVENUE_SWEEPS.with(|s| s.borrow().get(&sweep_id)).ok_or(WithdrawalError::NotFound)?;
+    
+    let mut result = Ok(());
+    if sweep.company_block.is_none() && sweep.company_amount > 0 {
+        result = sweep_transfer(ledger, &sweep, sweep.company_wallet, sweep.company_amount).await
+            .map(|block| update_sweep(sweep_id, |s| s.company_block = Some(block)));
+    }
+    let dao_due = result.is_ok() && sweep.dao_block.is_none() && sweep.dao_amount > 0;
+    if let (true, Some(treasury)) = (dao_due, sweep.dao_treasury) {
+        result = sweep_transfer(ledger, &sweep, treasury, sweep.dao_amount).await
+            .map(|block| update_sweep(sweep_id, |s| s.dao_block = Some(block)));
+    }
+    
+    let now = ic_cdk::api::time();
+    match result {
+        Ok(()) => update_sweep(sweep_id, |s| {
+            s.completed_at = Some(now);
+            s.last_error = None;
+        }),
+        Err(ref error) => update_sweep(sweep_id, |s| s.last_error = Some(format!("{:?}", error))),
+    }
+    audit::record(
+        ic_cdk::api::canister_self(),
+        if result.is_ok() { AuditAction::RevenueSwept } else { AuditAction::RevenueSweepFailed },
+        AuditEntity::RevenueSweep { id: sweep_id },
+        Transition::default().with_note(format!("{} {}", sweep.amount, sweep.currency.code())),
+        now,
+    );
+    
+    result?;
+    REVENUE_SWEEPS.with(|s| s.borrow().get(&sweep_id)).ok_or(WithdrawalError::NotFound)
+}
+
+async fn sweep_transfer(ledger: Principal, sweep: &RevenueSweep, to: Principal, amount: u64) -> Result<u64, WithdrawalError> {
+    fiat_ledger::transfer(ledger, TransferArgs {
+        from_subaccount: Some(fee_subaccount()),
+        to: Account::of(to),
+        currency: sweep.currency,
+        amount,
+        memo: Some(sweep.id.to_be_bytes().to_vec()),
     })
+    .await
+    .map_err(|reason| WithdrawalError::LedgerUnavailable { reason })?
+    .map_err(ledger_error)
+}
+
+fn update_sweep(sweep_id: u64, update: impl FnOnce(&mut RevenueSweep)) {
+    REVENUE_SWEEPS.with(|s| {
+        let mut sweeps = s.borrow_mut();
+        if let Some(mut sweep) = sweeps.get(&sweep_id) {
+            update(&mut sweep);
+            sweeps.insert(sweep_id, sweep);
+        }
+    });
+}
+
+/// Keeps the timer and a manual trigger from sweeping at the same time.
+struct SweepGuard;
+
+impl SweepGuard {
+    fn acquire() -> Result<Self, WithdrawalError> {
+        if SWEEP_RUNNING.with(|r| r.replace(true)) {
+            return Err(WithdrawalError::SweepInProgress);
+        }
+        Ok(SweepGuard)
+    }
+}
+
+impl Drop for SweepGuard {
+    fn drop(&mut self) {
+        SWEEP_RUNNING.with(|r| *r.borrow_mut() = false);
+    }
 }
 
+// ============================================================================
+// AUDIT LOG
+// ============================================================================
+
+/// Audit events, newest first (controllers, SNS governance or the company
+/// wallet). Pass the lowest event id of the previous page as `before`.
+#[query]
+fn get_audit_events(before: Option<u64>, limit: u64) -> Result<Vec<AuditEvent>, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if require_governance(caller).is_err() && Some(caller) != get_company_wallet().ok() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    Ok(audit::events(before, limit))
+}
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+// Sanity bounds for runtime config changes
+const MAX_TOTAL_FEE_BPS: u64 = 2_500;
+const MIN_UTC_OFFSET_HOURS: i64 = -12;
+const MAX_UTC_OFFSET_HOURS: i64 = 14;
+const MAX_QUOTE_VALIDITY_SECONDS: u64 = 3_600;
+const MAX_CODE_VALIDITY_HOURS: u64 = 168;
+const MAX_RELEASE_ATTEMPTS: u32 = 10;
+const MAX_SWEEP_INTERVAL_HOURS: u64 = 24 * 31;
+
 #[query]
-fn get_fee_split() -> (u64, u64) {
-    let config = get_config();
-    (config.withdrawal.platform_fee_basis_points, config.withdrawal.agent_commission_basis_points)
+fn get_config() -> RevenueConfig {
+    current_config()
+}
+
+/// Replace the live configuration (controllers or SNS governance only).
+#[update]
+fn set_config(config: RevenueConfig) -> Result<ConfigChange, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    require_governance(caller)?;
+    validate_config(&config).map_err(|reason| WithdrawalError::InvalidInput { reason })?;
+    
+    Ok(apply_config(config, caller, ic_cdk::api::time()))
 }
 
+/// Every runtime config change, newest first.
 #[query]
-fn get_company_wallet_principal() -> Result<Principal, String> {
-    get_company_wallet()
+fn get_config_history() -> Result<Vec<ConfigChange>, WithdrawalError> {
+    let caller = ic_cdk::api::msg_caller();
+    if require_governance(caller).is_err() && Some(caller) != get_company_wallet().ok() {
+        return Err(WithdrawalError::Unauthorized);
+    }
+    
+    Ok(CONFIG_HISTORY.with(|h| h.borrow().iter().rev().map(|(_, change)| change).collect()))
+}
+
+fn apply_config(config: RevenueConfig, actor: Principal, now: u64) -> ConfigChange {
+    let id = CONFIG_HISTORY.with(|h| h.borrow().last_key_value().map_or(1, |(id, _)| id + 1));
+    let change = ConfigChange {
+        id,
+        changed_by: actor,
+        changed_at: now,
+        old: current_config(),
+        new: config.clone(),
+    };
+    
+    CONFIG_HISTORY.with(|h| h.borrow_mut().insert(id, change.clone()));
+    CONFIG.with(|c| *c.borrow_mut() = Some(config));
+    audit::record(
+        actor,
+        AuditAction::ConfigChanged,
+        AuditEntity::Config { change_id: id },
+        Transition::default().with_note("Old and new values are in get_config_history"),
+        now,
+    );
+    change
+}
+
+fn require_governance(caller: Principal) -> Result<(), WithdrawalError> {
+    if ic_cdk::api::is_controller(&caller) || is_sns_governance(caller) {
+        Ok(())
+    } else {
+        Err(WithdrawalError::Unauthorized)
+    }
+}
+
+fn is_sns_governance(caller: Principal) -> bool {
+    let governance = current_config().governance.sns_governance;
+    !governance.is_empty() && Principal::from_text(&governance).is_ok_and(|p| p == caller)
+}
+
+fn parse_principal(field: &str, text: &str) -> Result<Principal, String> {
+    Principal::from_text(text).map_err(|e| format!("{} is not a valid principal: {}", field, e))
+}
+
+fn validate_revenue_sweep(sweep: &RevenueSweepConfig) -> Result<(), String> {
+    if sweep.interval_hours == 0 || sweep.interval_hours > MAX_SWEEP_INTERVAL_HOURS {
+        return Err(format!("revenue_sweep.interval_hours must be between 1 and {}", MAX_SWEEP_INTERVAL_HOURS));
+    }
+    if sweep.dao_share_bps > 10000 {
+        return Err("revenue_sweep.dao_share_bps must be at most 10000".to_string());
+    }
+    if !sweep.dao_treasury.is_empty() {
+        parse_principal("revenue_sweep.dao_treasury", &sweep.dao_treasury)?;
+    } else if sweep.dao_share_bps > 0 {
+        return Err("revenue_sweep.dao_treasury is required when dao_share_bps is set".to_string());
+    }
+    Ok(())
+}
+
+fn validate_config(config: &RevenueConfig) -> Result<(), String> {
+    let company = parse_principal("company_wallet.principal", &config.company_wallet.principal)?;
+    if company == Principal::anonymous() {
+        return Err("company_wallet.principal must not be anonymous".to_string());
+    }
+    if !config.agent_registry.canister_id.is_empty() {
+        parse_principal("agent_registry.canister_id", &config.agent_registry.canister_id)?;
+    }
+    if !config.withdrawal.fiat_ledger.is_empty() {
+        parse_principal("withdrawal.fiat_ledger", &config.withdrawal.fiat_ledger)?;
+    }
+    if !config.withdrawal.deposit_canister.is_empty() {
+        parse_principal("withdrawal.deposit_canister", &config.withdrawal.deposit_canister)?;
+    }
+    if !config.governance.sns_governance.is_empty() {
+        parse_principal("governance.sns_governance", &config.governance.sns_governance)?;
+    }
+    
+    if !config.withdrawal.notifier.is_empty() {
+        parse_principal("withdrawal.notifier", &config.withdrawal.notifier)?;
+    }
+    if config.withdrawal.code_validity_hours == 0 || config.withdrawal.code_validity_hours > MAX_CODE_VALIDITY_HOURS {
+        return Err(format!("withdrawal.code_validity_hours must be between 1 and {}", MAX_CODE_VALIDITY_HOURS));
+    }
+    if config.withdrawal.max_release_attempts == 0 || config.withdrawal.max_release_attempts > MAX_RELEASE_ATTEMPTS {
+        return Err(format!("withdrawal.max_release_attempts must be between 1 and {}", MAX_RELEASE_ATTEMPTS));
+    }
+    if config.withdrawal.quote_validity_seconds == 0 || config.withdrawal.quote_validity_seconds > MAX_QUOTE_VALIDITY_SECONDS {
+        return Err(format!("withdrawal.quote_validity_seconds must be between 1 and {}", MAX_QUOTE_VALIDITY_SECONDS));
+    }
+    pricing::validate(&config.withdrawal.pricing)?;
+    validate_revenue_sweep(&config.withdrawal.revenue_sweep)?;
+    fraud::validate(&config.withdrawal.fraud)?;
+    
+    if config.withdrawal.currencies.is_empty() {
+        return Err("At least one currency must be configured".to_string());
+    }
+    for (code, currency) in &config.withdrawal.currencies {
+        Currency::from_code(code).ok_or(format!("Unknown currency '{}'", code))?;
+        
+        let total_fee = currency.platform_fee_basis_points.saturating_add(config.withdrawal.pricing.max_agent_fee_bps);
+        if total_fee > MAX_TOTAL_FEE_BPS {
+            return Err(format!(
+                "{}: platform fee plus maximum agent fee must be at most {} bps",
+                code, MAX_TOTAL_FEE_BPS
+            ));
+        }
+        if !(MIN_UTC_OFFSET_HOURS..=MAX_UTC_OFFSET_HOURS).contains(&currency.utc_offset_hours) {
+            return Err(format!("{}: utc_offset_hours is out of range", code));
+        }
+        if currency.min_withdrawal == 0 || currency.min_withdrawal > currency.max_withdrawal {
+            return Err(format!("{}: min_withdrawal must be positive and at most max_withdrawal", code));
+        }
+    }
+    
+    Ok(())
 }
 
 // ============================================================================
@@ -330,6 +2711,15 @@ fn generate_withdrawal_code(id: u64) -> String {
     format!("WTH-{:08}", id)
 }
 
+fn next_id(counter: &'static std::thread::LocalKey<RefCell<StableCell<u64, Memory>>>) -> u64 {
+    counter.with(|c| {
+        let mut cell = c.borrow_mut();
+        let current = *cell.get();
+        cell.set(current + 1).expect("Failed to persist id counter");
+        current
+    })
+}
+
 // Tests module
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,132 @@
+//! Outbox of withdrawal notifications.
+//!
+//! The canister cannot send SMS itself. Events users and agents should hear
+//! about are queued here, and the satellite polls `get_notifications` with
+//! the last id it has seen and forwards each one by SMS. It then acknowledges
+//! what it sent, which deletes it: release secrets are only kept until they
+//! are delivered.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::storage::{self, Memory};
+use crate::Currency;
+
+/// Maximum number of notifications returned by a single poll.
+pub const MAX_NOTIFICATION_PAGE: u64 = 100;
+
+/// Undelivered notifications kept at most; past this the oldest are dropped,
+/// so a notifier that stops acknowledging cannot grow the outbox unbounded.
+/// Release codes are never dropped: without one the user could not release
+/// the cash. They are bounded by the pending withdrawals instead.
+pub const MAX_OUTBOX_LEN: u64 = 10_000;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum NotificationKind {
+    /// The one-time secret the user gives the agent to release the cash
+    ReleaseCode { code: String },
+    /// Too many wrong release secrets were entered; the user should cancel
+    ReleaseLocked,
+    /// Cancelled by the user or the agent; the amount went back to the user
+    WithdrawalCancelled { by: Principal },
+    /// Not confirmed within its validity window; the amount went back to the user
+    WithdrawalExpired,
+    /// Frozen by the fraud rules until staff review it
+    HeldForReview,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub id: u64,
+    pub created_at: u64,
+    /// Whose phone the satellite should text
+    pub recipient: Principal,
+    pub withdrawal_id: u64,
+    pub withdrawal_code: String,
+    pub currency: Currency,
+    pub amount: u64,
+    pub kind: NotificationKind,
+}
+
+thread_local! {
+    // Undelivered notifications, oldest first
+    static OUTBOX: RefCell<StableBTreeMap<u64, Notification, Memory>> = RefCell::new(
+        StableBTreeMap::init(storage::memory(storage::OUTBOX_MEMORY_ID))
+    );
+
+    static NEXT_NOTIFICATION_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(storage::memory(storage::NEXT_NOTIFICATION_ID_MEMORY_ID), 0)
+            .expect("Failed to init notification id counter")
+    );
+}
+
+/// Queue a notification and return its id.
+pub fn push(
+    recipient: Principal,
+    withdrawal_id: u64,
+    withdrawal_code: &str,
+    currency: Currency,
+    amount: u64,
+    kind: NotificationKind,
+    now: u64,
+) -> u64 {
+    let id = NEXT_NOTIFICATION_ID.with(|n| {
+        let mut next = n.borrow_mut();
+        let id = *next.get();
+        next.set(id + 1).expect("Failed to persist notification id counter");
+        id
+    });
+    
+    OUTBOX.with(|o| {
+        let mut outbox = o.borrow_mut();
+        outbox.insert(id, Notification {
+            id,
+            created_at: now,
+            recipient,
+            withdrawal_id,
+            withdrawal_code: withdrawal_code.to_string(),
+            currency,
+            amount,
+            kind,
+        });
+        while outbox.len() > MAX_OUTBOX_LEN {
+            let oldest = outbox.iter()
+                .find(|(_, n)| !matches!(n.kind, NotificationKind::ReleaseCode { .. }))
+                .map(|(id, _)| id);
+            match oldest {
+                Some(oldest) => outbox.remove(&oldest),
+                None => break,
+            };
+        }
+        id
+    })
+}
+
+/// Notifications oldest first. `after` is the last id the caller has seen.
+pub fn since(after: Option<u64>, limit: u64) -> Vec<Notification> {
+    let Some(start) = after.map_or(Some(0), |a| a.checked_add(1)) else {
+        return vec![];
+    };
+    OUTBOX.with(|o| {
+        o.borrow()
+            .range(start..)
+            .take(limit.min(MAX_NOTIFICATION_PAGE) as usize)
+            .map(|(_, notification)| notification)
+            .collect()
+    })
+}
+
+/// Delete every notification up to and including `up_to`, once delivered.
+/// Returns how many were deleted.
+pub fn acknowledge(up_to: u64) -> u64 {
+    OUTBOX.with(|o| {
+        let mut outbox = o.borrow_mut();
+        let delivered: Vec<u64> = outbox.range(..=up_to).map(|(id, _)| id).collect();
+        for id in &delivered {
+            outbox.remove(id);
+        }
+        delivered.len() as u64
+    })
+}
@@ -0,0 +1,146 @@
+//! Dynamic agent fee pricing.
+//!
+//! The agent fee starts from the base rate of the agent's location tier and
+//! is raised by surcharges for night hours, weekends and urgent service, as
+//! laid out in the whitepaper's fee structure. Surcharges are percentages of
+//! the base rate and add up; the result is held within configured bounds.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SECONDS_PER_HOUR: i64 = 3_600;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// How well served the agent's area is; remote areas pay agents more.
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LocationTier {
+    #[default]
+    Urban,
+    Suburban,
+    Rural,
+    Remote,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Urgency {
+    #[default]
+    Standard,
+    Express,
+    Emergency,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PricingConfig {
+    // Base agent fee per location tier, in basis points
+    pub tiers: TierRates,
+    // Local hours [night_start_hour, night_end_hour) count as night; may wrap midnight
+    pub night_start_hour: u64,
+    pub night_end_hour: u64,
+    // Surcharges, in percent of the base rate
+    pub night_surcharge_percent: u64,
+    pub weekend_surcharge_percent: u64,
+    pub express_surcharge_percent: u64,
+    pub emergency_surcharge_percent: u64,
+    // Bounds on the final agent fee, in basis points
+    pub min_agent_fee_bps: u64,
+    pub max_agent_fee_bps: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TierRates {
+    pub urban: u64,
+    pub suburban: u64,
+    pub rural: u64,
+    pub remote: u64,
+}
+
+impl TierRates {
+    pub fn rate(&self, tier: LocationTier) -> u64 {
+        match tier {
+            LocationTier::Urban => self.urban,
+            LocationTier::Suburban => self.suburban,
+            LocationTier::Rural => self.rural,
+            LocationTier::Remote => self.remote,
+        }
+    }
+}
+
+/// How an agent fee was arrived at. Surcharges that did not apply are 0.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentFeeBreakdown {
+    pub location_tier: LocationTier,
+    pub urgency: Urgency,
+    pub base_bps: u64,
+    pub night_surcharge_percent: u64,
+    pub weekend_surcharge_percent: u64,
+    pub urgency_surcharge_percent: u64,
+    /// Final rate after surcharges and bounds
+    pub agent_fee_bps: u64,
+}
+
+/// Price the agent fee for a withdrawal made at `now` (nanoseconds) in a
+/// country `utc_offset_hours` from UTC.
+pub fn agent_fee_bps(
+    config: &PricingConfig,
+    location_tier: LocationTier,
+    urgency: Urgency,
+    now: u64,
+    utc_offset_hours: i64,
+) -> AgentFeeBreakdown {
+    let local_seconds = (now / NANOS_PER_SECOND) as i64 + utc_offset_hours * SECONDS_PER_HOUR;
+    let hour = local_seconds.rem_euclid(SECONDS_PER_DAY) / SECONDS_PER_HOUR;
+    // 1970-01-01 was a Thursday; weekday 0 is Monday
+    let weekday = (local_seconds.div_euclid(SECONDS_PER_DAY) + 3).rem_euclid(7);
+
+    let night = is_night(config, hour as u64);
+    let weekend = weekday >= 5;
+
+    let base_bps = config.tiers.rate(location_tier);
+    let night_surcharge_percent = if night { config.night_surcharge_percent } else { 0 };
+    let weekend_surcharge_percent = if weekend { config.weekend_surcharge_percent } else { 0 };
+    let urgency_surcharge_percent = match urgency {
+        Urgency::Standard => 0,
+        Urgency::Express => config.express_surcharge_percent,
+        Urgency::Emergency => config.emergency_surcharge_percent,
+    };
+
+    let surcharge_percent = night_surcharge_percent
+        .saturating_add(weekend_surcharge_percent)
+        .saturating_add(urgency_surcharge_percent);
+    let raw_bps = base_bps.saturating_mul(100u64.saturating_add(surcharge_percent)) / 100;
+
+    AgentFeeBreakdown {
+        location_tier,
+        urgency,
+        base_bps,
+        night_surcharge_percent,
+        weekend_surcharge_percent,
+        urgency_surcharge_percent,
+        agent_fee_bps: raw_bps.clamp(config.min_agent_fee_bps, config.max_agent_fee_bps),
+    }
+}
+
+fn is_night(config: &PricingConfig, hour: u64) -> bool {
+    if config.night_start_hour <= config.night_end_hour {
+        hour >= config.night_start_hour && hour < config.night_end_hour
+    } else {
+        hour >= config.night_start_hour || hour < config.night_end_hour
+    }
+}
+
+pub fn validate(config: &PricingConfig) -> Result<(), String> {
+    if config.night_start_hour > 23 || config.night_end_hour > 23 {
+        return Err("pricing: night hours must be between 0 and 23".to_string());
+    }
+    if config.min_agent_fee_bps > config.max_agent_fee_bps {
+        return Err("pricing: min_agent_fee_bps must be at most max_agent_fee_bps".to_string());
+    }
+
+    let tiers = &config.tiers;
+    if !(tiers.urban <= tiers.suburban && tiers.suburban <= tiers.rural && tiers.rural <= tiers.remote) {
+        return Err("pricing: tier rates must not decrease from urban to remote".to_string());
+    }
+
+    Ok(())
+}
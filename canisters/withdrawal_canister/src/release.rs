@@ -0,0 +1,73 @@
+//! Two-sided release handshake.
+//!
+//! The withdrawal code identifies a request and is visible to the agent, so
+//! on its own it proves nothing about the user being present. Each withdrawal
+//! also gets a one-time release secret that only the user receives (by SMS);
+//! the agent must enter it to confirm. Only a salted hash of the secret is
+//! kept, and too many wrong entries lock the withdrawal until it is cancelled.
+
+use candid::{CandidType, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::WithdrawalError;
+
+/// Digits in a release secret; short enough to read out at a kiosk.
+pub const SECRET_DIGITS: u32 = 6;
+const SALT_LEN: usize = 16;
+
+/// Entropy consumed by `new_secret`.
+pub const ENTROPY_LEN: usize = SALT_LEN + 4;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReleaseLock {
+    salt: [u8; SALT_LEN],
+    hash: [u8; 32],
+    pub failed_attempts: u32,
+    pub locked: bool,
+}
+
+/// Draw a release secret from `ENTROPY_LEN` bytes of entropy. Returns the
+/// secret for delivery to the user and the lock to store.
+pub fn new_secret(entropy: &[u8]) -> (String, ReleaseLock) {
+    assert!(entropy.len() >= ENTROPY_LEN, "Not enough entropy for a release secret");
+
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&entropy[..SALT_LEN]);
+    let mut number = [0u8; 4];
+    number.copy_from_slice(&entropy[SALT_LEN..ENTROPY_LEN]);
+
+    let secret = format!(
+        "{:0width$}",
+        u32::from_be_bytes(number) % 10u32.pow(SECRET_DIGITS),
+        width = SECRET_DIGITS as usize
+    );
+    let lock = ReleaseLock { salt, hash: digest(&salt, &secret), failed_attempts: 0, locked: false };
+    (secret, lock)
+}
+
+/// Check the secret the agent entered. Every wrong entry counts; reaching
+/// `max_attempts` locks the withdrawal for good.
+pub fn verify(lock: &mut ReleaseLock, input: &str, max_attempts: u32) -> Result<(), WithdrawalError> {
+    if lock.locked {
+        return Err(WithdrawalError::ReleaseLocked);
+    }
+
+    let entered: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    if digest(&lock.salt, &entered) == lock.hash {
+        return Ok(());
+    }
+
+    lock.failed_attempts += 1;
+    if lock.failed_attempts >= max_attempts {
+        lock.locked = true;
+        return Err(WithdrawalError::ReleaseLocked);
+    }
+    Err(WithdrawalError::WrongReleaseCode { attempts_left: max_attempts - lock.failed_attempts })
+}
+
+fn digest(salt: &[u8], secret: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(secret.as_bytes());
+    hasher.finalize().into()
+}
@@ -0,0 +1,34 @@
+//! Agent reputation from withdrawal outcomes and ratings; see `canister_shared::reputation`.
+
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+pub use canister_shared::reputation::{validate_rating, AgentReputation, Tally};
+use canister_shared::reputation::{RequestOutcome, Stage};
+
+use crate::{TransactionStatus, WithdrawalTransaction};
+
+/// A user's rating of the agent that handled one of their withdrawals.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AgentRating {
+    pub withdrawal_id: u64,
+    pub agent: Principal,
+    pub user: Principal,
+    pub stars: u8,
+    pub comment: Option<String>,
+    pub rated_at: u64,
+}
+
+/// What a withdrawal contributes to its agent's tally.
+pub fn outcome(withdrawal: &WithdrawalTransaction) -> RequestOutcome {
+    RequestOutcome {
+        stage: match withdrawal.status {
+            TransactionStatus::Pending => Stage::Pending,
+            TransactionStatus::Confirmed => Stage::Confirmed,
+            TransactionStatus::Cancelled => Stage::Cancelled,
+            TransactionStatus::Expired => Stage::Expired,
+        },
+        disputed: false,
+        confirmation_latency: withdrawal.confirmed_at.map(|at| at.saturating_sub(withdrawal.timestamp)),
+    }
+}
@@ -0,0 +1,169 @@
+//! Stable memory layout for the withdrawal canister.
+//!
+//! Every persistent collection lives in its own virtual memory so that state
+//! survives canister upgrades without a `pre_upgrade` serialization step.
+//! Records are written inside a versioned envelope: when a record layout
+//! changes, add a new variant and convert older variants on read.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use canister_shared::ordered_key::{put_principal, take_principal, take_u64, ORDERED_PRINCIPAL_LEN};
+
+use crate::notifications::Notification;
+use crate::pricing::LocationTier;
+use crate::release::ReleaseLock;
+use crate::reputation::AgentRating;
+use crate::{
+    AgentCurrencyKey, AgentEarnings, AgentPayout, CashFloat, ConfigChange, FeeAccrual, RevenueSweep,
+    UserWithdrawalKey, WithdrawalQuote, WithdrawalTransaction,
+};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Current layout of the records stored by this canister.
+/// Bump this whenever a new envelope variant is introduced.
+pub const SCHEMA_VERSION: u32 = 5;
+
+// Memory IDs are part of the on-chain layout: never reuse or renumber them.
+pub const CONFIG_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const NEXT_WITHDRAWAL_ID_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const AGENT_EARNINGS_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const ESCROW_IN_FLIGHT_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const AGENT_LOCATION_TIERS_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const QUOTES_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const QUOTE_EXPIRIES_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const NEXT_QUOTE_ID_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const AGENT_PAYOUTS_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const OUTBOX_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const NEXT_NOTIFICATION_ID_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const RELEASE_LOCKS_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const CASH_FLOATS_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub const RESERVED_CASH_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub const FEE_ACCRUALS_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub const SWEEP_WATERMARKS_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub const UNSWEPT_FEES_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub const REVENUE_SWEEPS_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub const LAST_SWEEP_AT_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub const AGENT_RATINGS_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub const AGENT_TALLIES_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub const FRAUD_ALERTS_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub const WITHDRAWAL_ALERTS_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub const WITHDRAWAL_EXPIRIES_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub const USER_WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub const WITHDRAWAL_CODES_MEMORY_ID: MemoryId = MemoryId::new(28);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+fn encode<T: CandidType>(value: &T) -> Cow<'static, [u8]> {
+    Cow::Owned(candid::encode_one(value).expect("Failed to encode stable record"))
+}
+
+fn decode<T: CandidType + for<'de> Deserialize<'de>>(bytes: &[u8]) -> T {
+    candid::decode_one(bytes).expect("Failed to decode stable record")
+}
+
+// ============================================================================
+// VERSIONED ENVELOPES
+// ============================================================================
+
+/// Implements `Storable` for a record through a versioned envelope enum.
+/// Once a record has more than one variant, write the impl by hand instead.
+macro_rules! versioned_storable {
+    ($record:ty, $envelope:ident) => {
+        #[derive(CandidType, Deserialize)]
+        enum $envelope {
+            V1($record),
+        }
+
+        impl Storable for $record {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                encode(&$envelope::V1(self.clone()))
+            }
+
+            fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+                match decode(&bytes) {
+                    $envelope::V1(record) => record,
+                }
+            }
+
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    };
+}
+
+versioned_storable!(WithdrawalTransaction, StoredWithdrawalTransaction);
+versioned_storable!(AgentEarnings, StoredAgentEarnings);
+versioned_storable!(ConfigChange, StoredConfigChange);
+versioned_storable!(LocationTier, StoredLocationTier);
+versioned_storable!(WithdrawalQuote, StoredWithdrawalQuote);
+versioned_storable!(AgentPayout, StoredAgentPayout);
+versioned_storable!(Notification, StoredNotification);
+versioned_storable!(ReleaseLock, StoredReleaseLock);
+versioned_storable!(CashFloat, StoredCashFloat);
+versioned_storable!(FeeAccrual, StoredFeeAccrual);
+versioned_storable!(RevenueSweep, StoredRevenueSweep);
+versioned_storable!(AgentRating, StoredAgentRating);
+
+// ============================================================================
+// KEYS
+// ============================================================================
+
+/// Implements `Storable` for a map key. Key encodings are frozen: changing
+/// one would orphan every entry already stored under it.
+macro_rules! candid_storable_key {
+    ($key:ty) => {
+        impl Storable for $key {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                encode(self)
+            }
+
+            fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+                decode(&bytes)
+            }
+
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    };
+}
+
+candid_storable_key!(AgentCurrencyKey);
+
+// Keys that are range-scanned use the ordered encoding from `canister_shared`.
+
+impl Storable for UserWithdrawalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut buf = Vec::with_capacity(Self::SIZE as usize);
+        put_principal(&mut buf, &self.user);
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        Cow::Owned(buf)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (user, rest) = take_principal(&bytes);
+        let (timestamp, rest) = take_u64(rest);
+        let (id, _) = take_u64(rest);
+        UserWithdrawalKey { user, timestamp, id }
+    }
+
+    const BOUND: Bound = Bound::Bounded { max_size: Self::SIZE, is_fixed_size: true };
+}
+
+impl UserWithdrawalKey {
+    const SIZE: u32 = ORDERED_PRINCIPAL_LEN + 8 + 8;
+}
@@ -2,7 +2,7 @@ use super::*;
 
 // Test constants - match the config values
 const TEST_PLATFORM_FEE_BPS: u64 = 50; // 0.5% from revenue_config.toml
-const TEST_AGENT_FEE_BPS: u64 = 1000; // 10% from revenue_config.toml
+const TEST_AGENT_FEE_BPS: u64 = 1000; // 10%, within the priced 2-12% range
 
 // ============================================================================
 // FEE SPLIT TESTS - CORRECT REVENUE MODEL
@@ -296,3 +296,1139 @@ fn test_platform_revenue_scaling() {
         assert_eq!(revenue, volume / 10);
     }
 }
+
+// ============================================================================
+// MULTI-CURRENCY TESTS
+// ============================================================================
+
+fn test_config() -> WithdrawalConfig {
+    let config: RevenueConfig = toml::from_str(CONFIG_TOML).expect("Failed to parse revenue_config.toml");
+    config.withdrawal
+}
+
+fn sample_withdrawal(currency: Currency, amount: u64, agent_fee: u64) -> WithdrawalTransaction {
+    WithdrawalTransaction {
+        id: 1,
+        user_principal: Principal::from_slice(&[1]),
+        agent_principal: Principal::from_slice(&[2]),
+        currency,
+        amount,
+        platform_fee: 0,
+        agent_fee,
+        withdrawal_code: generate_withdrawal_code(1),
+        timestamp: 0,
+        expires_at: 0,
+        confirmed_at: None,
+        status: TransactionStatus::Confirmed,
+        escrow: None,
+    }
+}
+
+#[test]
+fn test_config_fee_rates_match_test_constants() {
+    let ugx = test_config().currency(Currency::UGX).cloned().unwrap();
+    
+    assert_eq!(calculate_fees(100_000, &ugx, TEST_AGENT_FEE_BPS), (
+        (100_000 * TEST_PLATFORM_FEE_BPS) / 10000,
+        (100_000 * TEST_AGENT_FEE_BPS) / 10000,
+    ));
+}
+
+#[test]
+fn test_amount_bounds_are_per_currency() {
+    let config = test_config();
+    let ugx = config.currency(Currency::UGX).unwrap();
+    let kes = config.currency(Currency::KES).unwrap();
+    
+    assert_eq!(check_amount_bounds(0, ugx), Err(WithdrawalError::InvalidAmount));
+    assert_eq!(
+        check_amount_bounds(ugx.min_withdrawal - 1, ugx),
+        Err(WithdrawalError::BelowMinimum { min: ugx.min_withdrawal })
+    );
+    assert!(check_amount_bounds(ugx.max_withdrawal, ugx).is_ok());
+    
+    // A valid UGX amount is far above the KES maximum
+    assert!(check_amount_bounds(ugx.max_withdrawal, kes).is_err());
+    assert!(check_amount_bounds(kes.max_withdrawal, kes).is_ok());
+}
+
+#[test]
+fn test_unconfigured_currency_is_rejected() {
+    let mut config = test_config();
+    config.currencies.remove("NGN");
+    
+    assert_eq!(config.currency(Currency::NGN).err(), Some(WithdrawalError::UnsupportedCurrency { currency: Currency::NGN }));
+    assert!(config.currency(Currency::UGX).is_ok());
+}
+
+#[test]
+fn test_agent_earnings_are_kept_per_currency() {
+    update_agent_earnings(&sample_withdrawal(Currency::UGX, 100_000, 10_000), 1);
+    update_agent_earnings(&sample_withdrawal(Currency::KES, 2_000, 200), 2);
+    update_agent_earnings(&sample_withdrawal(Currency::UGX, 50_000, 5_000), 3);
+    
+    let earnings = AGENT_EARNINGS.with(|e| e.borrow().get(&Principal::from_slice(&[2]))).unwrap();
+    assert_eq!(earnings.currencies.len(), 2);
+    assert_eq!(earnings.last_withdrawal_date, Some(3));
+    
+    assert_eq!(get_total_agent_earnings(), vec![
+        CurrencyAmount { currency: Currency::UGX, amount: 15_000 },
+        CurrencyAmount { currency: Currency::KES, amount: 200 },
+    ]);
+}
+
+// ============================================================================
+// CONFIGURATION TESTS
+// ============================================================================
+
+fn test_revenue_config() -> RevenueConfig {
+    toml::from_str(CONFIG_TOML).expect("Failed to parse revenue_config.toml")
+}
+
+#[test]
+fn test_shipped_config_passes_validation() {
+    assert_eq!(validate_config(&test_revenue_config()), Ok(()));
+}
+
+#[test]
+fn test_config_validation_rejects_bad_values() {
+    let mut config = test_revenue_config();
+    config.withdrawal.currencies.get_mut("UGX").unwrap().platform_fee_basis_points = MAX_TOTAL_FEE_BPS;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    let kes = config.withdrawal.currencies.get_mut("KES").unwrap();
+    kes.min_withdrawal = kes.max_withdrawal + 1;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.agent_registry.canister_id = "not-a-principal".to_string();
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.withdrawal.fiat_ledger = "not-a-principal".to_string();
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn test_config_change_is_recorded_and_survives_reload() {
+    load_config();
+    let governor = Principal::from_slice(&[11]);
+    let old = current_config();
+    
+    let mut new = old.clone();
+    new.withdrawal.currencies.get_mut("UGX").unwrap().platform_fee_basis_points = 75;
+    
+    let change = apply_config(new.clone(), governor, 1_000);
+    assert_eq!(change.id, 1);
+    assert_eq!(change.changed_by, governor);
+    assert_eq!(change.old, old);
+    assert_eq!(current_config(), new);
+    
+    // The newest history entry wins over the TOML defaults, e.g. after an upgrade
+    load_config();
+    assert_eq!(current_config(), new);
+    
+    assert_eq!(apply_config(old, governor, 2_000).id, 2);
+}
+
+// ============================================================================
+// AUDIT LOG TESTS
+// ============================================================================
+
+#[test]
+fn test_config_change_is_audited() {
+    load_config();
+    let governor = Principal::from_slice(&[11]);
+    let change = apply_config(current_config(), governor, 1_000);
+    
+    let events = audit::events(None, 10);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor, governor);
+    assert_eq!(events[0].action, AuditAction::ConfigChanged);
+    assert_eq!(events[0].entity, AuditEntity::Config { change_id: change.id });
+    assert_eq!(events[0].timestamp, 1_000);
+}
+
+#[test]
+fn test_audit_events_page_newest_first() {
+    for id in 0..5 {
+        audit::record(
+            Principal::anonymous(),
+            AuditAction::WithdrawalCreated,
+            AuditEntity::Withdrawal { id },
+            Transition::status(None, &TransactionStatus::Pending),
+            id,
+        );
+    }
+    
+    let first: Vec<u64> = audit::events(None, 2).iter().map(|e| e.id).collect();
+    assert_eq!(first, vec![4, 3]);
+    
+    let rest: Vec<u64> = audit::events(Some(3), 10).iter().map(|e| e.id).collect();
+    assert_eq!(rest, vec![2, 1, 0]);
+}
+
+// ============================================================================
+// ESCROW TESTS
+// ============================================================================
+
+#[test]
+fn test_escrow_subaccounts_are_distinct() {
+    assert_ne!(escrow_subaccount(1), escrow_subaccount(2));
+    assert_ne!(escrow_subaccount(0), fee_subaccount());
+    assert_eq!(escrow_subaccount(1)[24..], 1u64.to_be_bytes());
+}
+
+#[test]
+fn test_escrow_split_pays_agent_amount_minus_fees() {
+    let mut withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    withdrawal.platform_fee = 500;
+    
+    let (payout, fees) = escrow_split(&withdrawal);
+    assert_eq!(payout, 96_500);
+    assert_eq!(fees, 3_500);
+    assert_eq!(payout + fees, withdrawal.amount);
+}
+
+// ============================================================================
+// PRICING TESTS
+// ============================================================================
+
+// 2024-01-01 was a Monday
+const MONDAY_UTC: u64 = 1_704_067_200 * 1_000_000_000;
+const HOUR: u64 = 3_600 * 1_000_000_000;
+const DAY: u64 = 24 * HOUR;
+
+fn test_pricing() -> PricingConfig {
+    test_revenue_config().withdrawal.pricing
+}
+
+#[test]
+fn test_agent_fee_uses_tier_base_rate() {
+    let pricing = test_pricing();
+    let monday_noon = MONDAY_UTC + 9 * HOUR; // 12:00 in Kampala
+    
+    let urban = pricing::agent_fee_bps(&pricing, LocationTier::Urban, Urgency::Standard, monday_noon, 3);
+    assert_eq!(urban.agent_fee_bps, 250);
+    assert_eq!(urban.night_surcharge_percent + urban.weekend_surcharge_percent + urban.urgency_surcharge_percent, 0);
+    
+    let rural = pricing::agent_fee_bps(&pricing, LocationTier::Rural, Urgency::Standard, monday_noon, 3);
+    assert_eq!(rural.agent_fee_bps, 550);
+}
+
+#[test]
+fn test_agent_fee_surcharges_add_up() {
+    let pricing = test_pricing();
+    let saturday_night = MONDAY_UTC + 5 * DAY + 19 * HOUR; // 22:00 in Kampala
+    
+    let fee = pricing::agent_fee_bps(&pricing, LocationTier::Suburban, Urgency::Express, saturday_night, 3);
+    assert_eq!(fee.night_surcharge_percent, 40);
+    assert_eq!(fee.weekend_surcharge_percent, 15);
+    assert_eq!(fee.urgency_surcharge_percent, 30);
+    assert_eq!(fee.agent_fee_bps, 350 * 185 / 100);
+}
+
+#[test]
+fn test_agent_fee_is_bounded() {
+    let mut pricing = test_pricing();
+    let saturday_night = MONDAY_UTC + 5 * DAY + 19 * HOUR;
+    
+    let remote = pricing::agent_fee_bps(&pricing, LocationTier::Remote, Urgency::Emergency, saturday_night, 3);
+    assert_eq!(remote.agent_fee_bps, pricing.max_agent_fee_bps);
+    
+    pricing.tiers.urban = 100;
+    let urban = pricing::agent_fee_bps(&pricing, LocationTier::Urban, Urgency::Standard, MONDAY_UTC + 9 * HOUR, 3);
+    assert_eq!(urban.agent_fee_bps, pricing.min_agent_fee_bps);
+}
+
+#[test]
+fn test_agent_fee_uses_local_time() {
+    let pricing = test_pricing();
+    let sunday_late = MONDAY_UTC - 2 * HOUR; // Sunday 22:00 UTC
+    
+    // Still Sunday night in Accra, already Monday small hours in Kampala
+    let accra = pricing::agent_fee_bps(&pricing, LocationTier::Urban, Urgency::Standard, sunday_late, 0);
+    assert_eq!((accra.night_surcharge_percent, accra.weekend_surcharge_percent), (40, 15));
+    
+    let kampala = pricing::agent_fee_bps(&pricing, LocationTier::Urban, Urgency::Standard, sunday_late, 3);
+    assert_eq!((kampala.night_surcharge_percent, kampala.weekend_surcharge_percent), (40, 0));
+}
+
+#[test]
+fn test_quote_prices_agent_by_location_tier() {
+    let config = test_revenue_config();
+    let user = Principal::from_slice(&[20]);
+    let agent = Principal::from_slice(&[21]);
+    let monday_noon = MONDAY_UTC + 9 * HOUR;
+    
+    let quote_at = |tier| {
+        set_location_tier(agent, tier, Principal::anonymous(), 0);
+        quote(&config, user, agent, Currency::UGX, 100_000, Urgency::Standard, monday_noon).unwrap()
+    };
+    
+    let urban = quote_at(LocationTier::Urban);
+    assert_eq!((urban.platform_fee, urban.agent_fee, urban.net_cash), (500, 2_500, 97_000));
+    
+    let remote = quote_at(LocationTier::Remote);
+    assert_eq!(remote.breakdown.location_tier, LocationTier::Remote);
+    assert_eq!((remote.agent_fee, remote.net_cash), (9_500, 90_000));
+}
+
+#[test]
+fn test_quote_rejects_out_of_bounds_amount() {
+    let config = test_revenue_config();
+    let user = Principal::from_slice(&[20]);
+    let agent = Principal::from_slice(&[21]);
+    
+    assert_eq!(
+        quote(&config, user, agent, Currency::UGX, 0, Urgency::Standard, MONDAY_UTC),
+        Err(WithdrawalError::InvalidAmount)
+    );
+}
+
+#[test]
+fn test_config_validation_rejects_bad_pricing() {
+    let mut config = test_revenue_config();
+    config.withdrawal.pricing.tiers.remote = config.withdrawal.pricing.tiers.urban - 1;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.withdrawal.pricing.max_agent_fee_bps = MAX_TOTAL_FEE_BPS;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.withdrawal.currencies.get_mut("NGN").unwrap().utc_offset_hours = 30;
+    assert!(validate_config(&config).is_err());
+}
+
+// ============================================================================
+// QUOTE TESTS
+// ============================================================================
+
+fn quote_request(user: Principal, quote: &WithdrawalQuote) -> CreateWithdrawalRequest {
+    CreateWithdrawalRequest {
+        user_principal: user,
+        agent_principal: quote.agent_principal,
+        currency: quote.currency,
+        amount: quote.amount,
+        urgency: Urgency::Standard,
+        quote_id: Some(quote.id),
+    }
+}
+
+fn issued_quote(user: Principal, now: u64) -> WithdrawalQuote {
+    let agent = Principal::from_slice(&[21]);
+    let priced = quote(&test_revenue_config(), user, agent, Currency::UGX, 100_000, Urgency::Express, now).unwrap();
+    issue_quote(priced, now)
+}
+
+/// Reserve a quote and use it up, as a successful request does.
+fn redeem_quote(quote_id: u64, request: &CreateWithdrawalRequest, now: u64) -> Result<WithdrawalQuote, WithdrawalError> {
+    reserve_quote(quote_id, request, now).map(QuoteReservation::consume)
+}
+
+#[test]
+fn test_redeemed_quote_keeps_quoted_fees() {
+    let user = Principal::from_slice(&[20]);
+    let issued = issued_quote(user, MONDAY_UTC + 9 * HOUR);
+    assert_eq!(issued.expires_at, MONDAY_UTC + 9 * HOUR + 300 * 1_000_000_000);
+    
+    // Redeemed just before expiry, at the quoted fees
+    let request = quote_request(user, &issued);
+    let redeemed = redeem_quote(issued.id, &request, issued.expires_at - 1).unwrap();
+    assert_eq!(redeemed, issued);
+    
+    // Single use
+    assert_eq!(redeem_quote(issued.id, &request, issued.expires_at - 1), Err(WithdrawalError::QuoteNotFound));
+}
+
+#[test]
+fn test_reserved_quote_is_held_and_restored_on_failure() {
+    let user = Principal::from_slice(&[20]);
+    let issued = issued_quote(user, MONDAY_UTC);
+    let request = quote_request(user, &issued);
+    
+    // While a request holds it, no other request can use it
+    let reservation = reserve_quote(issued.id, &request, MONDAY_UTC).unwrap();
+    assert_eq!(reservation.quote(), &issued);
+    assert!(matches!(reserve_quote(issued.id, &request, MONDAY_UTC), Err(WithdrawalError::QuoteNotFound)));
+    
+    // A request that fails before escrowing gives it back, still prunable
+    drop(reservation);
+    assert_eq!(redeem_quote(issued.id, &request, MONDAY_UTC), Ok(issued.clone()));
+    
+    let again = issued_quote(user, MONDAY_UTC);
+    drop(reserve_quote(again.id, &quote_request(user, &again), MONDAY_UTC).unwrap());
+    issued_quote(user, again.expires_at);
+    assert!(QUOTES.with(|q| !q.borrow().contains_key(&again.id)));
+}
+
+#[test]
+fn test_quote_expires() {
+    let user = Principal::from_slice(&[20]);
+    let issued = issued_quote(user, MONDAY_UTC);
+    
+    let request = quote_request(user, &issued);
+    assert_eq!(redeem_quote(issued.id, &request, issued.expires_at), Err(WithdrawalError::QuoteExpired));
+}
+
+#[test]
+fn test_quote_is_bound_to_user_and_terms() {
+    let user = Principal::from_slice(&[20]);
+    let issued = issued_quote(user, MONDAY_UTC);
+    
+    let other_user = quote_request(Principal::from_slice(&[22]), &issued);
+    assert_eq!(redeem_quote(issued.id, &other_user, MONDAY_UTC), Err(WithdrawalError::QuoteNotFound));
+    
+    let mut larger = quote_request(user, &issued);
+    larger.amount += 1;
+    assert_eq!(redeem_quote(issued.id, &larger, MONDAY_UTC), Err(WithdrawalError::QuoteMismatch));
+    
+    // A rejected redemption leaves the quote usable
+    assert!(redeem_quote(issued.id, &quote_request(user, &issued), MONDAY_UTC).is_ok());
+}
+
+#[test]
+fn test_issuing_quotes_drops_expired_ones() {
+    let user = Principal::from_slice(&[20]);
+    let old = issued_quote(user, MONDAY_UTC);
+    let new = issued_quote(user, old.expires_at);
+    
+    assert_ne!(old.id, new.id);
+    assert!(QUOTES.with(|q| !q.borrow().contains_key(&old.id)));
+}
+
+#[test]
+fn test_config_validation_rejects_bad_quote_validity() {
+    let mut config = test_revenue_config();
+    config.withdrawal.quote_validity_seconds = 0;
+    assert!(validate_config(&config).is_err());
+}
+
+// ============================================================================
+// AGENT PAYOUT TESTS
+// ============================================================================
+
+fn available_earnings(agent: Principal, currency: Currency) -> u64 {
+    AGENT_EARNINGS.with(|e| e.borrow().get(&agent))
+        .and_then(|e| e.currencies.into_iter().find(|c| c.currency == currency))
+        .map_or(0, |c| c.available())
+}
+
+#[test]
+fn test_agent_payout_reserves_earnings() {
+    let withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    let agent = withdrawal.agent_principal;
+    update_agent_earnings(&withdrawal, 0);
+    
+    assert_eq!(
+        start_agent_payout(agent, Currency::UGX, 3_001, Account::of(agent), 1),
+        Err(WithdrawalError::InsufficientEarnings { available: 3_000 })
+    );
+    
+    let payout = start_agent_payout(agent, Currency::UGX, 2_000, Account::of(agent), 1).unwrap();
+    assert_eq!(payout.status, AgentPayoutStatus::Pending);
+    assert_eq!(available_earnings(agent, Currency::UGX), 1_000);
+    
+    // Earnings in one currency cannot be paid out in another
+    assert_eq!(
+        start_agent_payout(agent, Currency::KES, 1, Account::of(agent), 1),
+        Err(WithdrawalError::InsufficientEarnings { available: 0 })
+    );
+}
+
+#[test]
+fn test_paid_agent_payout_is_recorded() {
+    let withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    let agent = withdrawal.agent_principal;
+    update_agent_earnings(&withdrawal, 0);
+    
+    let payout = start_agent_payout(agent, Currency::UGX, 3_000, Account::of(agent), 1).unwrap();
+    let paid = finish_agent_payout(payout.id, Ok(42), agent, 2).unwrap();
+    
+    assert_eq!(paid.status, AgentPayoutStatus::Paid { block_index: 42 });
+    assert_eq!(available_earnings(agent, Currency::UGX), 0);
+    assert_eq!(audit::events(None, 1)[0].action, AuditAction::AgentPayoutPaid);
+}
+
+#[test]
+fn test_failed_agent_payout_restores_earnings() {
+    let withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    let agent = withdrawal.agent_principal;
+    update_agent_earnings(&withdrawal, 0);
+    
+    let payout = start_agent_payout(agent, Currency::UGX, 3_000, Account::of(agent), 1).unwrap();
+    let error = WithdrawalError::LedgerUnavailable { reason: "down".to_string() };
+    assert_eq!(finish_agent_payout(payout.id, Err(error.clone()), agent, 2), Err(error));
+    
+    assert_eq!(available_earnings(agent, Currency::UGX), 3_000);
+    let recorded = AGENT_PAYOUTS.with(|p| p.borrow().get(&payout.id)).unwrap();
+    assert!(matches!(recorded.status, AgentPayoutStatus::Failed { .. }));
+}
+
+// ============================================================================
+// EXPIRY TESTS
+// ============================================================================
+
+fn pending_sample(id: u64, expires_at: u64) -> WithdrawalTransaction {
+    pending_sample_with(id, expires_at, |_| {})
+}
+
+fn pending_sample_with(id: u64, expires_at: u64, edit: impl FnOnce(&mut WithdrawalTransaction)) -> WithdrawalTransaction {
+    let mut withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    withdrawal.id = id;
+    withdrawal.withdrawal_code = generate_withdrawal_code(id);
+    withdrawal.status = TransactionStatus::Pending;
+    withdrawal.expires_at = expires_at;
+    withdrawal.escrow = Some(Escrow {
+        subaccount: escrow_subaccount(id),
+        lock_block: id,
+        payout_block: None,
+        fee_block: None,
+        refund_block: None,
+    });
+    edit(&mut withdrawal);
+    store_withdrawal(&withdrawal);
+    reserve_cash(withdrawal.agent_principal, withdrawal.currency, escrow_split(&withdrawal).0);
+    withdrawal
+}
+
+#[test]
+fn test_withdrawal_code_expires() {
+    let mut withdrawal = pending_sample(1, 100);
+    
+    assert_eq!(check_not_expired(&withdrawal, 99), Ok(()));
+    assert_eq!(check_not_expired(&withdrawal, 100), Err(WithdrawalError::CodeExpired));
+    
+    // Once the agent is paid the release may still finish
+    withdrawal.escrow.as_mut().unwrap().payout_block = Some(7);
+    assert_eq!(check_not_expired(&withdrawal, 100), Ok(()));
+}
+
+#[test]
+fn test_due_for_expiry_skips_settled_and_busy_withdrawals() {
+    pending_sample(1, 100);
+    pending_sample(2, 200);
+    let mut paid = pending_sample(3, 100);
+    paid.escrow.as_mut().unwrap().payout_block = Some(7);
+    WITHDRAWALS.with(|w| w.borrow_mut().insert(3, paid));
+    pending_sample(4, 100);
+    let _busy = EscrowGuard::acquire(4).unwrap();
+    let mut confirmed = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    confirmed.id = 5;
+    WITHDRAWALS.with(|w| w.borrow_mut().insert(5, confirmed));
+    
+    assert_eq!(due_for_expiry(150), vec![1]);
+}
+
+#[test]
+fn test_settled_withdrawals_leave_the_expiry_index() {
+    pending_sample(1, 100);
+    pending_sample(2, 100);
+    
+    mark_confirmed(1, 50).unwrap();
+    set_status(2, TransactionStatus::Cancelled).unwrap();
+    
+    assert!(WITHDRAWAL_EXPIRIES.with(|e| e.borrow().is_empty()));
+    assert!(due_for_expiry(150).is_empty());
+}
+
+#[test]
+fn test_withdrawal_codes_are_indexed_on_creation() {
+    pending_sample(7, 100);
+    set_status(7, TransactionStatus::Cancelled).unwrap();
+    
+    assert_eq!(WITHDRAWAL_CODES.with(|c| c.borrow().get(&generate_withdrawal_code(7))), Some(7));
+    assert_eq!(WITHDRAWAL_CODES.with(|c| c.borrow().len()), 1);
+}
+
+#[test]
+fn test_expired_withdrawal_notifies_both_parties() {
+    let withdrawal = pending_sample(1, 100);
+    let sweeper = Principal::from_slice(&[30]);
+    
+    mark_expired(1, sweeper, 150).unwrap();
+    
+    assert_eq!(WITHDRAWALS.with(|w| w.borrow().get(&1).unwrap().status), TransactionStatus::Expired);
+    assert_eq!(audit::events(None, 1)[0].action, AuditAction::WithdrawalExpired);
+    
+    let sent = notifications::since(None, 10);
+    let recipients: Vec<Principal> = sent.iter().map(|n| n.recipient).collect();
+    assert_eq!(recipients, vec![withdrawal.user_principal, withdrawal.agent_principal]);
+    assert!(sent.iter().all(|n| n.kind == NotificationKind::WithdrawalExpired && n.withdrawal_code == "WTH-00000001"));
+}
+
+#[test]
+fn test_notifications_poll_after_cursor() {
+    let withdrawal = pending_sample(1, 100);
+    for _ in 0..3 {
+        notify_parties(&withdrawal, NotificationKind::WithdrawalExpired, 0);
+    }
+    
+    let ids: Vec<u64> = notifications::since(Some(3), 2).iter().map(|n| n.id).collect();
+    assert_eq!(ids, vec![4, 5]);
+    assert!(notifications::since(Some(5), 10).is_empty());
+}
+
+#[test]
+fn test_acknowledged_notifications_are_deleted() {
+    let withdrawal = pending_sample(1, 100);
+    notifications::push(
+        withdrawal.user_principal,
+        1,
+        &withdrawal.withdrawal_code,
+        withdrawal.currency,
+        withdrawal.amount,
+        NotificationKind::ReleaseCode { code: "123456".to_string() },
+        0,
+    );
+    notify_parties(&withdrawal, NotificationKind::WithdrawalExpired, 0);
+    
+    // The release secret is gone once delivered; later ids keep counting up
+    assert_eq!(notifications::acknowledge(0), 1);
+    let left: Vec<u64> = notifications::since(None, 10).iter().map(|n| n.id).collect();
+    assert_eq!(left, vec![1, 2]);
+    assert!(notifications::since(None, 10).iter().all(|n| n.kind == NotificationKind::WithdrawalExpired));
+    
+    assert_eq!(notifications::acknowledge(5), 2);
+    assert!(notifications::since(None, 10).is_empty());
+    notify_parties(&withdrawal, NotificationKind::WithdrawalExpired, 0);
+    assert_eq!(notifications::since(None, 10)[0].id, 3);
+}
+
+#[test]
+fn test_outbox_is_bounded() {
+    let withdrawal = pending_sample(1, 100);
+    for _ in 0..notifications::MAX_OUTBOX_LEN / 2 + 1 {
+        notify_parties(&withdrawal, NotificationKind::WithdrawalExpired, 0);
+    }
+    
+    // The oldest undelivered notifications make room
+    let oldest = notifications::since(None, 1);
+    assert_eq!(oldest[0].id, 2);
+    assert_eq!(notifications::since(Some(notifications::MAX_OUTBOX_LEN), 10).len(), 1);
+}
+
+#[test]
+fn test_full_outbox_keeps_undelivered_release_codes() {
+    let withdrawal = pending_sample(1, 100);
+    let release = NotificationKind::ReleaseCode { code: "123456".to_string() };
+    let push = |kind| notifications::push(withdrawal.user_principal, 1, &withdrawal.withdrawal_code, Currency::UGX, 100_000, kind, 0);
+    
+    let code_id = push(release.clone());
+    for _ in 0..notifications::MAX_OUTBOX_LEN {
+        push(NotificationKind::WithdrawalExpired);
+    }
+    
+    // The release code is the oldest, but the expiry notice after it went instead
+    let oldest = notifications::since(None, 2);
+    assert_eq!(oldest[0].id, code_id);
+    assert_eq!(oldest[0].kind, release);
+    assert_eq!(oldest[1].id, code_id + 2);
+}
+
+#[test]
+fn test_config_validation_rejects_bad_code_validity() {
+    let mut config = test_revenue_config();
+    config.withdrawal.code_validity_hours = 0;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.withdrawal.notifier = "not-a-principal".to_string();
+    assert!(validate_config(&config).is_err());
+}
+
+// ============================================================================
+// RELEASE HANDSHAKE TESTS
+// ============================================================================
+
+const TEST_ENTROPY: [u8; release::ENTROPY_LEN] = [7; release::ENTROPY_LEN];
+
+#[test]
+fn test_release_secret_is_six_digits() {
+    let (secret, _) = release::new_secret(&TEST_ENTROPY);
+    assert_eq!(secret.len(), release::SECRET_DIGITS as usize);
+    assert!(secret.chars().all(|c| c.is_ascii_digit()));
+    
+    let mut other = TEST_ENTROPY;
+    other[release::ENTROPY_LEN - 1] = 8;
+    assert_ne!(release::new_secret(&other).0, secret);
+}
+
+#[test]
+fn test_release_secret_verifies() {
+    let (secret, mut lock) = release::new_secret(&TEST_ENTROPY);
+    
+    assert_eq!(release::verify(&mut lock, &format!(" {} ", secret), 3), Ok(()));
+    assert_eq!(lock.failed_attempts, 0);
+}
+
+#[test]
+fn test_release_locks_after_repeated_wrong_codes() {
+    let (secret, mut lock) = release::new_secret(&TEST_ENTROPY);
+    let wrong = if secret == "000000" { "000001" } else { "000000" };
+    
+    assert_eq!(release::verify(&mut lock, wrong, 3), Err(WithdrawalError::WrongReleaseCode { attempts_left: 2 }));
+    assert_eq!(release::verify(&mut lock, wrong, 3), Err(WithdrawalError::WrongReleaseCode { attempts_left: 1 }));
+    assert_eq!(release::verify(&mut lock, wrong, 3), Err(WithdrawalError::ReleaseLocked));
+    
+    // The right code no longer helps
+    assert_eq!(release::verify(&mut lock, &secret, 3), Err(WithdrawalError::ReleaseLocked));
+}
+
+#[test]
+fn test_release_lockout_is_audited_and_notified() {
+    load_config();
+    let withdrawal = pending_sample(1, u64::MAX);
+    let (_, lock) = release::new_secret(&TEST_ENTROPY);
+    RELEASE_LOCKS.with(|l| l.borrow_mut().insert(1, lock));
+    let max_attempts = current_config().withdrawal.max_release_attempts;
+    
+    for _ in 1..max_attempts {
+        assert!(matches!(
+            check_release_code(&withdrawal, "not-a-code", withdrawal.agent_principal, 0),
+            Err(WithdrawalError::WrongReleaseCode { .. })
+        ));
+    }
+    assert!(notifications::since(None, 10).is_empty());
+    
+    assert_eq!(
+        check_release_code(&withdrawal, "not-a-code", withdrawal.agent_principal, 0),
+        Err(WithdrawalError::ReleaseLocked)
+    );
+    assert_eq!(audit::events(None, 1)[0].action, AuditAction::ReleaseLocked);
+    assert_eq!(notifications::since(None, 10).len(), 2);
+}
+
+#[test]
+fn test_leaving_pending_drops_release_secret() {
+    pending_sample(1, u64::MAX);
+    RELEASE_LOCKS.with(|l| l.borrow_mut().insert(1, release::new_secret(&TEST_ENTROPY).1));
+    
+    set_status(1, TransactionStatus::Cancelled).unwrap();
+    assert!(RELEASE_LOCKS.with(|l| l.borrow().is_empty()));
+}
+
+// ============================================================================
+// AGENT LIQUIDITY TESTS
+// ============================================================================
+
+#[test]
+fn test_pending_withdrawals_reserve_cash_float() {
+    let agent = Principal::from_slice(&[2]);
+    set_cash_float(agent, Currency::UGX, 150_000, "Kampala".to_string(), 0);
+    pending_sample(1, u64::MAX); // 97,000 cash after fees
+    
+    let liquidity = agent_liquidity(agent, Currency::UGX).unwrap();
+    assert_eq!((liquidity.reserved, liquidity.available), (97_000, 53_000));
+    
+    assert_eq!(
+        LiquidityHold::acquire(agent, Currency::UGX, 60_000).err(),
+        Some(WithdrawalError::InsufficientAgentLiquidity { available: 53_000, alternatives: vec![] })
+    );
+    
+    let hold = LiquidityHold::acquire(agent, Currency::UGX, 50_000).unwrap();
+    assert_eq!(agent_liquidity(agent, Currency::UGX).unwrap().available, 3_000);
+    drop(hold);
+    assert_eq!(agent_liquidity(agent, Currency::UGX).unwrap().available, 53_000);
+    
+    // Leaving pending gives the cash back
+    set_status(1, TransactionStatus::Cancelled).unwrap();
+    assert_eq!(agent_liquidity(agent, Currency::UGX).unwrap().reserved, 0);
+}
+
+#[test]
+fn test_kept_hold_stays_reserved_until_the_withdrawal_settles() {
+    let agent = Principal::from_slice(&[2]);
+    set_cash_float(agent, Currency::UGX, 150_000, "Kampala".to_string(), 0);
+    
+    LiquidityHold::acquire(agent, Currency::UGX, 97_000).unwrap().keep();
+    assert_eq!(reserved_cash(agent, Currency::UGX), 97_000);
+    
+    // Settling it twice does not release more than it claimed
+    let mut withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    withdrawal.status = TransactionStatus::Pending;
+    WITHDRAWALS.with(|w| w.borrow_mut().insert(withdrawal.id, withdrawal.clone()));
+    mark_confirmed(withdrawal.id, 1).unwrap();
+    set_status(withdrawal.id, TransactionStatus::Confirmed).unwrap();
+    assert_eq!(reserved_cash(agent, Currency::UGX), 0);
+}
+
+#[test]
+fn test_agent_without_float_is_not_liquidity_checked() {
+    let agent = Principal::from_slice(&[2]);
+    LiquidityHold::acquire(agent, Currency::UGX, 1_000_000).unwrap().keep();
+    assert_eq!(agent_liquidity(agent, Currency::UGX), None);
+    
+    // Once declared, the float is net of what pending withdrawals claim
+    set_cash_float(agent, Currency::UGX, 1_500_000, "Kampala".to_string(), 0);
+    assert_eq!(agent_liquidity(agent, Currency::UGX).unwrap().available, 500_000);
+}
+
+#[test]
+fn test_short_agent_suggests_nearby_alternatives() {
+    let agent = Principal::from_slice(&[2]);
+    let nearby = Principal::from_slice(&[3]);
+    let far = Principal::from_slice(&[4]);
+    set_cash_float(agent, Currency::UGX, 10_000, "Kampala".to_string(), 0);
+    set_cash_float(nearby, Currency::UGX, 200_000, "Kampala".to_string(), 0);
+    set_cash_float(far, Currency::UGX, 200_000, "Gulu".to_string(), 0);
+    
+    assert_eq!(
+        LiquidityHold::acquire(agent, Currency::UGX, 50_000).err(),
+        Some(WithdrawalError::InsufficientAgentLiquidity { available: 10_000, alternatives: vec![nearby] })
+    );
+}
+
+#[test]
+fn test_find_agents_with_liquidity_filters_and_ranks() {
+    let small = Principal::from_slice(&[3]);
+    let large = Principal::from_slice(&[4]);
+    let elsewhere = Principal::from_slice(&[5]);
+    set_cash_float(small, Currency::UGX, 60_000, "Kampala".to_string(), 0);
+    set_cash_float(large, Currency::UGX, 500_000, "Kampala".to_string(), 0);
+    set_cash_float(elsewhere, Currency::UGX, 900_000, "Gulu".to_string(), 0);
+    set_cash_float(large, Currency::KES, 1_000, "Kampala".to_string(), 0);
+    
+    let agents = |amount, city| -> Vec<Principal> {
+        agents_with_liquidity(amount, Currency::UGX, city, 10).iter().map(|l| l.agent).collect()
+    };
+    assert_eq!(agents(50_000, Some(" kampala ")), vec![large, small]);
+    assert_eq!(agents(100_000, Some("Kampala")), vec![large]);
+    assert_eq!(agents(100_000, None), vec![elsewhere, large]);
+}
+
+#[test]
+fn test_confirmed_withdrawal_spends_cash_float() {
+    let withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    set_cash_float(withdrawal.agent_principal, Currency::UGX, 150_000, "Kampala".to_string(), 0);
+    
+    spend_cash_float(&withdrawal);
+    
+    let liquidity = agent_liquidity(withdrawal.agent_principal, Currency::UGX).unwrap();
+    assert_eq!(liquidity.float.amount, 53_000);
+}
+
+// ============================================================================
+// REVENUE SWEEP TESTS
+// ============================================================================
+
+fn accrue(id: u64, currency: Currency, platform_fee: u64) {
+    let mut withdrawal = sample_withdrawal(currency, 100_000, 3_000);
+    withdrawal.id = id;
+    withdrawal.platform_fee = platform_fee;
+    record_fee_accrual(&withdrawal);
+}
+
+#[test]
+fn test_sweep_split_between_company_and_dao() {
+    assert_eq!(split_sweep(10_000, 0), (10_000, 0));
+    assert_eq!(split_sweep(10_000, 2_500), (7_500, 2_500));
+    assert_eq!(split_sweep(999, 5_000), (500, 499));
+}
+
+#[test]
+fn test_sweep_claims_each_fee_once() {
+    let company = Principal::from_slice(&[40]);
+    accrue(1, Currency::UGX, 500);
+    accrue(2, Currency::KES, 50);
+    accrue(3, Currency::UGX, 250);
+    
+    let first = claim_new_sweeps(company, None, 1);
+    assert_eq!(first.len(), 2);
+    let sweeps: Vec<RevenueSweep> = REVENUE_SWEEPS.with(|s| s.borrow().iter().map(|(_, s)| s).collect());
+    let ugx = sweeps.iter().find(|s| s.currency == Currency::UGX).unwrap();
+    assert_eq!((ugx.amount, ugx.from_accrual, ugx.to_accrual), (750, 0, 3));
+    assert_eq!(sweep_watermark(Currency::UGX), 3);
+    assert!(get_unswept_platform_revenue().is_empty());
+    
+    // Nothing new accrued: nothing to claim
+    assert!(claim_new_sweeps(company, None, 2).is_empty());
+    
+    accrue(4, Currency::UGX, 100);
+    assert_eq!(get_unswept_platform_revenue(), vec![CurrencyAmount { currency: Currency::UGX, amount: 100 }]);
+    let second = claim_new_sweeps(company, None, 3);
+    let sweep = REVENUE_SWEEPS.with(|s| s.borrow().get(&second[0]).unwrap());
+    assert_eq!((sweep.amount, sweep.from_accrual, sweep.to_accrual), (100, 3, 4));
+}
+
+#[test]
+fn test_sweep_records_dao_share() {
+    let company = Principal::from_slice(&[40]);
+    let treasury = Principal::from_slice(&[41]);
+    accrue(1, Currency::UGX, 1_000);
+    
+    let ids = claim_new_sweeps(company, Some((treasury, 2_000)), 1);
+    let sweep = REVENUE_SWEEPS.with(|s| s.borrow().get(&ids[0]).unwrap());
+    assert_eq!((sweep.company_amount, sweep.dao_amount), (800, 200));
+    assert_eq!(sweep.dao_treasury, Some(treasury));
+}
+
+#[test]
+fn test_unfinished_sweeps_are_retried() {
+    let company = Principal::from_slice(&[40]);
+    accrue(1, Currency::UGX, 500);
+    accrue(2, Currency::KES, 50);
+    let ids = claim_new_sweeps(company, None, 1);
+    
+    update_sweep(ids[0], |s| {
+        s.company_block = Some(9);
+        s.completed_at = Some(2);
+    });
+    assert_eq!(unfinished_sweeps(), vec![ids[1]]);
+}
+
+#[test]
+fn test_only_one_sweep_runs_at_a_time() {
+    let guard = SweepGuard::acquire().unwrap();
+    assert!(matches!(SweepGuard::acquire(), Err(WithdrawalError::SweepInProgress)));
+    drop(guard);
+    assert!(SweepGuard::acquire().is_ok());
+}
+
+#[test]
+fn test_config_validation_rejects_bad_revenue_sweep() {
+    let mut config = test_revenue_config();
+    config.withdrawal.revenue_sweep.dao_share_bps = 1_000;
+    assert!(validate_config(&config).is_err());
+    
+    config.withdrawal.revenue_sweep.dao_treasury = Principal::from_slice(&[41]).to_text();
+    assert_eq!(validate_config(&config), Ok(()));
+    
+    config.withdrawal.revenue_sweep.interval_hours = 0;
+    assert!(validate_config(&config).is_err());
+}
+
+// ============================================================================
+// AGENT REPUTATION TESTS
+// ============================================================================
+
+fn store_outcome(id: u64, status: TransactionStatus, latency: Option<u64>) -> WithdrawalTransaction {
+    let mut withdrawal = sample_withdrawal(Currency::UGX, 100_000, 3_000);
+    withdrawal.id = id;
+    withdrawal.status = status;
+    withdrawal.timestamp = MONDAY_UTC;
+    withdrawal.confirmed_at = latency.map(|l| MONDAY_UTC + l);
+    store_withdrawal(&withdrawal);
+    withdrawal
+}
+
+#[test]
+fn test_reputation_rates_exclude_pending_requests() {
+    store_outcome(1, TransactionStatus::Confirmed, Some(HOUR));
+    store_outcome(2, TransactionStatus::Confirmed, Some(3 * HOUR));
+    store_outcome(3, TransactionStatus::Cancelled, None);
+    store_outcome(4, TransactionStatus::Expired, None);
+    store_outcome(5, TransactionStatus::Pending, None);
+    
+    let reputation = agent_reputation(Principal::from_slice(&[2]));
+    
+    assert_eq!(reputation.total_requests, 5);
+    assert_eq!(reputation.pending, 1);
+    assert_eq!(reputation.cancellation_rate_bps, 2_500);
+    assert_eq!(reputation.expired_ratio_bps, 2_500);
+    assert_eq!(reputation.avg_confirmation_latency_secs, Some(2 * 3_600));
+    assert_eq!(reputation.average_rating_x100, None);
+    assert_eq!(agent_reputation(Principal::from_slice(&[7])).total_requests, 0);
+}
+
+#[test]
+fn test_reputation_follows_status_changes() {
+    let withdrawal = store_outcome(1, TransactionStatus::Pending, None);
+    assert_eq!(agent_reputation(withdrawal.agent_principal).pending, 1);
+    
+    mark_confirmed(1, MONDAY_UTC + HOUR).unwrap();
+    
+    let reputation = agent_reputation(withdrawal.agent_principal);
+    assert_eq!((reputation.total_requests, reputation.pending, reputation.confirmed), (1, 0, 1));
+    assert_eq!(reputation.avg_confirmation_latency_secs, Some(3_600));
+}
+
+#[test]
+fn test_only_the_user_rates_a_confirmed_withdrawal_once() {
+    let confirmed = store_outcome(1, TransactionStatus::Confirmed, Some(HOUR));
+    store_outcome(2, TransactionStatus::Confirmed, Some(HOUR));
+    store_outcome(3, TransactionStatus::Expired, None);
+    let user = confirmed.user_principal;
+    
+    assert!(matches!(submit_rating(confirmed.agent_principal, 1, 5, None, 0), Err(WithdrawalError::Unauthorized)));
+    assert!(matches!(submit_rating(user, 1, 0, None, 0), Err(WithdrawalError::InvalidInput { .. })));
+    assert!(matches!(submit_rating(user, 3, 4, None, 0), Err(WithdrawalError::InvalidInput { .. })));
+    assert!(matches!(submit_rating(user, 9, 4, None, 0), Err(WithdrawalError::NotFound)));
+    
+    let rating = submit_rating(user, 1, 5, Some("Had the cash ready".to_string()), 0).unwrap();
+    assert_eq!(rating.agent, confirmed.agent_principal);
+    assert!(matches!(submit_rating(user, 1, 1, None, 0), Err(WithdrawalError::InvalidInput { .. })));
+    submit_rating(user, 2, 4, None, 0).unwrap();
+    
+    let reputation = agent_reputation(confirmed.agent_principal);
+    assert_eq!(reputation.rating_count, 2);
+    assert_eq!(reputation.average_rating_x100, Some(450));
+}
+
+// ============================================================================
+// FRAUD SCREENING TESTS
+// ============================================================================
+
+const UGX_NEAR_LIMIT: u64 = 4_600_000; // max_withdrawal is 5,000,000
+
+fn store_request(id: u64, amount: u64, timestamp: u64) -> WithdrawalTransaction {
+    pending_sample_with(id, timestamp + DAY, |withdrawal| {
+        withdrawal.amount = amount;
+        withdrawal.timestamp = timestamp;
+    })
+}
+
+fn rules_of(hits: &[RuleHit]) -> Vec<FraudRule> {
+    hits.iter().map(|hit| hit.rule).collect()
+}
+
+#[test]
+fn test_user_withdrawal_key_sorts_by_time() {
+    use ic_stable_structures::Storable;
+    
+    let user = Principal::from_slice(&[7; 29]);
+    let early = UserWithdrawalKey { user, timestamp: 255, id: 9 };
+    let late = UserWithdrawalKey { user, timestamp: 256, id: 1 };
+    
+    assert!(early.to_bytes() < late.to_bytes());
+    assert_eq!(UserWithdrawalKey::from_bytes(late.to_bytes()), late);
+}
+
+#[test]
+fn test_screening_reads_only_the_users_own_withdrawals() {
+    let config = test_config();
+    store_request(1, UGX_NEAR_LIMIT, MONDAY_UTC);
+    pending_sample_with(2, MONDAY_UTC + DAY, |withdrawal| {
+        withdrawal.user_principal = Principal::from_slice(&[42]);
+        withdrawal.amount = UGX_NEAR_LIMIT;
+        withdrawal.timestamp = MONDAY_UTC + HOUR;
+    });
+    let subject = alert_subject(&store_request(3, UGX_NEAR_LIMIT, MONDAY_UTC + 2 * HOUR));
+    
+    let hits = screen_withdrawal(&config, &subject, 0, MONDAY_UTC + 2 * HOUR);
+    assert!(!rules_of(&hits).contains(&FraudRule::Structuring));
+}
+
+#[test]
+fn test_repeated_near_limit_withdrawals_are_held() {
+    let config = test_config();
+    store_request(1, UGX_NEAR_LIMIT, MONDAY_UTC);
+    store_request(2, UGX_NEAR_LIMIT, MONDAY_UTC + HOUR);
+    let mut subject = alert_subject(&store_request(3, UGX_NEAR_LIMIT, MONDAY_UTC + 2 * HOUR));
+    
+    let hits = screen_withdrawal(&config, &subject, 0, MONDAY_UTC + 2 * HOUR);
+    assert!(rules_of(&hits).contains(&FraudRule::Structuring));
+    assert_eq!(fraud::strongest(&hits), Some(FraudAction::Hold));
+    
+    subject.amount = 1_000_000;
+    let hits = screen_withdrawal(&config, &subject, 0, MONDAY_UTC + 2 * HOUR);
+    assert!(!rules_of(&hits).contains(&FraudRule::Structuring));
+}
+
+#[test]
+fn test_new_account_volume_counts_only_live_withdrawals() {
+    let config = test_config();
+    store_request(1, 4_000_000, MONDAY_UTC);
+    let mut cancelled = store_request(2, 4_000_000, MONDAY_UTC + HOUR);
+    let subject = alert_subject(&store_request(3, 4_000_000, MONDAY_UTC + 2 * HOUR));
+    
+    let hits = screen_withdrawal(&config, &subject, 0, MONDAY_UTC + 2 * HOUR);
+    assert_eq!(rules_of(&hits), vec![FraudRule::NewAccountVolume]);
+    assert_eq!(fraud::strongest(&hits), Some(FraudAction::Flag));
+    
+    cancelled.status = TransactionStatus::Cancelled;
+    WITHDRAWALS.with(|w| w.borrow_mut().insert(2, cancelled));
+    assert!(screen_withdrawal(&config, &subject, 0, MONDAY_UTC + 2 * HOUR).is_empty());
+    
+    // The account is no longer new
+    store_request(2, 4_000_000, MONDAY_UTC + HOUR);
+    assert!(screen_withdrawal(&config, &subject, 0, MONDAY_UTC + 73 * HOUR).is_empty());
+}
+
+#[test]
+fn test_withdrawing_through_the_deposit_agent_is_a_round_trip() {
+    let mut config = test_config();
+    let subject = alert_subject(&store_request(1, 100_000, MONDAY_UTC));
+    
+    assert!(screen_withdrawal(&config, &subject, 0, MONDAY_UTC).is_empty());
+    let hits = screen_withdrawal(&config, &subject, 1, MONDAY_UTC);
+    assert_eq!(rules_of(&hits), vec![FraudRule::RoundTrip]);
+    assert_eq!(fraud::strongest(&hits), Some(FraudAction::Hold));
+    
+    config.fraud.round_trip_hours = 0;
+    assert!(screen_withdrawal(&config, &subject, 1, MONDAY_UTC).is_empty());
+}
+
+#[test]
+fn test_round_trip_counts_recent_deposits_with_the_same_agent() {
+    let config = test_config();
+    let withdrawal = store_request(1, 100_000, MONDAY_UTC + DAY);
+    let subject = alert_subject(&withdrawal);
+    let deposit = |id, agent, confirmed_at| deposit_history::Deposit {
+        id,
+        agent_principal: agent,
+        currency: Currency::UGX,
+        amount: 100_000,
+        confirmed_at,
+    };
+    let since = MONDAY_UTC;
+    let deposits = [
+        deposit(1, withdrawal.agent_principal, Some(MONDAY_UTC + HOUR)),
+        deposit(2, withdrawal.agent_principal, Some(MONDAY_UTC - HOUR)),
+        deposit(3, Principal::from_slice(&[7]), Some(MONDAY_UTC + HOUR)),
+    ];
+    
+    let count = round_trips(&deposits, withdrawal.agent_principal, since);
+    assert_eq!(count, 1);
+    let hits = screen_withdrawal(&config, &subject, count, MONDAY_UTC + DAY);
+    assert_eq!(rules_of(&hits), vec![FraudRule::RoundTrip]);
+    assert_eq!(round_trips(&deposits[1..], withdrawal.agent_principal, since), 0);
+}
+
+#[test]
+fn test_held_withdrawal_does_not_expire_until_cleared() {
+    load_config();
+    let withdrawal = pending_sample(1, 100);
+    let company = Principal::from_slice(&[9]);
+    let alert = raise_alert(withdrawal.user_principal, alert_subject(&withdrawal), ScreeningStage::Create, FraudAction::Hold, vec![], 0);
+    
+    assert_eq!(check_not_held(1), Err(WithdrawalError::HeldForReview { alert_id: alert.id }));
+    assert!(due_for_expiry(150).is_empty());
+    
+    let cleared = close_alert(alert.id, ReviewDecision::Clear, "Known customer".to_string(), company, 150).unwrap();
+    assert_eq!(cleared.status, AlertStatus::Cleared);
+    assert_eq!(check_not_held(1), Ok(true));
+    assert_eq!(WITHDRAWALS.with(|w| w.borrow().get(&1).unwrap().expires_at), 150 + 24 * HOUR);
+    assert!(due_for_expiry(150).is_empty());
+    assert_eq!(due_for_expiry(150 + 24 * HOUR), vec![1]);
+    
+    assert!(close_alert(alert.id, ReviewDecision::Reject, String::new(), company, 200).is_err());
+    assert_eq!(audit::events(None, 1)[0].action, AuditAction::FraudAlertReviewed);
+}
+
+#[test]
+fn test_flagged_withdrawal_is_not_held() {
+    let withdrawal = pending_sample(1, 100);
+    raise_alert(withdrawal.user_principal, alert_subject(&withdrawal), ScreeningStage::Create, FraudAction::Flag, vec![], 0);
+    
+    assert_eq!(check_not_held(1), Ok(false));
+    assert_eq!(check_not_held(2), Ok(false));
+    assert_eq!(due_for_expiry(150), vec![1]);
+}
+
+#[test]
+fn test_config_validation_rejects_bad_fraud_rules() {
+    let mut config = test_revenue_config();
+    config.withdrawal.fraud.round_trip_hours = canister_shared::fraud::MAX_RULE_WINDOW_HOURS + 1;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.withdrawal.fraud.near_limit_percent = 101;
+    assert!(validate_config(&config).is_err());
+    
+    let mut config = test_revenue_config();
+    config.withdrawal.deposit_canister = "not a principal".to_string();
+    assert!(validate_config(&config).is_err());
+}